@@ -0,0 +1,906 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::chip8::{self, Address, Instruction, RegisterNumber};
+
+/// Where a statement came from, for error messages and source maps.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLocation {
+    pub file: String,
+    /// 1-based.
+    pub line: usize,
+    /// 1-based, counted in characters.
+    pub column: usize,
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(f, "{}:{}:{}", self.file, self.line, self.column);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssemblyError {
+    pub location: SourceLocation,
+    pub message: String,
+}
+
+impl fmt::Display for AssemblyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(f, "{}: {}", self.location, self.message);
+    }
+}
+
+impl std::error::Error for AssemblyError {}
+
+/// The statement that produced the bytes starting at each address.
+pub type SourceMap = BTreeMap<Address, SourceLocation>;
+
+/// An assembled program, ready for `Chip8::load_rom`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assembly {
+    pub rom: Vec<u8>,
+    pub source_map: SourceMap,
+}
+
+/// Assembles source written with the classic mnemonics, as printed by the
+/// disassembler:
+///
+/// ```text
+/// ; comments run to the end of the line
+/// SPEED = 2               ; constants, also `SPEED equ 2`
+/// start:  LD V0, SPEED
+///         LD I, sprite
+///         DRW V0, V1, 5
+///         JP start
+/// sprite: db 0xF0, $90, %11110000, "text"
+///         dw 0x1234
+///         include "other.asm"
+/// ```
+///
+/// Numbers are decimal, hex (`0x` or `$`) or binary (`0b` or `%`), and can
+/// be combined with labels and constants using `+` and `-`. Includes are
+/// resolved relative to the current directory.
+pub fn assemble(source: &str) -> Result<Assembly, AssemblyError> {
+    let mut assembler = Assembler::default();
+    assembler.read_source("<source>", source, Path::new("."))?;
+    return assembler.finish();
+}
+
+/// Assembles a file, resolving includes relative to the including file.
+pub fn assemble_file<P: AsRef<Path>>(path: P) -> Result<Assembly, AssemblyError> {
+    let mut assembler = Assembler::default();
+    assembler.read_file(path.as_ref(), None)?;
+    return assembler.finish();
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    Identifier(String),
+    Number(i64),
+    Text(Vec<u8>),
+    Symbol(char),
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    kind: TokenKind,
+    location: SourceLocation,
+}
+
+#[derive(Debug, Clone)]
+enum Term {
+    Number(i64),
+    Name(String),
+}
+
+/// Terms added together, each with its sign.
+#[derive(Debug, Clone)]
+struct Expression {
+    terms: Vec<(bool, Term, SourceLocation)>,
+}
+
+#[derive(Debug, Clone)]
+enum Operand {
+    Register(RegisterNumber),
+    I,
+    IndirectI,
+    DelayTimer,
+    SoundTimer,
+    Key,
+    Font,
+    BigFont,
+    Bcd,
+    Flags,
+    Long(Expression),
+    Value(Expression),
+}
+
+#[derive(Debug, Clone)]
+enum DataItem {
+    Value(Expression),
+    Text(Vec<u8>),
+}
+
+#[derive(Debug, Clone)]
+enum StatementKind {
+    Instruction(String, Vec<(Operand, SourceLocation)>),
+    Data(usize, Vec<(DataItem, SourceLocation)>),
+}
+
+#[derive(Debug, Clone)]
+struct Statement {
+    kind: StatementKind,
+    address: Address,
+    location: SourceLocation,
+}
+
+#[derive(Debug, Clone)]
+enum Definition {
+    Label(Address),
+    Constant(Expression),
+}
+
+#[derive(Default)]
+struct Assembler {
+    statements: Vec<Statement>,
+    symbols: HashMap<String, (Definition, SourceLocation)>,
+    address: usize,
+    /// Files currently being read, to catch an include of itself.
+    including: Vec<PathBuf>,
+}
+
+fn error<T>(location: &SourceLocation, message: String) -> Result<T, AssemblyError> {
+    return Err(AssemblyError {
+        location: location.clone(),
+        message,
+    });
+}
+
+impl Assembler {
+    fn read_file(
+        &mut self,
+        path: &Path,
+        included_from: Option<&SourceLocation>,
+    ) -> Result<(), AssemblyError> {
+        let name = path.display().to_string();
+        let start = SourceLocation {
+            file: name.clone(),
+            line: 1,
+            column: 1,
+        };
+        let location = included_from.unwrap_or(&start);
+        let canonical = fs::canonicalize(path)
+            .or_else(|io_error| error(location, format!("cannot read {name}: {io_error}")))?;
+        if self.including.contains(&canonical) {
+            return error(location, format!("{name} includes itself"));
+        }
+        let source = fs::read_to_string(path)
+            .or_else(|io_error| error(location, format!("cannot read {name}: {io_error}")))?;
+
+        self.including.push(canonical);
+        let directory = path.parent().unwrap_or(Path::new("."));
+        let result = self.read_source(&name, &source, directory);
+        self.including.pop();
+        return result;
+    }
+
+    fn read_source(
+        &mut self,
+        file: &str,
+        source: &str,
+        directory: &Path,
+    ) -> Result<(), AssemblyError> {
+        for (index, line) in source.lines().enumerate() {
+            let tokens = tokenize(file, index + 1, line)?;
+            self.read_line(tokens, directory)?;
+        }
+        return Ok(());
+    }
+
+    fn define(
+        &mut self,
+        name: &str,
+        definition: Definition,
+        location: &SourceLocation,
+    ) -> Result<(), AssemblyError> {
+        if let Some((_, previous)) = self.symbols.get(name) {
+            return error(
+                location,
+                format!("'{name}' is already defined at {previous}"),
+            );
+        }
+        self.symbols
+            .insert(name.to_string(), (definition, location.clone()));
+        return Ok(());
+    }
+
+    fn read_line(&mut self, tokens: Vec<Token>, directory: &Path) -> Result<(), AssemblyError> {
+        let mut tokens = &tokens[..];
+
+        // `name:` labels the next statement, which may be on the same line.
+        if let [Token {
+            kind: TokenKind::Identifier(name),
+            location,
+        }, Token {
+            kind: TokenKind::Symbol(':'),
+            ..
+        }, rest @ ..] = tokens
+        {
+            let address = (chip8::PROGRAM_START + self.address) as Address;
+            self.define(name, Definition::Label(address), location)?;
+            tokens = rest;
+        }
+
+        let (first, rest) = match tokens.split_first() {
+            Some(split) => split,
+            None => return Ok(()),
+        };
+        let word = match &first.kind {
+            TokenKind::Identifier(word) => word,
+            _ => {
+                return error(
+                    &first.location,
+                    String::from("expected a mnemonic or directive"),
+                )
+            }
+        };
+
+        // `NAME = value` and `NAME equ value` define constants.
+        match rest.split_first() {
+            Some((
+                Token {
+                    kind: TokenKind::Symbol('='),
+                    ..
+                },
+                value,
+            )) => {
+                let expression = parse_whole_expression(value, &first.location)?;
+                return self.define(word, Definition::Constant(expression), &first.location);
+            }
+            Some((
+                Token {
+                    kind: TokenKind::Identifier(equ),
+                    ..
+                },
+                value,
+            )) if equ.eq_ignore_ascii_case("equ") => {
+                let expression = parse_whole_expression(value, &first.location)?;
+                return self.define(word, Definition::Constant(expression), &first.location);
+            }
+            _ => {}
+        }
+
+        let mnemonic = word.to_ascii_uppercase();
+        let (kind, length) = match mnemonic.as_str() {
+            "INCLUDE" => {
+                return match rest {
+                    [Token {
+                        kind: TokenKind::Text(path),
+                        location,
+                    }] => {
+                        let path = directory.join(String::from_utf8_lossy(path).as_ref());
+                        self.read_file(&path, Some(location))
+                    }
+                    _ => error(&first.location, String::from("expected a quoted file name")),
+                };
+            }
+            "DB" | "DW" => {
+                let width = if mnemonic == "DB" { 1 } else { 2 };
+                let items = parse_data(rest, &first.location)?;
+                let length = items
+                    .iter()
+                    .map(|(item, _)| match item {
+                        DataItem::Value(_) => width,
+                        DataItem::Text(bytes) => bytes.len(),
+                    })
+                    .sum();
+                (StatementKind::Data(width, items), length)
+            }
+            _ => {
+                let operands = parse_operands(rest, &first.location)?;
+                let length = match operands.as_slice() {
+                    [(Operand::I, _), (Operand::Long(_), _)] => 4,
+                    _ => 2,
+                };
+                (StatementKind::Instruction(mnemonic, operands), length)
+            }
+        };
+
+        if self.address + length > chip8::EXTENDED_MEMORY_SIZE - chip8::PROGRAM_START {
+            return error(
+                &first.location,
+                String::from("program does not fit in memory"),
+            );
+        }
+        self.statements.push(Statement {
+            kind,
+            address: (chip8::PROGRAM_START + self.address) as Address,
+            location: first.location.clone(),
+        });
+        self.address += length;
+        return Ok(());
+    }
+
+    fn finish(self) -> Result<Assembly, AssemblyError> {
+        let mut rom = Vec::with_capacity(self.address);
+        let mut source_map = BTreeMap::new();
+        for statement in self.statements.iter() {
+            source_map.insert(statement.address, statement.location.clone());
+            match &statement.kind {
+                StatementKind::Instruction(mnemonic, operands) => {
+                    let mut resolved = Vec::with_capacity(operands.len());
+                    for (operand, location) in operands.iter() {
+                        resolved.push((self.resolve_operand(operand)?, location.clone()));
+                    }
+                    rom.extend(encode(mnemonic, &resolved, &statement.location)?);
+                }
+                StatementKind::Data(width, items) => {
+                    for (item, location) in items.iter() {
+                        match item {
+                            DataItem::Text(bytes) => rom.extend_from_slice(bytes),
+                            DataItem::Value(expression) => {
+                                let value = self.evaluate(expression, &mut Vec::new())?;
+                                if *width == 1 {
+                                    rom.push(check_range(value, -0x80, 0xFF, location)? as u8);
+                                } else {
+                                    let word = check_range(value, -0x8000, 0xFFFF, location)?;
+                                    rom.extend_from_slice(&(word as u16).to_be_bytes());
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        return Ok(Assembly { rom, source_map });
+    }
+
+    fn resolve_operand(&self, operand: &Operand) -> Result<Resolved, AssemblyError> {
+        return Ok(match operand {
+            Operand::Register(x) => Resolved::Register(*x),
+            Operand::I => Resolved::I,
+            Operand::IndirectI => Resolved::IndirectI,
+            Operand::DelayTimer => Resolved::DelayTimer,
+            Operand::SoundTimer => Resolved::SoundTimer,
+            Operand::Key => Resolved::Key,
+            Operand::Font => Resolved::Font,
+            Operand::BigFont => Resolved::BigFont,
+            Operand::Bcd => Resolved::Bcd,
+            Operand::Flags => Resolved::Flags,
+            Operand::Long(expression) => {
+                Resolved::Long(self.evaluate(expression, &mut Vec::new())?)
+            }
+            Operand::Value(expression) => {
+                Resolved::Value(self.evaluate(expression, &mut Vec::new())?)
+            }
+        });
+    }
+
+    /// `evaluating` holds the constants being expanded, to report cycles.
+    fn evaluate(
+        &self,
+        expression: &Expression,
+        evaluating: &mut Vec<String>,
+    ) -> Result<i64, AssemblyError> {
+        let mut total = 0i64;
+        for (negative, term, location) in expression.terms.iter() {
+            let value = match term {
+                Term::Number(value) => *value,
+                Term::Name(name) => match self.symbols.get(name) {
+                    Some((Definition::Label(address), _)) => *address as i64,
+                    Some((Definition::Constant(expression), _)) => {
+                        if evaluating.contains(name) {
+                            return error(
+                                location,
+                                format!("'{name}' is defined in terms of itself"),
+                            );
+                        }
+                        evaluating.push(name.clone());
+                        let value = self.evaluate(expression, evaluating)?;
+                        evaluating.pop();
+                        value
+                    }
+                    None => return error(location, format!("undefined symbol '{name}'")),
+                },
+            };
+            let sum = if *negative {
+                total.checked_sub(value)
+            } else {
+                total.checked_add(value)
+            };
+            total = match sum {
+                Some(sum) => sum,
+                None => return error(location, String::from("expression overflows")),
+            };
+        }
+        return Ok(total);
+    }
+}
+
+/// An operand with its expression evaluated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Resolved {
+    Register(RegisterNumber),
+    I,
+    IndirectI,
+    DelayTimer,
+    SoundTimer,
+    Key,
+    Font,
+    BigFont,
+    Bcd,
+    Flags,
+    Long(i64),
+    Value(i64),
+}
+
+fn check_range(
+    value: i64,
+    minimum: i64,
+    maximum: i64,
+    location: &SourceLocation,
+) -> Result<i64, AssemblyError> {
+    if value < minimum || value > maximum {
+        return error(
+            location,
+            format!("value {value} is out of range, expected {minimum} to {maximum}"),
+        );
+    }
+    return Ok(value);
+}
+
+fn encode(
+    mnemonic: &str,
+    operands: &[(Resolved, SourceLocation)],
+    location: &SourceLocation,
+) -> Result<Vec<u8>, AssemblyError> {
+    use Resolved::*;
+
+    let kinds: Vec<Resolved> = operands.iter().map(|(operand, _)| *operand).collect();
+    let at = |index: usize| return &operands[index].1;
+    let address = |value: i64, index: usize| {
+        return check_range(value, 0, 0xFFF, at(index)).map(|value| value as Address);
+    };
+    let byte = |value: i64, index: usize| {
+        return check_range(value, -0x80, 0xFF, at(index)).map(|value| value as u8);
+    };
+    let nibble = |value: i64, index: usize| {
+        return check_range(value, 0, 0xF, at(index)).map(|value| value as u8);
+    };
+
+    let instruction = match (mnemonic, kinds.as_slice()) {
+        ("CLS", []) => Instruction::ClearDisplay,
+        ("RET", []) => Instruction::Return,
+        ("SCD", [Value(n)]) => Instruction::ScrollDown(nibble(*n, 0)?),
+        ("SCU", [Value(n)]) => Instruction::ScrollUp(nibble(*n, 0)?),
+        ("SCR", []) => Instruction::ScrollRight,
+        ("SCL", []) => Instruction::ScrollLeft,
+        ("EXIT", []) => Instruction::Exit,
+        ("LOW", []) => Instruction::LowRes,
+        ("HIGH", []) => Instruction::HighRes,
+        ("JP", [Value(nnn)]) => Instruction::Jump(address(*nnn, 0)?),
+        ("JP", [Register(0), Value(nnn)]) => Instruction::LongJump(address(*nnn, 1)?),
+        ("CALL", [Value(nnn)]) => Instruction::Call(address(*nnn, 0)?),
+        ("SE", [Register(x), Value(nn)]) => Instruction::SkipEqualK(*x, byte(*nn, 1)?),
+        ("SE", [Register(x), Register(y)]) => Instruction::SkipEqual(*x, *y),
+        ("SNE", [Register(x), Value(nn)]) => Instruction::SkipNotEqualK(*x, byte(*nn, 1)?),
+        ("SNE", [Register(x), Register(y)]) => Instruction::SkipNotEqual(*x, *y),
+        ("SAVE", [Register(x), Register(y)]) => Instruction::SaveRange(*x, *y),
+        ("LOAD", [Register(x), Register(y)]) => Instruction::LoadRange(*x, *y),
+        ("LD", [Register(x), Value(nn)]) => Instruction::SetK(*x, byte(*nn, 1)?),
+        ("LD", [Register(x), Register(y)]) => Instruction::Set(*x, *y),
+        ("LD", [I, Value(nnn)]) => Instruction::LoadI(address(*nnn, 1)?),
+        ("LD", [I, Long(nnnn)]) => {
+            let nnnn = check_range(*nnnn, 0, 0xFFFF, at(1))? as u16;
+            let mut bytes = Instruction::LoadLongI.encode().to_be_bytes().to_vec();
+            bytes.extend_from_slice(&nnnn.to_be_bytes());
+            return Ok(bytes);
+        }
+        ("LD", [Register(x), DelayTimer]) => Instruction::GetTimer(*x),
+        ("LD", [Register(x), Key]) => Instruction::WaitKey(*x),
+        ("LD", [DelayTimer, Register(x)]) => Instruction::SetTimer(*x),
+        ("LD", [SoundTimer, Register(x)]) => Instruction::SetSoundTimer(*x),
+        ("LD", [Font, Register(x)]) => Instruction::LoadHexGlyph(*x),
+        ("LD", [BigFont, Register(x)]) => Instruction::LoadBigHexGlyph(*x),
+        ("LD", [Bcd, Register(x)]) => Instruction::StoreBCD(*x),
+        ("LD", [IndirectI, Register(x)]) => Instruction::StoreRegisters(*x),
+        ("LD", [Register(x), IndirectI]) => Instruction::LoadRegisters(*x),
+        ("LD", [Flags, Register(x)]) => Instruction::StoreFlags(*x),
+        ("LD", [Register(x), Flags]) => Instruction::LoadFlags(*x),
+        ("ADD", [Register(x), Value(nn)]) => Instruction::AddK(*x, byte(*nn, 1)?),
+        ("ADD", [Register(x), Register(y)]) => Instruction::Add(*x, *y),
+        ("ADD", [I, Register(x)]) => Instruction::AddToI(*x),
+        ("OR", [Register(x), Register(y)]) => Instruction::Or(*x, *y),
+        ("AND", [Register(x), Register(y)]) => Instruction::And(*x, *y),
+        ("XOR", [Register(x), Register(y)]) => Instruction::XOr(*x, *y),
+        ("SUB", [Register(x), Register(y)]) => Instruction::Sub(*x, *y),
+        ("SUBN", [Register(x), Register(y)]) => Instruction::SubInv(*x, *y),
+        // Without a second register, shift VX in place whichever quirk is on.
+        ("SHR", [Register(x)]) => Instruction::ShiftRight(*x, *x),
+        ("SHR", [Register(x), Register(y)]) => Instruction::ShiftRight(*x, *y),
+        ("SHL", [Register(x)]) => Instruction::ShiftLeft(*x, *x),
+        ("SHL", [Register(x), Register(y)]) => Instruction::ShiftLeft(*x, *y),
+        ("RND", [Register(x), Value(nn)]) => Instruction::Rand(*x, byte(*nn, 1)?),
+        ("DRW", [Register(x), Register(y), Value(n)]) => Instruction::Draw(*x, *y, nibble(*n, 2)?),
+        ("SKP", [Register(x)]) => Instruction::SkipPressed(*x),
+        ("SKNP", [Register(x)]) => Instruction::SkipNotPressed(*x),
+        ("PLANE", [Value(n)]) => Instruction::SelectPlanes(nibble(*n, 0)?),
+        ("AUDIO", []) => Instruction::LoadAudioPattern,
+        ("PITCH", [Register(x)]) => Instruction::SetPitch(*x),
+        _ => {
+            let known = [
+                "CLS", "RET", "SCD", "SCU", "SCR", "SCL", "EXIT", "LOW", "HIGH", "JP", "CALL",
+                "SE", "SNE", "SAVE", "LOAD", "LD", "ADD", "OR", "AND", "XOR", "SUB", "SUBN", "SHR",
+                "SHL", "RND", "DRW", "SKP", "SKNP", "PLANE", "AUDIO", "PITCH",
+            ];
+            if known.contains(&mnemonic) {
+                return error(location, format!("invalid operands for {mnemonic}"));
+            }
+            return error(location, format!("unknown mnemonic '{mnemonic}'"));
+        }
+    };
+    return Ok(instruction.encode().to_be_bytes().to_vec());
+}
+
+fn tokenize(file: &str, line: usize, text: &str) -> Result<Vec<Token>, AssemblyError> {
+    let characters: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut index = 0;
+    while index < characters.len() {
+        let character = characters[index];
+        let location = SourceLocation {
+            file: file.to_string(),
+            line,
+            column: index + 1,
+        };
+        let start = index;
+        let kind = if character == ';' {
+            break;
+        } else if character.is_whitespace() {
+            index += 1;
+            continue;
+        } else if character.is_ascii_alphabetic() || character == '_' || character == '.' {
+            while index < characters.len()
+                && (characters[index].is_ascii_alphanumeric()
+                    || characters[index] == '_'
+                    || characters[index] == '.')
+            {
+                index += 1;
+            }
+            TokenKind::Identifier(characters[start..index].iter().collect())
+        } else if character.is_ascii_digit() || character == '$' || character == '%' {
+            index += 1;
+            while index < characters.len()
+                && (characters[index].is_ascii_alphanumeric() || characters[index] == '_')
+            {
+                index += 1;
+            }
+            let literal: String = characters[start..index].iter().collect();
+            match parse_number(&literal) {
+                Some(value) => TokenKind::Number(value),
+                None => return error(&location, format!("invalid number '{literal}'")),
+            }
+        } else if character == '"' {
+            index += 1;
+            while index < characters.len() && characters[index] != '"' {
+                index += 1;
+            }
+            if index == characters.len() {
+                return error(&location, String::from("unterminated string"));
+            }
+            index += 1;
+            let text: String = characters[start + 1..index - 1].iter().collect();
+            TokenKind::Text(text.into_bytes())
+        } else if ",:=+-[]".contains(character) {
+            index += 1;
+            TokenKind::Symbol(character)
+        } else {
+            return error(&location, format!("unexpected character '{character}'"));
+        };
+        tokens.push(Token { kind, location });
+    }
+    return Ok(tokens);
+}
+
+fn parse_number(literal: &str) -> Option<i64> {
+    let literal = literal.replace('_', "");
+    let lower = literal.to_ascii_lowercase();
+    let (digits, radix) = if let Some(digits) = lower.strip_prefix("0x") {
+        (digits, 16)
+    } else if let Some(digits) = lower.strip_prefix('$') {
+        (digits, 16)
+    } else if let Some(digits) = lower.strip_prefix("0b") {
+        (digits, 2)
+    } else if let Some(digits) = lower.strip_prefix('%') {
+        (digits, 2)
+    } else {
+        (lower.as_str(), 10)
+    };
+    return i64::from_str_radix(digits, radix).ok();
+}
+
+/// Splits tokens on commas, complaining about empty pieces.
+fn split_commas<'a>(
+    tokens: &'a [Token],
+    location: &SourceLocation,
+) -> Result<Vec<&'a [Token]>, AssemblyError> {
+    if tokens.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut pieces = Vec::new();
+    for piece in tokens.split(|token| token.kind == TokenKind::Symbol(',')) {
+        if piece.is_empty() {
+            return error(location, String::from("missing operand"));
+        }
+        pieces.push(piece);
+    }
+    return Ok(pieces);
+}
+
+fn parse_operands(
+    tokens: &[Token],
+    location: &SourceLocation,
+) -> Result<Vec<(Operand, SourceLocation)>, AssemblyError> {
+    let mut operands = Vec::new();
+    for piece in split_commas(tokens, location)? {
+        let start = piece[0].location.clone();
+        let keyword = match &piece[0].kind {
+            TokenKind::Identifier(word) => word.to_ascii_uppercase(),
+            _ => String::new(),
+        };
+        let operand = match (keyword.as_str(), piece.len()) {
+            (_, 1) if parse_register(&keyword).is_some() => {
+                Operand::Register(parse_register(&keyword).unwrap_or_default())
+            }
+            ("I", 1) => Operand::I,
+            ("DT", 1) => Operand::DelayTimer,
+            ("ST", 1) => Operand::SoundTimer,
+            ("K", 1) => Operand::Key,
+            ("F", 1) => Operand::Font,
+            ("HF", 1) => Operand::BigFont,
+            ("B", 1) => Operand::Bcd,
+            ("R", 1) => Operand::Flags,
+            ("LONG", _) => Operand::Long(parse_expression(&piece[1..], &start)?),
+            _ => match piece.iter().map(|token| &token.kind).collect::<Vec<_>>()[..] {
+                [TokenKind::Symbol('['), TokenKind::Identifier(i), TokenKind::Symbol(']')]
+                    if i.eq_ignore_ascii_case("i") =>
+                {
+                    Operand::IndirectI
+                }
+                _ => Operand::Value(parse_expression(piece, &start)?),
+            },
+        };
+        operands.push((operand, start));
+    }
+    return Ok(operands);
+}
+
+fn parse_register(word: &str) -> Option<RegisterNumber> {
+    let digit = word.strip_prefix('V')?;
+    if digit.len() != 1 {
+        return None;
+    }
+    return u8::from_str_radix(digit, 16).ok();
+}
+
+fn parse_data(
+    tokens: &[Token],
+    location: &SourceLocation,
+) -> Result<Vec<(DataItem, SourceLocation)>, AssemblyError> {
+    let mut items = Vec::new();
+    for piece in split_commas(tokens, location)? {
+        let start = piece[0].location.clone();
+        let item = match piece {
+            [Token {
+                kind: TokenKind::Text(bytes),
+                ..
+            }] => DataItem::Text(bytes.clone()),
+            _ => DataItem::Value(parse_expression(piece, &start)?),
+        };
+        items.push((item, start));
+    }
+    if items.is_empty() {
+        return error(location, String::from("expected at least one value"));
+    }
+    return Ok(items);
+}
+
+fn parse_whole_expression(
+    tokens: &[Token],
+    location: &SourceLocation,
+) -> Result<Expression, AssemblyError> {
+    if tokens.is_empty() {
+        return error(location, String::from("expected a value"));
+    }
+    return parse_expression(tokens, location);
+}
+
+/// `[+|-] term {(+|-) term}`, where a term is a number or a name.
+fn parse_expression(
+    tokens: &[Token],
+    location: &SourceLocation,
+) -> Result<Expression, AssemblyError> {
+    let mut terms = Vec::new();
+    let mut negative = false;
+    let mut expect_term = true;
+    for token in tokens {
+        match (&token.kind, expect_term) {
+            (TokenKind::Symbol('-'), true) if terms.is_empty() => negative = !negative,
+            (TokenKind::Symbol('+'), true) if terms.is_empty() => {}
+            (TokenKind::Number(value), true) => {
+                terms.push((negative, Term::Number(*value), token.location.clone()));
+                expect_term = false;
+            }
+            (TokenKind::Identifier(name), true) => {
+                terms.push((negative, Term::Name(name.clone()), token.location.clone()));
+                expect_term = false;
+            }
+            (TokenKind::Symbol(sign @ ('+' | '-')), false) => {
+                negative = *sign == '-';
+                expect_term = true;
+            }
+            _ => {
+                return error(
+                    &token.location,
+                    String::from("unexpected token in expression"),
+                )
+            }
+        }
+    }
+    if expect_term {
+        let end = tokens.last().map_or(location, |token| &token.location);
+        return error(end, String::from("expected a value"));
+    }
+    return Ok(Expression { terms });
+}
+
+#[test]
+fn assembles_classic_mnemonics() {
+    let source = "
+        HEIGHT = 5          ; constants can be used before or after their definition
+        start:  LD V0, 0x05
+                LD I, sprite
+                DRW V0, V1, HEIGHT
+                LD I, LONG sprite + 1
+                SHR V2
+                JP start
+        sprite: db $F0, %10010000, -1
+                dw 0x1234
+    ";
+    let assembly = assemble(source).unwrap();
+    assert_eq!(
+        assembly.rom,
+        vec![
+            0x60, 0x05, 0xA2, 0x0E, 0xD0, 0x15, 0xF0, 0x00, 0x02, 0x0F, 0x82, 0x26, 0x12, 0x00,
+            0xF0, 0x90, 0xFF, 0x12, 0x34
+        ]
+    );
+    assert_eq!(assembly.source_map[&0x206].line, 6);
+
+    let error = assemble("LD V0, 0x100").unwrap_err();
+    assert_eq!((error.location.line, error.location.column), (1, 8));
+    let error = assemble("  JP nowhere").unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "<source>:1:6: undefined symbol 'nowhere'"
+    );
+    let error = assemble("LD V0, 0x7FFFFFFFFFFFFFFF + 1").unwrap_err();
+    assert_eq!(error.to_string(), "<source>:1:29: expression overflows");
+}
+
+#[test]
+fn assembles_extended_mnemonics() {
+    let source = "
+        SCD 4
+        SCU 2
+        SCR
+        SCL
+        LOW
+        HIGH
+        EXIT
+        JP V0, 0x300
+        LD HF, V0
+        LD R, V2
+        LD V3, R
+        SAVE V1, V3
+        LOAD V2, V4
+        PLANE 3
+        AUDIO
+        PITCH V1
+        LD I, LONG 0x1234
+    ";
+    assert_eq!(
+        assemble(source).unwrap().rom,
+        vec![
+            0x00, 0xC4, 0x00, 0xD2, 0x00, 0xFB, 0x00, 0xFC, 0x00, 0xFE, 0x00, 0xFF, 0x00, 0xFD,
+            0xB3, 0x00, 0xF0, 0x30, 0xF2, 0x75, 0xF3, 0x85, 0x51, 0x32, 0x52, 0x43, 0xF3, 0x01,
+            0xF0, 0x02, 0xF1, 0x3A, 0xF0, 0x00, 0x12, 0x34,
+        ]
+    );
+
+    // The long load is four bytes, so the label after it is too.
+    let assembly = assemble("LD I, LONG end\nend: CLS").unwrap();
+    assert_eq!(assembly.rom, vec![0xF0, 0x00, 0x02, 0x04, 0x00, 0xE0]);
+    assert_eq!(assembly.source_map[&0x204].line, 2);
+
+    let error = assemble("SCD").unwrap_err();
+    assert_eq!(error.message, "invalid operands for SCD");
+    let error = assemble("PLANE 16").unwrap_err();
+    assert_eq!(error.message, "value 16 is out of range, expected 0 to 15");
+    let error = assemble("LD I, LONG 0x10000").unwrap_err();
+    assert_eq!(
+        error.message,
+        "value 65536 is out of range, expected 0 to 65535"
+    );
+    let error = assemble("FLY V0").unwrap_err();
+    assert_eq!(error.message, "unknown mnemonic 'FLY'");
+}
+
+#[test]
+fn reports_label_errors() {
+    let error = assemble("loop: CLS\nloop: RET").unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "<source>:2:1: 'loop' is already defined at <source>:1:1"
+    );
+    let error = assemble("A = B\nB = A + 1\nLD V0, A").unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "<source>:2:5: 'A' is defined in terms of itself"
+    );
+    let error = assemble("FAR = 0x1000\nJP FAR").unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "<source>:2:4: value 4096 is out of range, expected 0 to 4095"
+    );
+    let error = assemble("5: CLS").unwrap_err();
+    assert_eq!(error.message, "expected a mnemonic or directive");
+}
+
+#[test]
+fn resolves_includes() {
+    let directory = std::env::temp_dir().join(format!("chip8-includes-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&directory);
+    fs::create_dir_all(directory.join("lib")).unwrap();
+    let write = |name: &str, source: &str| fs::write(directory.join(name), source).unwrap();
+    write(
+        "main.asm",
+        "include \"lib/routines.asm\"\nstart: CALL routine\n",
+    );
+    write("lib/routines.asm", "routine: RET\ninclude \"data.asm\"\n");
+    write("lib/data.asm", "db 1\n");
+    write("missing.asm", "CLS\n  include \"nowhere.asm\"\n");
+    write("loop.asm", "include \"lib/back.asm\"\n");
+    write("lib/back.asm", "include \"../loop.asm\"\n");
+
+    // Each include is found next to the file that includes it.
+    let assembly = assemble_file(directory.join("main.asm")).unwrap();
+    assert_eq!(assembly.rom, vec![0x00, 0xEE, 0x01, 0x22, 0x00]);
+    assert!(assembly.source_map[&0x200].file.ends_with("routines.asm"));
+    assert!(assembly.source_map[&0x202].file.ends_with("data.asm"));
+    assert!(assembly.source_map[&0x203].file.ends_with("main.asm"));
+
+    let error = assemble_file(directory.join("missing.asm")).unwrap_err();
+    assert!(error.location.file.ends_with("missing.asm"));
+    assert_eq!((error.location.line, error.location.column), (2, 11));
+    assert!(
+        error.message.starts_with("cannot read"),
+        "{}",
+        error.message
+    );
+
+    let error = assemble_file(directory.join("loop.asm")).unwrap_err();
+    assert!(error.location.file.ends_with("back.asm"));
+    assert!(
+        error.message.ends_with("loop.asm includes itself"),
+        "{}",
+        error.message
+    );
+
+    let error = assemble("include nowhere").unwrap_err();
+    assert_eq!(error.message, "expected a quoted file name");
+    let _ = fs::remove_dir_all(&directory);
+}
@@ -0,0 +1,389 @@
+use std::f32::consts::PI;
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+#[cfg(feature = "frontend")]
+use std::sync::mpsc::{self, Receiver, Sender};
+
+use crate::chip8::Chip8;
+
+pub const DEFAULT_SAMPLE_RATE: u32 = 44100;
+const FRAMES_PER_SECOND: u32 = 60;
+
+/// Where generated samples go. Samples are signed 16-bit mono.
+pub trait AudioSink {
+    fn write_samples(&mut self, samples: &[i16]) -> io::Result<()>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Waveform {
+    Square,
+    Sine,
+    Triangle,
+    Sawtooth,
+}
+
+impl Waveform {
+    pub fn from_name(name: &str) -> Option<Waveform> {
+        return match name.to_ascii_lowercase().as_str() {
+            "square" => Some(Waveform::Square),
+            "sine" => Some(Waveform::Sine),
+            "triangle" => Some(Waveform::Triangle),
+            "sawtooth" => Some(Waveform::Sawtooth),
+            _ => None,
+        };
+    }
+
+    /// The value of the wave at `phase`, which runs from 0 to 1 over a period.
+    fn sample(&self, phase: f32) -> f32 {
+        return match self {
+            Waveform::Square => {
+                if phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Waveform::Sine => (phase * 2.0 * PI).sin(),
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            Waveform::Sawtooth => 2.0 * phase - 1.0,
+        };
+    }
+}
+
+/// Turns the sound timer into samples.
+///
+/// While the timer is running a plain tone is generated, unless the ROM has
+/// loaded an XO-CHIP audio pattern (F002), in which case the 128-bit pattern
+/// is looped at the rate set by the pitch register (FX3A).
+#[derive(Debug, Clone, PartialEq)]
+pub struct Buzzer {
+    pub sample_rate: u32,
+    pub frequency: f32,
+    pub waveform: Waveform,
+    pub volume: f32,
+    phase: f32,
+}
+
+impl Buzzer {
+    pub fn new(sample_rate: u32) -> Buzzer {
+        return Buzzer {
+            sample_rate,
+            frequency: 440.0,
+            waveform: Waveform::Square,
+            volume: 0.25,
+            phase: 0.0,
+        };
+    }
+
+    pub fn frequency(mut self, frequency: f32) -> Buzzer {
+        self.frequency = frequency;
+        return self;
+    }
+
+    pub fn waveform(mut self, waveform: Waveform) -> Buzzer {
+        self.waveform = waveform;
+        return self;
+    }
+
+    pub fn volume(mut self, volume: f32) -> Buzzer {
+        self.volume = volume.clamp(0.0, 1.0);
+        return self;
+    }
+
+    /// How many samples make up one 60 Hz frame.
+    pub fn samples_per_frame(&self) -> usize {
+        return (self.sample_rate / FRAMES_PER_SECOND) as usize;
+    }
+
+    /// Fills `samples` with what `chip8` played over the frame that ended
+    /// at its last timer tick.
+    pub fn generate(&mut self, chip8: &Chip8, samples: &mut [i16]) {
+        if !chip8.is_sounding() {
+            self.phase = 0.0;
+            samples.fill(0);
+            return;
+        }
+
+        let pattern = chip8.get_audio_pattern();
+        let amplitude = self.volume * i16::MAX as f32;
+        if pattern.iter().all(|byte| *byte == 0) {
+            let step = self.frequency / self.sample_rate as f32;
+            for sample in samples.iter_mut() {
+                *sample = (self.waveform.sample(self.phase) * amplitude) as i16;
+                self.phase = (self.phase + step).fract();
+            }
+        } else {
+            // The phase counts bits into the pattern rather than periods.
+            let bits = (pattern.len() * 8) as f32;
+            let rate = 4000.0 * 2f32.powf((chip8.get_pitch() as f32 - 64.0) / 48.0);
+            let step = rate / self.sample_rate as f32;
+            for sample in samples.iter_mut() {
+                let bit = self.phase as usize;
+                let set = pattern[bit / 8] & (0x80 >> (bit % 8)) != 0;
+                *sample = (if set { amplitude } else { -amplitude }) as i16;
+                self.phase = (self.phase + step) % bits;
+            }
+        }
+    }
+
+    /// Generates the audio for the frame `chip8` just ran, once, to be
+    /// written to every sink.
+    pub fn render_frame(&mut self, chip8: &Chip8) -> Vec<i16> {
+        let mut samples = vec![0; self.samples_per_frame()];
+        self.generate(chip8, &mut samples);
+        return samples;
+    }
+}
+
+/// Keeps every sample in memory, for tests and tools.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemorySink {
+    pub samples: Vec<i16>,
+}
+
+impl AudioSink for MemorySink {
+    fn write_samples(&mut self, samples: &[i16]) -> io::Result<()> {
+        self.samples.extend_from_slice(samples);
+        return Ok(());
+    }
+}
+
+/// Writes a 16-bit mono PCM WAV file. The header sizes are filled in by
+/// `finish`, so a sink that is dropped without it leaves a truncated file.
+pub struct WavSink<W: Write + Seek> {
+    writer: W,
+    data_length: u32,
+}
+
+impl WavSink<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P, sample_rate: u32) -> io::Result<Self> {
+        return WavSink::new(BufWriter::new(File::create(path)?), sample_rate);
+    }
+}
+
+impl<W: Write + Seek> WavSink<W> {
+    pub fn new(mut writer: W, sample_rate: u32) -> io::Result<Self> {
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.write_all(b"WAVEfmt ")?;
+        writer.write_all(&16u32.to_le_bytes())?;
+        writer.write_all(&1u16.to_le_bytes())?; // PCM
+        writer.write_all(&1u16.to_le_bytes())?; // mono
+        writer.write_all(&sample_rate.to_le_bytes())?;
+        writer.write_all(&(sample_rate * 2).to_le_bytes())?;
+        writer.write_all(&2u16.to_le_bytes())?;
+        writer.write_all(&16u16.to_le_bytes())?;
+        writer.write_all(b"data")?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        return Ok(WavSink {
+            writer,
+            data_length: 0,
+        });
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        let riff_length = self.data_length.checked_add(36).ok_or_else(too_long)?;
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer.write_all(&riff_length.to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(40))?;
+        self.writer.write_all(&self.data_length.to_le_bytes())?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()?;
+        return Ok(self.writer);
+    }
+}
+
+impl<W: Write + Seek> AudioSink for WavSink<W> {
+    /// Fails without writing anything once the file would pass the 4 GiB
+    /// a WAV header can describe.
+    fn write_samples(&mut self, samples: &[i16]) -> io::Result<()> {
+        let data_length = u32::try_from(samples.len() * 2)
+            .ok()
+            .and_then(|length| self.data_length.checked_add(length))
+            .filter(|length| length.checked_add(36).is_some())
+            .ok_or_else(too_long)?;
+        for sample in samples {
+            self.writer.write_all(&sample.to_le_bytes())?;
+        }
+        self.data_length = data_length;
+        return Ok(());
+    }
+}
+
+fn too_long() -> io::Error {
+    return io::Error::new(io::ErrorKind::InvalidData, "WAV file too long");
+}
+
+/// How much audio may queue up for the device, in 60 Hz frames. More rides
+/// out a slow frame, less keeps the buzzer closer to the screen.
+#[cfg(feature = "frontend")]
+const DEVICE_LATENCY_FRAMES: u32 = 3;
+
+/// Plays samples on the default output device. The device pulls samples
+/// from a ring buffer on its own thread, so writing never blocks: samples
+/// that do not fit within the latency are dropped, and the device plays
+/// silence when the buffer runs dry.
+#[cfg(feature = "frontend")]
+pub struct DeviceSink {
+    samples: ringbuf::HeapProd<i16>,
+    // Errors the device reports from its own thread.
+    errors: Receiver<String>,
+    sample_rate: u32,
+    // Playback stops when the stream is dropped.
+    _stream: cpal::Stream,
+}
+
+#[cfg(feature = "frontend")]
+impl DeviceSink {
+    /// Opens the default output device at its preferred sample rate, which
+    /// `get_sample_rate` reports so the `Buzzer` can match it.
+    pub fn open() -> io::Result<DeviceSink> {
+        use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+        use cpal::SampleFormat;
+        use ringbuf::traits::Split;
+        use ringbuf::HeapRb;
+
+        let device = cpal::default_host()
+            .default_output_device()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no audio output device"))?;
+        let supported = device.default_output_config().map_err(device_error)?;
+        let config = supported.config();
+        let capacity = (config.sample_rate.0 / FRAMES_PER_SECOND * DEVICE_LATENCY_FRAMES) as usize;
+        let (producer, consumer) = HeapRb::<i16>::new(capacity).split();
+        let (sender, errors) = mpsc::channel();
+        let stream = match supported.sample_format() {
+            SampleFormat::I16 => build_stream::<i16>(&device, &config, consumer, sender),
+            SampleFormat::U16 => build_stream::<u16>(&device, &config, consumer, sender),
+            SampleFormat::F32 => build_stream::<f32>(&device, &config, consumer, sender),
+            format => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!("unsupported audio sample format {format}"),
+                ))
+            }
+        }?;
+        stream.play().map_err(device_error)?;
+        return Ok(DeviceSink {
+            samples: producer,
+            errors,
+            sample_rate: config.sample_rate.0,
+            _stream: stream,
+        });
+    }
+
+    pub fn get_sample_rate(&self) -> u32 {
+        return self.sample_rate;
+    }
+}
+
+/// An output stream that plays mono samples from `samples` on every channel,
+/// sending any error it runs into to `errors`.
+#[cfg(feature = "frontend")]
+fn build_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    mut samples: ringbuf::HeapCons<i16>,
+    errors: Sender<String>,
+) -> io::Result<cpal::Stream>
+where
+    T: cpal::SizedSample + cpal::FromSample<i16>,
+{
+    use cpal::traits::DeviceTrait;
+    use ringbuf::traits::Consumer;
+
+    let channels = config.channels as usize;
+    let stream = device.build_output_stream(
+        config,
+        move |output: &mut [T], _: &cpal::OutputCallbackInfo| {
+            for frame in output.chunks_mut(channels) {
+                let sample = T::from_sample(samples.try_pop().unwrap_or(0));
+                frame.fill(sample);
+            }
+        },
+        move |error| {
+            let _ = errors.send(error.to_string());
+        },
+        None,
+    );
+    return stream.map_err(device_error);
+}
+
+#[cfg(feature = "frontend")]
+fn device_error(error: impl std::fmt::Display) -> io::Error {
+    return io::Error::other(format!("audio device: {error}"));
+}
+
+#[cfg(feature = "frontend")]
+impl AudioSink for DeviceSink {
+    /// Fails with the first error the device has reported since the last
+    /// write, if any.
+    fn write_samples(&mut self, samples: &[i16]) -> io::Result<()> {
+        use ringbuf::traits::Producer;
+
+        if let Ok(error) = self.errors.try_recv() {
+            return Err(device_error(error));
+        }
+        self.samples.push_slice(samples);
+        return Ok(());
+    }
+}
+
+#[test]
+fn buzzer_follows_sound_timer() {
+    use crate::quirks::Quirks;
+
+    // 6002 F018 1204: V0 = 2, sound timer = V0, loop forever
+    let mut chip8 = Chip8::new(Quirks::default());
+    chip8
+        .load_rom(vec![0x60, 0x02, 0xF0, 0x18, 0x12, 0x04])
+        .unwrap();
+    chip8.execute_cycle().unwrap();
+    chip8.execute_cycle().unwrap();
+
+    let mut buzzer = Buzzer::new(6000).frequency(500.0).volume(1.0);
+    let mut sink = MemorySink::default();
+    for _ in 0..3 {
+        chip8.tick_timers();
+        let samples = buzzer.render_frame(&chip8);
+        sink.write_samples(&samples).unwrap();
+    }
+
+    // Two frames of a 500 Hz square wave at 6 kHz, then silence.
+    assert_eq!(sink.samples.len(), 300);
+    assert_eq!(&sink.samples[0..6], &[i16::MAX; 6]);
+    assert_eq!(&sink.samples[6..12], &[-i16::MAX; 6]);
+    assert!(sink.samples[100..200].iter().all(|sample| *sample != 0));
+    assert!(sink.samples[200..].iter().all(|sample| *sample == 0));
+
+    // A timer of 1 set during a frame is counted down at the end of it, but
+    // still sounds for that frame.
+    chip8.set_sound_timer(1);
+    chip8.run_frame().unwrap();
+    assert_eq!(chip8.get_sound_timer(), 0);
+    assert!(buzzer
+        .render_frame(&chip8)
+        .iter()
+        .any(|sample| *sample != 0));
+    chip8.run_frame().unwrap();
+    assert!(buzzer
+        .render_frame(&chip8)
+        .iter()
+        .all(|sample| *sample == 0));
+}
+
+#[test]
+fn wav_size_limit() {
+    use std::io::Cursor;
+
+    let mut sink = WavSink::new(Cursor::new(Vec::new()), 8000).unwrap();
+    sink.write_samples(&[1, -1]).unwrap();
+    sink.data_length = u32::MAX - 36 - 4;
+    sink.write_samples(&[1, 2]).unwrap();
+    let error = sink.write_samples(&[3]).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(sink.data_length, u32::MAX - 36);
+    let wav = sink.finish().unwrap().into_inner();
+    assert_eq!(&wav[4..8], &u32::MAX.to_le_bytes());
+    assert_eq!(wav.len(), 44 + 8);
+}
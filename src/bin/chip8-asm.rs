@@ -0,0 +1,61 @@
+#![allow(clippy::needless_return)]
+
+//! Assembles classic CHIP-8 mnemonics into a ROM.
+//!
+//! usage: chip8-asm [options] <source>
+//!   --output <path>    where to write the ROM (default: the source path with .ch8)
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::exit;
+
+use chip_8_interpreter::assembler;
+
+struct Options {
+    source_path: String,
+    output_path: Option<String>,
+}
+
+fn fail(message: String) -> ! {
+    eprintln!("{message}");
+    exit(1);
+}
+
+fn parse_options() -> Options {
+    let mut source_path = None;
+    let mut output_path = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--output" => output_path = args.next(),
+            _ => source_path = Some(arg),
+        }
+    }
+
+    return Options {
+        source_path: source_path.unwrap_or_else(|| fail("source path not provided".to_string())),
+        output_path,
+    };
+}
+
+fn main() {
+    let options = parse_options();
+
+    let assembly = assembler::assemble_file(&options.source_path)
+        .unwrap_or_else(|error| fail(error.to_string()));
+    let output_path = match options.output_path {
+        Some(path) => path,
+        None => Path::new(&options.source_path)
+            .with_extension("ch8")
+            .display()
+            .to_string(),
+    };
+    if output_path == options.source_path {
+        fail(format!("refusing to overwrite {output_path}, use --output"));
+    }
+    if let Err(error) = fs::write(&output_path, &assembly.rom) {
+        fail(format!("error writing {output_path}: {error}"));
+    }
+}
@@ -0,0 +1,76 @@
+#![allow(clippy::needless_return)]
+
+//! Disassembles a ROM, following jumps and calls to tell code from data.
+//!
+//! usage: chip8-disasm [options] <rom>
+//!   --syntax <name>    classic (default) or octo mnemonics in the listing
+//!   --octo             print Octo source that assembles back to the same ROM
+//!   --output <path>    write to a file instead of stdout
+
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::process::exit;
+
+use chip_8_interpreter::disassembler::{Disassembly, Syntax};
+
+struct Options {
+    rom_path: String,
+    syntax: Syntax,
+    octo_source: bool,
+    output_path: Option<String>,
+}
+
+fn fail(message: String) -> ! {
+    eprintln!("{message}");
+    exit(1);
+}
+
+fn parse_options() -> Options {
+    let mut rom_path = None;
+    let mut options = Options {
+        rom_path: String::new(),
+        syntax: Syntax::Classic,
+        octo_source: false,
+        output_path: None,
+    };
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--syntax" => {
+                let name = args.next().unwrap_or_default();
+                options.syntax = Syntax::from_name(&name)
+                    .unwrap_or_else(|| fail(format!("unknown syntax '{name}'")));
+            }
+            "--octo" => options.octo_source = true,
+            "--output" => options.output_path = args.next(),
+            _ => rom_path = Some(arg),
+        }
+    }
+
+    options.rom_path = rom_path.unwrap_or_else(|| fail("rom path not provided".to_string()));
+    return options;
+}
+
+fn main() {
+    let options = parse_options();
+
+    let rom = fs::read(&options.rom_path)
+        .unwrap_or_else(|error| fail(format!("error reading {}: {error}", options.rom_path)));
+    let disassembly = Disassembly::new(&rom)
+        .unwrap_or_else(|error| fail(format!("{}: {error}", options.rom_path)));
+    let text = if options.octo_source {
+        disassembly.octo_source()
+    } else {
+        disassembly.listing(options.syntax)
+    };
+
+    let result = match &options.output_path {
+        Some(path) => fs::write(path, text),
+        None => io::stdout().write_all(text.as_bytes()),
+    };
+    if let Err(error) = result {
+        fail(format!("error writing output: {error}"));
+    }
+}
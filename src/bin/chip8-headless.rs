@@ -0,0 +1,362 @@
+#![allow(clippy::needless_return)]
+
+//! Runs a ROM without a window, for CI and regression tests.
+//!
+//! usage: chip8-headless [options] <rom or .8o source>
+//!   --quirks <preset>          vip, chip48, schip or xochip
+//!   --ipf <n>                  instructions per frame
+//!   --seed <n>                 random number seed (default 0, so runs repeat)
+//!   --frames <n>               stop after n frames (default 600, or the movie's length)
+//!   --keys <script>            inject key presses, one `<frame> <key> press|release` per line
+//!   --movie <path>             play back a recorded movie, using its quirks, ipf and seed
+//!   --display <path>           write the final screen as text, or as PNG for *.png, or - for stdout
+//!   --registers <path>         write the final registers as JSON, or - for stdout
+//!   --expect-display <path>    compare the final screen against a golden file
+//!   --expect-registers <path>  compare the final registers against a golden file
+//!   --trace <path>             write a record of every instruction executed
+//!   --trace-format <format>    text (default) or json, one object per line
+//!   --trace-range <start-end>  only trace instructions in this hex address range; repeatable
+//!   --trace-max-size <bytes>   start a new trace file past this size, moving the old one to <path>.1
+//!   --trace-keep <n>           how many old trace files to keep when rotating (default 1)
+//!   --profile <path>           write a report of where cycles went, or - for stdout
+//!   --profile-top <n>          hottest addresses to list in the report (default 20)
+//!   --callgrind <path>         write the profile in callgrind format for KCachegrind
+//!   --coverage <path>          write which lines and skips ran as an lcov tracefile
+//!
+//! The run also stops early when the ROM exits with 00FD or settles on a
+//! jump to itself. Coverage of Octo source is reported against its lines;
+//! for a plain ROM each instruction's line number is its address. Exits
+//! with 1 on errors and 2 when a golden file differs.
+
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+use std::process::exit;
+use std::str::FromStr;
+
+use chip_8_interpreter::assembler::Assembly;
+use chip_8_interpreter::coverage::Coverage;
+use chip_8_interpreter::movie::parse_key_events;
+use chip_8_interpreter::octo::read_program;
+use chip_8_interpreter::profile::Profiler;
+use chip_8_interpreter::trace::{parse_address_range, TraceFormat, Tracer};
+use chip_8_interpreter::{Address, Chip8, Config, DisplayBuffer, Movie, MoviePlayer, Quirks};
+
+const DEFAULT_FRAMES: usize = 600;
+const DEFAULT_PROFILE_TOP: usize = 20;
+
+/// Grey levels for the XO-CHIP plane combinations, matching the window's palette.
+const PALETTE: [u8; 4] = [0x00, 0xFF, 0xAA, 0x55];
+
+struct Options {
+    rom_path: String,
+    quirks: Quirks,
+    instructions_per_frame: usize,
+    seed: u64,
+    frames: Option<usize>,
+    keys_path: Option<String>,
+    movie_path: Option<String>,
+    display_path: Option<String>,
+    registers_path: Option<String>,
+    expect_display_path: Option<String>,
+    expect_registers_path: Option<String>,
+    trace_path: Option<String>,
+    trace_format: TraceFormat,
+    trace_ranges: Vec<RangeInclusive<Address>>,
+    trace_max_size: Option<u64>,
+    trace_keep: usize,
+    profile_path: Option<String>,
+    profile_top: usize,
+    callgrind_path: Option<String>,
+    coverage_path: Option<String>,
+}
+
+fn fail(message: String) -> ! {
+    eprintln!("{message}");
+    exit(1);
+}
+
+fn parse_value<T: FromStr>(flag: &str, value: Option<String>) -> T {
+    let value = value.unwrap_or_default();
+    match value.parse() {
+        Ok(value) => return value,
+        Err(_) => fail(format!("invalid value '{value}' for {flag}")),
+    }
+}
+
+fn parse_options() -> Options {
+    let mut rom_path = None;
+    let mut options = Options {
+        rom_path: String::new(),
+        quirks: Quirks::default(),
+        instructions_per_frame: Config::DEFAULT_INSTRUCTIONS_PER_FRAME,
+        seed: 0,
+        frames: None,
+        keys_path: None,
+        movie_path: None,
+        display_path: None,
+        registers_path: None,
+        expect_display_path: None,
+        expect_registers_path: None,
+        trace_path: None,
+        trace_format: TraceFormat::Text,
+        trace_ranges: Vec::new(),
+        trace_max_size: None,
+        trace_keep: 1,
+        profile_path: None,
+        profile_top: DEFAULT_PROFILE_TOP,
+        callgrind_path: None,
+        coverage_path: None,
+    };
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--quirks" => {
+                let name = args.next().unwrap_or_default();
+                options.quirks = Quirks::from_name(&name)
+                    .unwrap_or_else(|| fail(format!("unknown quirk preset '{name}'")));
+            }
+            "--ipf" => options.instructions_per_frame = parse_value(&arg, args.next()),
+            "--seed" => options.seed = parse_value(&arg, args.next()),
+            "--frames" => options.frames = Some(parse_value(&arg, args.next())),
+            "--keys" => options.keys_path = args.next(),
+            "--movie" => options.movie_path = args.next(),
+            "--display" => options.display_path = args.next(),
+            "--registers" => options.registers_path = args.next(),
+            "--expect-display" => options.expect_display_path = args.next(),
+            "--expect-registers" => options.expect_registers_path = args.next(),
+            "--trace" => options.trace_path = args.next(),
+            "--trace-format" => {
+                let name = args.next().unwrap_or_default();
+                options.trace_format = TraceFormat::from_name(&name)
+                    .unwrap_or_else(|| fail(format!("unknown trace format '{name}'")));
+            }
+            "--trace-range" => {
+                let range = args.next().unwrap_or_default();
+                options.trace_ranges.push(
+                    parse_address_range(&range)
+                        .unwrap_or_else(|| fail(format!("invalid address range '{range}'"))),
+                );
+            }
+            "--trace-max-size" => options.trace_max_size = Some(parse_value(&arg, args.next())),
+            "--trace-keep" => options.trace_keep = parse_value(&arg, args.next()),
+            "--profile" => options.profile_path = args.next(),
+            "--profile-top" => options.profile_top = parse_value(&arg, args.next()),
+            "--callgrind" => options.callgrind_path = args.next(),
+            "--coverage" => options.coverage_path = args.next(),
+            _ => rom_path = Some(arg),
+        }
+    }
+
+    options.rom_path = rom_path.unwrap_or_else(|| fail("rom path not provided".to_string()));
+    return options;
+}
+
+fn registers_json(chip8: &Chip8) -> String {
+    let registers: Vec<String> = chip8
+        .get_registers()
+        .iter()
+        .map(|value| value.to_string())
+        .collect();
+    let stack: Vec<String> = chip8
+        .get_stack()
+        .iter()
+        .map(|address| address.to_string())
+        .collect();
+    return format!(
+        "{{\"v\":[{}],\"i\":{},\"pc\":{},\"sp\":{},\"stack\":[{}],\"delay_timer\":{},\"sound_timer\":{}}}\n",
+        registers.join(","),
+        chip8.get_index_register(),
+        chip8.get_program_counter(),
+        chip8.get_stack().len(),
+        stack.join(","),
+        chip8.get_delay_timer(),
+        chip8.get_sound_timer(),
+    );
+}
+
+fn display_png(display: &DisplayBuffer) -> Vec<u8> {
+    let mut pixels = Vec::with_capacity(display.width() * display.height());
+    for y in 0..display.height() {
+        for x in 0..display.width() {
+            pixels.push(PALETTE[display.get_colour(x, y) as usize]);
+        }
+    }
+
+    let mut bytes = Vec::new();
+    let mut encoder =
+        png::Encoder::new(&mut bytes, display.width() as u32, display.height() as u32);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder
+        .write_header()
+        .expect("writing to memory cannot fail");
+    writer
+        .write_image_data(&pixels)
+        .expect("writing to memory cannot fail");
+    writer.finish().expect("writing to memory cannot fail");
+    return bytes;
+}
+
+/// The display in the format implied by the file name: PNG for `.png`, text otherwise.
+fn display_bytes(display: &DisplayBuffer, path: &str) -> Vec<u8> {
+    if path.to_ascii_lowercase().ends_with(".png") {
+        return display_png(display);
+    }
+    return display.to_text().into_bytes();
+}
+
+fn read_text(path: &str) -> String {
+    return fs::read_to_string(path)
+        .unwrap_or_else(|error| fail(format!("error reading {path}: {error}")));
+}
+
+fn write_output(path: &str, bytes: &[u8]) {
+    let result = if path == "-" {
+        io::stdout().write_all(bytes)
+    } else {
+        fs::write(path, bytes)
+    };
+    if let Err(error) = result {
+        fail(format!("error writing {path}: {error}"));
+    }
+}
+
+/// Compares `actual` against a golden file, reporting whether they match.
+fn matches_golden(what: &str, path: &str, actual: &[u8]) -> bool {
+    let expected =
+        fs::read(path).unwrap_or_else(|error| fail(format!("error reading {path}: {error}")));
+    if expected == actual {
+        return true;
+    }
+    eprintln!("{what} does not match {path}");
+    if let (Ok(expected), Ok(actual)) = (String::from_utf8(expected), std::str::from_utf8(actual)) {
+        eprintln!("expected:\n{expected}actual:\n{actual}");
+    }
+    return false;
+}
+
+fn finish_trace(chip8: &mut Chip8, path: Option<&str>) {
+    if let (Some(tracer), Some(path)) = (chip8.take_tracer(), path) {
+        if let Err(error) = tracer.finish() {
+            fail(format!("error writing {path}: {error}"));
+        }
+    }
+}
+
+fn write_profile(chip8: &mut Chip8, options: &Options) {
+    let profiler = match chip8.take_profiler() {
+        Some(profiler) => profiler,
+        None => return,
+    };
+    if let Some(path) = &options.profile_path {
+        write_output(path, profiler.report(options.profile_top).as_bytes());
+    }
+    if let Some(path) = &options.callgrind_path {
+        write_output(path, profiler.callgrind(&options.rom_path).as_bytes());
+    }
+}
+
+fn write_coverage(chip8: &mut Chip8, options: &Options, program: &Assembly) {
+    if let (Some(coverage), Some(path)) = (chip8.take_coverage(), &options.coverage_path) {
+        let lcov = coverage.lcov(&program.rom, &options.rom_path, Some(&program.source_map));
+        write_output(path, lcov.as_bytes());
+    }
+}
+
+fn main() {
+    let options = parse_options();
+
+    let program = read_program(&options.rom_path).unwrap_or_else(|error| fail(error));
+    let key_events = match &options.keys_path {
+        Some(path) => parse_key_events(&read_text(path))
+            .unwrap_or_else(|error| fail(format!("{path}: {error}"))),
+        None => Vec::new(),
+    };
+    let movie = options.movie_path.as_ref().map(|path| {
+        let movie =
+            Movie::parse(&read_text(path)).unwrap_or_else(|error| fail(format!("{path}: {error}")));
+        if !movie.matches_rom(&program.rom) {
+            fail(format!("{path} was recorded against a different ROM"));
+        }
+        return movie;
+    });
+
+    let config = match &movie {
+        Some(movie) => movie.config(),
+        None => Config::new()
+            .quirks(options.quirks)
+            .instructions_per_frame(options.instructions_per_frame)
+            .seed(options.seed),
+    };
+    let frames = options
+        .frames
+        .or(movie.as_ref().map(|movie| movie.frames))
+        .unwrap_or(DEFAULT_FRAMES);
+    let mut player = movie.map(MoviePlayer::new);
+    let mut chip8 = config.build();
+    if let Err(error) = chip8.load_rom(program.rom.clone()) {
+        fail(error.to_string());
+    }
+    if let Some(path) = &options.trace_path {
+        let mut tracer = Tracer::to_file(path, options.trace_format)
+            .unwrap_or_else(|error| fail(format!("error creating {path}: {error}")));
+        for range in &options.trace_ranges {
+            tracer = tracer.range(range.clone());
+        }
+        if let Some(max_size) = options.trace_max_size {
+            tracer = tracer.max_size(max_size, options.trace_keep);
+        }
+        chip8.set_tracer(tracer);
+    }
+    if options.profile_path.is_some() || options.callgrind_path.is_some() {
+        chip8.set_profiler(Profiler::new());
+    }
+    if options.coverage_path.is_some() {
+        chip8.set_coverage(Coverage::new());
+    }
+
+    let mut pending_keys = key_events.iter().peekable();
+    for frame in 0..frames {
+        if let Some(player) = player.as_mut() {
+            player.apply_frame(&mut chip8);
+        }
+        while let Some(event) = pending_keys.next_if(|event| event.frame <= frame) {
+            chip8.set_key(event.key, event.pressed);
+        }
+        if let Err(error) = chip8.run_frame() {
+            // The trace leading up to a fault is the interesting part, so keep it.
+            finish_trace(&mut chip8, options.trace_path.as_deref());
+            write_profile(&mut chip8, &options);
+            write_coverage(&mut chip8, &options, &program);
+            fail(format!("frame {frame}: {error}"));
+        }
+        if chip8.has_exited() || chip8.is_jumping_to_self() {
+            break;
+        }
+    }
+    finish_trace(&mut chip8, options.trace_path.as_deref());
+    write_profile(&mut chip8, &options);
+    write_coverage(&mut chip8, &options, &program);
+
+    if let Some(path) = &options.display_path {
+        write_output(path, &display_bytes(chip8.get_display_buffer(), path));
+    }
+    if let Some(path) = &options.registers_path {
+        write_output(path, registers_json(&chip8).as_bytes());
+    }
+
+    let mut matches = true;
+    if let Some(path) = &options.expect_display_path {
+        let actual = display_bytes(chip8.get_display_buffer(), path);
+        matches &= matches_golden("display", path, &actual);
+    }
+    if let Some(path) = &options.expect_registers_path {
+        matches &= matches_golden("registers", path, registers_json(&chip8).as_bytes());
+    }
+    if !matches {
+        exit(2);
+    }
+}
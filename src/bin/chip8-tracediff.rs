@@ -0,0 +1,181 @@
+#![allow(clippy::needless_return)]
+
+//! Finds where two instruction traces part ways.
+//!
+//! usage: chip8-tracediff [options] <left trace> <right trace>
+//!   --context <n>  records to show before and after the divergence (default 5)
+//!
+//! Traces are read in either format `chip8-headless --trace` writes and lined
+//! up by cycle, so filtered traces can be compared as long as both used the
+//! same filter. A cycle only one side traced counts as a divergence. Exits
+//! with 0 when the traces agree, 1 on errors and 2 when they diverge.
+
+use std::collections::VecDeque;
+use std::env;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Lines};
+use std::process::exit;
+use std::str::FromStr;
+
+use chip_8_interpreter::trace::{differences, TraceRecord};
+
+const DEFAULT_CONTEXT: usize = 5;
+
+struct Options {
+    left_path: String,
+    right_path: String,
+    context: usize,
+}
+
+fn fail(message: String) -> ! {
+    eprintln!("{message}");
+    exit(1);
+}
+
+fn parse_value<T: FromStr>(flag: &str, value: Option<String>) -> T {
+    let value = value.unwrap_or_default();
+    match value.parse() {
+        Ok(value) => return value,
+        Err(_) => fail(format!("invalid value '{value}' for {flag}")),
+    }
+}
+
+fn parse_options() -> Options {
+    let mut paths = Vec::new();
+    let mut context = DEFAULT_CONTEXT;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--context" => context = parse_value(&arg, args.next()),
+            _ => paths.push(arg),
+        }
+    }
+
+    match <[String; 2]>::try_from(paths) {
+        Ok([left_path, right_path]) => {
+            return Options {
+                left_path,
+                right_path,
+                context,
+            }
+        }
+        Err(_) => {
+            fail("usage: chip8-tracediff [--context n] <left trace> <right trace>".to_string())
+        }
+    }
+}
+
+/// Reads records one at a time, so traces of any length can be compared.
+struct TraceFile {
+    path: String,
+    lines: Lines<BufReader<File>>,
+    line_number: usize,
+}
+
+impl TraceFile {
+    fn open(path: &str) -> TraceFile {
+        let file =
+            File::open(path).unwrap_or_else(|error| fail(format!("error reading {path}: {error}")));
+        return TraceFile {
+            path: path.to_string(),
+            lines: BufReader::new(file).lines(),
+            line_number: 0,
+        };
+    }
+
+    /// The next record, skipping blank lines and the text format's headers.
+    fn next_record(&mut self) -> Option<TraceRecord> {
+        for line in self.lines.by_ref() {
+            self.line_number += 1;
+            let line =
+                line.unwrap_or_else(|error| fail(format!("error reading {}: {error}", self.path)));
+            let line = line.trim();
+            if line.is_empty() || line.starts_with("cycle") {
+                continue;
+            }
+            match TraceRecord::parse(line) {
+                Some(record) => return Some(record),
+                None => fail(format!(
+                    "{}:{}: not a trace record",
+                    self.path, self.line_number
+                )),
+            }
+        }
+        return None;
+    }
+
+    fn location(&self) -> String {
+        return format!("{}:{}", self.path, self.line_number);
+    }
+}
+
+/// Prints up to `count` more records from a trace, after `first`.
+fn print_following(name: &str, trace: &mut TraceFile, first: Option<TraceRecord>, count: usize) {
+    println!("{name}, from {}:", trace.location());
+    let records = first
+        .into_iter()
+        .chain(std::iter::from_fn(|| trace.next_record()));
+    for record in records.take(count + 1) {
+        println!("  {}", record.to_text());
+    }
+}
+
+fn main() {
+    let options = parse_options();
+    let mut left = TraceFile::open(&options.left_path);
+    let mut right = TraceFile::open(&options.right_path);
+
+    // Agreeing records just before the current pair, oldest first.
+    let mut history: VecDeque<TraceRecord> = VecDeque::with_capacity(options.context + 1);
+    let mut compared = 0;
+    let (left_record, right_record, reasons) = loop {
+        let (left_record, right_record) = (left.next_record(), right.next_record());
+        let reasons = match (&left_record, &right_record) {
+            (None, None) => {
+                println!("traces agree over {compared} records");
+                return;
+            }
+            (Some(record), None) => vec![format!("right trace ends before cycle {}", record.cycle)],
+            (None, Some(record)) => vec![format!("left trace ends before cycle {}", record.cycle)],
+            (Some(left_record), Some(right_record)) if left_record.cycle != right_record.cycle => {
+                vec![format!(
+                    "next traced cycle is {} on the left but {} on the right",
+                    left_record.cycle, right_record.cycle
+                )]
+            }
+            (Some(left_record), Some(right_record)) => differences(left_record, right_record),
+        };
+        if !reasons.is_empty() {
+            break (left_record, right_record, reasons);
+        }
+        compared += 1;
+        history.push_back(left_record.expect("both traces have a record here"));
+        if history.len() > options.context {
+            history.pop_front();
+        }
+    };
+
+    let cycle = left_record
+        .as_ref()
+        .or(right_record.as_ref())
+        .map(|record| record.cycle);
+    println!(
+        "traces diverge at cycle {} after {compared} matching records",
+        cycle.unwrap_or_default()
+    );
+    for reason in &reasons {
+        println!("  {reason}");
+    }
+    // Registers show the state before each instruction, so a wrong result is
+    // usually the fault of the last matching instruction.
+    if !history.is_empty() {
+        println!("matching records leading up to it, the culprit usually last:");
+        for record in &history {
+            println!("  {}", record.to_text());
+        }
+    }
+    print_following("left", &mut left, left_record, options.context);
+    print_following("right", &mut right, right_record, options.context);
+    exit(2);
+}
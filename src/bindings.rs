@@ -0,0 +1,335 @@
+//! Which host keys press which CHIP-8 keys.
+//!
+//! Bindings are read from a TOML file with a `[keys]` table for the global
+//! layout and a `[rom.<hash>]` table per ROM, keyed by `hash::rom_hash`. Each
+//! entry maps a CHIP-8 key, as a hex digit, to one host key name or a list of
+//! them:
+//!
+//! ```toml
+//! [keys]
+//! 5 = ["W", "Up"]
+//! 8 = ["S", "Down"]
+//!
+//! # Only uses 2/4/6/8, so put them on the arrows.
+//! [rom.2fd4e1c67a2d28fced849ee1bb76e7391b93eb12]
+//! 2 = "Up"
+//! 4 = "Left"
+//! 6 = "Right"
+//! 8 = "Down"
+//! ```
+//!
+//! Host key names are whatever the frontend calls its inputs, compared without
+//! regard to case. The window uses Piston's key names such as `D1`, `Q`,
+//! `Space` or `NumPad8`. Gamepad buttons are named by position: `South`,
+//! `East`, `West` and `North` for the face buttons, `LeftShoulder`,
+//! `RightShoulder`, `LeftTrigger` and `RightTrigger`, `Select` and `Start`,
+//! and `DPadUp`, `DPadDown`, `DPadLeft` and `DPadRight` for the d-pad. Keys a
+//! file leaves out keep the default layout, QWERTY plus the d-pad on 5/7/8/9,
+//! and a ROM's table replaces the global entries for the keys it lists.
+
+use std::fs;
+
+use toml::{Table, Value};
+
+/// The COSMAC VIP keypad on the left of a QWERTY keyboard, row by row, then
+/// the d-pad on the keys WASD would be, the bottom and right face buttons on
+/// the keys beside, and the shoulders on the two above.
+const DEFAULT_LAYOUT: [(&str, u8); 24] = [
+    ("D1", 0x1),
+    ("D2", 0x2),
+    ("D3", 0x3),
+    ("D4", 0xC),
+    ("Q", 0x4),
+    ("W", 0x5),
+    ("E", 0x6),
+    ("R", 0xD),
+    ("A", 0x7),
+    ("S", 0x8),
+    ("D", 0x9),
+    ("F", 0xE),
+    ("Z", 0xA),
+    ("X", 0x0),
+    ("C", 0xB),
+    ("V", 0xF),
+    ("DPadUp", 0x5),
+    ("DPadLeft", 0x7),
+    ("DPadDown", 0x8),
+    ("DPadRight", 0x9),
+    ("South", 0x6),
+    ("East", 0x4),
+    ("LeftShoulder", 0x1),
+    ("RightShoulder", 0x3),
+];
+
+/// The host keys bound to each of the sixteen CHIP-8 keys.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyBindings {
+    /// Lowercased host key names, indexed by CHIP-8 key.
+    keys: [Vec<String>; 16],
+}
+
+impl KeyBindings {
+    /// Bindings with nothing bound.
+    pub fn empty() -> KeyBindings {
+        return KeyBindings {
+            keys: Default::default(),
+        };
+    }
+
+    /// Adds `host_key` to the keys that press `key`.
+    pub fn bind(&mut self, key: u8, host_key: &str) {
+        let host_key = host_key.to_ascii_lowercase();
+        let bound = &mut self.keys[key as usize & 0xF];
+        if !bound.contains(&host_key) {
+            bound.push(host_key);
+        }
+    }
+
+    /// Replaces whatever `key` was bound to, and takes `host_keys` away from
+    /// any other key they pressed.
+    pub fn rebind(&mut self, key: u8, host_keys: &[String]) {
+        let host_keys: Vec<String> = host_keys
+            .iter()
+            .map(|host_key| host_key.to_ascii_lowercase())
+            .collect();
+        for bound in self.keys.iter_mut() {
+            bound.retain(|host_key| !host_keys.contains(host_key));
+        }
+        self.keys[key as usize & 0xF].clear();
+        for host_key in &host_keys {
+            self.bind(key, host_key);
+        }
+    }
+
+    /// The lowercased host key names bound to `key`.
+    pub fn get_host_keys(&self, key: u8) -> &[String] {
+        return &self.keys[key as usize & 0xF];
+    }
+
+    /// The CHIP-8 keys that `host_key` presses.
+    pub fn lookup(&self, host_key: &str) -> Vec<u8> {
+        let host_key = host_key.to_ascii_lowercase();
+        return (0..16)
+            .filter(|key| self.keys[*key as usize].contains(&host_key))
+            .collect();
+    }
+}
+
+impl Default for KeyBindings {
+    fn default() -> KeyBindings {
+        let mut bindings = KeyBindings::empty();
+        for (host_key, key) in DEFAULT_LAYOUT {
+            bindings.bind(key, host_key);
+        }
+        return bindings;
+    }
+}
+
+/// Which host inputs are held, so that a CHIP-8 key stays down until every
+/// input bound to it, on the keyboard or a gamepad, has been let go.
+#[derive(Debug, Clone)]
+pub struct HostInputs {
+    bindings: KeyBindings,
+    held: Vec<String>,
+}
+
+impl HostInputs {
+    pub fn new(bindings: KeyBindings) -> HostInputs {
+        return HostInputs {
+            bindings,
+            held: Vec::new(),
+        };
+    }
+
+    /// Notes that `host_key` went down, returning the CHIP-8 keys it pressed.
+    pub fn press(&mut self, host_key: &str) -> Vec<u8> {
+        let host_key = host_key.to_ascii_lowercase();
+        if self.held.contains(&host_key) {
+            return Vec::new();
+        }
+        let pressed = self.changed_keys(&host_key);
+        self.held.push(host_key);
+        return pressed;
+    }
+
+    /// Notes that `host_key` went up, returning the CHIP-8 keys it released.
+    pub fn release(&mut self, host_key: &str) -> Vec<u8> {
+        let host_key = host_key.to_ascii_lowercase();
+        if !self.held.contains(&host_key) {
+            return Vec::new();
+        }
+        self.held.retain(|held| *held != host_key);
+        return self.changed_keys(&host_key);
+    }
+
+    /// Which CHIP-8 keys the held inputs hold down, for putting the machine
+    /// back in step after loading a state saved with other keys held.
+    pub fn get_held_keys(&self) -> [bool; 16] {
+        let mut keys = [false; 16];
+        for held in &self.held {
+            for key in self.bindings.lookup(held) {
+                keys[key as usize] = true;
+            }
+        }
+        return keys;
+    }
+
+    /// The keys bound to `host_key` that no other held input holds down.
+    fn changed_keys(&self, host_key: &str) -> Vec<u8> {
+        return self
+            .bindings
+            .lookup(host_key)
+            .into_iter()
+            .filter(|key| {
+                !self
+                    .held
+                    .iter()
+                    .any(|held| self.bindings.get_host_keys(*key).contains(held))
+            })
+            .collect();
+    }
+}
+
+/// One table of a bindings file: the host keys listed for each CHIP-8 key.
+type BindingTable = Vec<(u8, Vec<String>)>;
+
+/// A parsed bindings file, from which each ROM's bindings are worked out.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BindingsFile {
+    global: BindingTable,
+    roms: Vec<(String, BindingTable)>,
+}
+
+impl BindingsFile {
+    pub fn parse(text: &str) -> Result<BindingsFile, String> {
+        let table: Table = text.parse().map_err(|error: toml::de::Error| {
+            return error.message().to_string();
+        })?;
+        let mut file = BindingsFile::default();
+        for (name, value) in &table {
+            match (name.as_str(), value) {
+                ("keys", Value::Table(keys)) => file.global = parse_table("keys", keys)?,
+                ("rom", Value::Table(roms)) => {
+                    for (hash, value) in roms {
+                        let keys = match value {
+                            Value::Table(keys) => keys,
+                            _ => return Err(format!("rom.{hash} should be a table")),
+                        };
+                        let table = parse_table(&format!("rom.{hash}"), keys)?;
+                        file.roms.push((hash.to_ascii_lowercase(), table));
+                    }
+                }
+                _ => return Err(format!("unexpected entry '{name}'")),
+            }
+        }
+        return Ok(file);
+    }
+
+    pub fn load(path: &str) -> Result<BindingsFile, String> {
+        let text =
+            fs::read_to_string(path).map_err(|error| format!("error reading {path}: {error}"))?;
+        return BindingsFile::parse(&text).map_err(|error| format!("{path}: {error}"));
+    }
+
+    /// The bindings for the ROM with the given `hash::rom_hash`.
+    pub fn for_rom(&self, rom_hash: &str) -> KeyBindings {
+        let mut bindings = KeyBindings::default();
+        let rom = self
+            .roms
+            .iter()
+            .filter(|(hash, _)| hash.eq_ignore_ascii_case(rom_hash))
+            .flat_map(|(_, table)| table);
+        for (key, host_keys) in self.global.iter().chain(rom) {
+            bindings.rebind(*key, host_keys);
+        }
+        return bindings;
+    }
+}
+
+fn parse_table(name: &str, table: &Table) -> Result<BindingTable, String> {
+    let mut bindings = Vec::new();
+    for (key, value) in table {
+        let index = match u8::from_str_radix(key, 16) {
+            Ok(index) if index < 16 && key.len() == 1 => index,
+            _ => return Err(format!("{name}: '{key}' is not a CHIP-8 key 0-F")),
+        };
+        let host_keys = match value {
+            Value::String(host_key) => vec![host_key.clone()],
+            Value::Array(host_keys) => host_keys
+                .iter()
+                .map(|host_key| host_key.as_str().map(str::to_string))
+                .collect::<Option<Vec<String>>>()
+                .ok_or_else(|| format!("{name}.{key}: host keys should be strings"))?,
+            _ => {
+                return Err(format!(
+                    "{name}.{key}: expected a key name or a list of them"
+                ))
+            }
+        };
+        bindings.push((index, host_keys));
+    }
+    return Ok(bindings);
+}
+
+#[test]
+fn per_rom_bindings() {
+    let file = BindingsFile::parse(
+        r#"
+        [keys]
+        5 = ["W", "Up"]
+
+        [rom.ABCD]
+        4 = "Left"
+        6 = ["Right", "W"]
+        "#,
+    )
+    .unwrap();
+
+    let global = file.for_rom("1234");
+    assert_eq!(global.lookup("up"), vec![0x5]);
+    assert_eq!(global.lookup("w"), vec![0x5]);
+    assert_eq!(global.lookup("q"), vec![0x4]);
+    assert!(global.lookup("left").is_empty());
+
+    let rom = file.for_rom("abcd");
+    assert_eq!(rom.lookup("Left"), vec![0x4]);
+    assert_eq!(rom.lookup("W"), vec![0x6]);
+    assert_eq!(rom.get_host_keys(0x5), ["up"]);
+    assert!(rom.lookup("Q").is_empty());
+
+    assert!(BindingsFile::parse("[keys]\n10 = \"A\"").is_err());
+}
+
+#[test]
+fn inputs_share_keys() {
+    let mut inputs = HostInputs::new(KeyBindings::default());
+    assert_eq!(inputs.press("W"), vec![0x5]);
+    assert!(inputs.press("DPadUp").is_empty());
+    assert!(inputs.release("w").is_empty());
+    assert_eq!(inputs.release("DPadUp"), vec![0x5]);
+    assert!(inputs.release("DPadUp").is_empty());
+    assert!(inputs.press("Select").is_empty());
+}
+
+#[test]
+fn held_keys_survive_loads() {
+    use crate::chip8::Chip8;
+    use crate::quirks::Quirks;
+
+    let mut chip8 = Chip8::new(Quirks::default());
+    chip8.set_key(0x4, true);
+    let state = chip8.save_state();
+    chip8.set_key(0x4, false);
+
+    let mut inputs = HostInputs::new(KeyBindings::default());
+    for key in inputs.press("W") {
+        chip8.set_key(key, true);
+    }
+    chip8.load_state(&state).unwrap();
+    for (index, pressed) in inputs.get_held_keys().into_iter().enumerate() {
+        chip8.set_key(index as u8, pressed);
+    }
+    let mut expected = [false; 16];
+    expected[0x5] = true;
+    assert_eq!(chip8.get_keys(), expected);
+}
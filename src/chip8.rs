@@ -1,7 +1,25 @@
-type DisplayBuffer = [[bool; 64]; 32];
+use crate::config::Config;
+use crate::coverage::Coverage;
+use crate::display::DisplayBuffer;
+use crate::error::{Chip8Error, StateError};
+use crate::profile::Profiler;
+use crate::quirks::Quirks;
+use crate::random::{RandomSource, SeededRandom};
+use crate::rewind::RewindBuffer;
+use crate::state::{self, StateReader, StateWriter};
+use crate::trace::{TraceRecord, Tracer};
+
+const MEMORY_SIZE: usize = 0x1000;
+pub const EXTENDED_MEMORY_SIZE: usize = 0x10000;
+pub const PROGRAM_START: usize = 0x200;
+pub const STACK_DEPTH: usize = 16;
+const BIG_FONT_START: usize = 0x50;
+/// Bumped whenever the save state layout changes. Version 1 had no RNG state
+/// and version 2 neither the seed nor which generator the state belongs to.
+const STATE_VERSION: u8 = 4;
 
 pub struct Chip8 {
-    memory: [u8; 4096],
+    memory: Vec<u8>,
     program_counter: u16,
     display_buffer: DisplayBuffer,
     stack: Vec<u16>,
@@ -11,6 +29,25 @@ pub struct Chip8 {
 
     delay_timer: u8,
     sound_timer: u8,
+    // Whether the sound timer was running at the last tick, so a timer set
+    // to 1 during a frame is still heard for that frame.
+    sounding: bool,
+    waiting_for_vblank: bool,
+    key_wait: Option<KeyWait>,
+    exited: bool,
+    memory_accesses: Vec<MemoryAccess>,
+    rpl_flags: [u8; 16],
+    audio_pattern: [u8; 16],
+    pitch: u8,
+    random: Box<dyn RandomSource>,
+    seed: u64,
+    cycles: u64,
+    tracer: Option<Tracer>,
+    profiler: Option<Profiler>,
+    coverage: Option<Coverage>,
+
+    quirks: Quirks,
+    instructions_per_frame: usize,
 }
 
 impl Chip8 {
@@ -32,24 +69,70 @@ impl Chip8 {
         0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
         0xF0, 0x80, 0xF0, 0x80, 0x80, // F
     ];
-    pub fn new() -> Chip8 {
-        let mut memory = [0; 4096];
+    const BIG_FONT_SET: [u8; 100] = [
+        0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+        0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+        0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+        0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+        0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+        0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+        0x3E, 0x7C, 0xE0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+        0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+        0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+        0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+    ];
+    pub fn new(quirks: Quirks) -> Chip8 {
+        return Config::new().quirks(quirks).build();
+    }
+
+    pub fn with_config(config: Config) -> Chip8 {
+        let quirks = config.quirks;
+        let mut memory = vec![
+            0;
+            if quirks.extended_memory {
+                EXTENDED_MEMORY_SIZE
+            } else {
+                MEMORY_SIZE
+            }
+        ];
+
+        let seed = config.seed.unwrap_or_else(rand::random);
 
         for (index, byte) in Chip8::FONT_SET.iter().enumerate() {
             memory[index] = *byte;
         }
+        for (index, byte) in Chip8::BIG_FONT_SET.iter().enumerate() {
+            memory[BIG_FONT_START + index] = *byte;
+        }
 
         return Chip8 {
             memory,
-            program_counter: 512, // 0x0200
-            display_buffer: [[false; 64]; 32],
-            stack: Vec::with_capacity(16),
+            program_counter: PROGRAM_START as Address,
+            display_buffer: DisplayBuffer::new(),
+            stack: Vec::with_capacity(STACK_DEPTH),
             registers: [0; 16],
             index_register: 0,
             keys: [false; 16],
 
             delay_timer: 0,
             sound_timer: 0,
+            sounding: false,
+            waiting_for_vblank: false,
+            key_wait: None,
+            exited: false,
+            memory_accesses: Vec::new(),
+            rpl_flags: [0; 16],
+            audio_pattern: [0; 16],
+            pitch: 64,
+            random: Box::new(SeededRandom::new(seed)),
+            seed,
+            cycles: 0,
+            tracer: None,
+            profiler: None,
+            coverage: None,
+
+            quirks,
+            instructions_per_frame: config.instructions_per_frame,
         };
     }
 
@@ -57,22 +140,443 @@ impl Chip8 {
         self.keys[index as usize] = pressed;
     }
 
-    pub fn get_display_buffer(&self) -> DisplayBuffer {
-        return self.display_buffer;
+    pub fn get_display_buffer(&self) -> &DisplayBuffer {
+        return &self.display_buffer;
+    }
+
+    /// The XO-CHIP 1-bit audio pattern, played back at `get_pitch()`.
+    pub fn get_audio_pattern(&self) -> [u8; 16] {
+        return self.audio_pattern;
+    }
+
+    /// The XO-CHIP pitch register; the pattern plays at
+    /// 4000 * 2^((pitch - 64) / 48) bits per second.
+    pub fn get_pitch(&self) -> u8 {
+        return self.pitch;
+    }
+
+    pub fn get_memory(&self) -> &[u8] {
+        return &self.memory;
+    }
+
+    pub fn get_registers(&self) -> [u8; 16] {
+        return self.registers;
+    }
+
+    pub fn get_index_register(&self) -> Address {
+        return self.index_register;
+    }
+
+    pub fn get_program_counter(&self) -> Address {
+        return self.program_counter;
+    }
+
+    /// Return addresses, innermost call last.
+    pub fn get_stack(&self) -> &[Address] {
+        return &self.stack;
+    }
+
+    pub fn get_delay_timer(&self) -> u8 {
+        return self.delay_timer;
+    }
+
+    pub fn get_sound_timer(&self) -> u8 {
+        return self.sound_timer;
+    }
+
+    /// Whether the buzzer sounded over the frame that ended at the last
+    /// timer tick.
+    pub fn is_sounding(&self) -> bool {
+        return self.sounding;
+    }
+
+    pub fn get_keys(&self) -> [bool; 16] {
+        return self.keys;
+    }
+
+    pub fn get_rpl_flags(&self) -> [u8; 16] {
+        return self.rpl_flags;
+    }
+
+    // Setters for putting the machine in an exact state, mostly for tests
+    // that call into a ROM's subroutines.
+
+    pub fn set_register(&mut self, register: RegisterNumber, value: u8) {
+        self.registers[register as usize & 0xF] = value;
+    }
+
+    pub fn set_index_register(&mut self, address: Address) {
+        self.index_register = address;
+    }
+
+    pub fn set_program_counter(&mut self, address: Address) {
+        self.program_counter = address;
+    }
+
+    /// Copies `bytes` into memory from `address` on. Panics if they do not fit.
+    pub fn set_memory(&mut self, address: usize, bytes: &[u8]) {
+        self.memory[address..address + bytes.len()].copy_from_slice(bytes);
+    }
+
+    /// Replaces the return addresses, innermost call last. Panics past
+    /// `STACK_DEPTH` entries.
+    pub fn set_stack(&mut self, stack: &[Address]) {
+        assert!(
+            stack.len() <= STACK_DEPTH,
+            "the stack holds {STACK_DEPTH} entries"
+        );
+        self.stack = stack.to_vec();
+    }
+
+    pub fn set_delay_timer(&mut self, value: u8) {
+        self.delay_timer = value;
+    }
+
+    pub fn set_sound_timer(&mut self, value: u8) {
+        self.sound_timer = value;
+    }
+
+    pub fn set_rpl_flags(&mut self, flags: [u8; 16]) {
+        self.rpl_flags = flags;
+    }
+
+    pub fn set_display_buffer(&mut self, display_buffer: DisplayBuffer) {
+        self.display_buffer = display_buffer;
+    }
+
+    /// The seed the random number generator started from.
+    pub fn get_seed(&self) -> u64 {
+        return self.seed;
+    }
+
+    /// Replaces the generator behind CXNN, for example with a `ScriptedRandom`.
+    pub fn set_random_source(&mut self, random: Box<dyn RandomSource>) {
+        self.random = random;
+    }
+
+    /// Instructions executed since the machine was created. Loading a save
+    /// state leaves the count alone.
+    pub fn get_cycle_count(&self) -> u64 {
+        return self.cycles;
+    }
+
+    /// Starts writing a trace record for each instruction executed.
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    pub fn get_tracer(&self) -> Option<&Tracer> {
+        return self.tracer.as_ref();
+    }
+
+    /// Detaches the tracer, so it can be `finish`ed.
+    pub fn take_tracer(&mut self) -> Option<Tracer> {
+        return self.tracer.take();
+    }
+
+    /// Starts counting where cycles are spent, from inside whatever
+    /// subroutines the machine has already called.
+    pub fn set_profiler(&mut self, mut profiler: Profiler) {
+        let calls: Vec<(Address, Address)> = self
+            .stack
+            .iter()
+            .map(|call_site| {
+                let opcode = u16::from_be_bytes([
+                    self.get_byte_from_memory(*call_site as usize).unwrap_or(0),
+                    self.get_byte_from_memory(*call_site as usize + 1)
+                        .unwrap_or(0),
+                ]);
+                // A stack set up by hand may not point at a CALL.
+                return match Chip8::parse_instruction(opcode) {
+                    Some(Instruction::Call(target)) => (*call_site, target),
+                    _ => (*call_site, *call_site),
+                };
+            })
+            .collect();
+        profiler.enter_calls(&calls);
+        self.profiler = Some(profiler);
+    }
+
+    pub fn get_profiler(&self) -> Option<&Profiler> {
+        return self.profiler.as_ref();
+    }
+
+    pub fn take_profiler(&mut self) -> Option<Profiler> {
+        return self.profiler.take();
+    }
+
+    /// Starts recording which instructions run and which way skips go.
+    pub fn set_coverage(&mut self, coverage: Coverage) {
+        self.coverage = Some(coverage);
+    }
+
+    pub fn get_coverage(&self) -> Option<&Coverage> {
+        return self.coverage.as_ref();
+    }
+
+    pub fn take_coverage(&mut self) -> Option<Coverage> {
+        return self.coverage.take();
     }
 
-    pub fn load_rom(&mut self, rom: Vec<u8>) {
-        for (index, byte) in rom.iter().enumerate() {
-            self.memory[512 + index] = byte.clone();
+    pub fn get_quirks(&self) -> Quirks {
+        return self.quirks;
+    }
+
+    pub fn get_instructions_per_frame(&self) -> usize {
+        return self.instructions_per_frame;
+    }
+
+    pub fn set_instructions_per_frame(&mut self, instructions_per_frame: usize) {
+        self.instructions_per_frame = instructions_per_frame;
+    }
+
+    /// The data reads and writes made by the last executed instruction, not
+    /// counting the instruction fetch itself.
+    pub fn get_memory_accesses(&self) -> &[MemoryAccess] {
+        return &self.memory_accesses;
+    }
+
+    /// Whether the CPU is parked on an FX0A until a key is pressed and released.
+    pub fn is_waiting_for_key(&self) -> bool {
+        return self.key_wait.is_some();
+    }
+
+    /// Whether the CPU is stuck on a jump to itself, which is how most test
+    /// ROMs signal that they have finished.
+    pub fn is_jumping_to_self(&self) -> bool {
+        let address = self.program_counter as usize;
+        return match (
+            self.get_byte_from_memory(address),
+            self.get_byte_from_memory(address + 1),
+        ) {
+            (Some(high), Some(low)) => {
+                u16::from_be_bytes([high, low]) == 0x1000 | self.program_counter
+            }
+            _ => false,
+        };
+    }
+
+    /// Whether the ROM has stopped itself with 00FD.
+    pub fn has_exited(&self) -> bool {
+        return self.exited;
+    }
+
+    pub fn load_rom(&mut self, rom: Vec<u8>) -> Result<(), Chip8Error> {
+        let capacity = self.memory.len() - PROGRAM_START;
+        if rom.len() > capacity {
+            return Err(Chip8Error::RomTooLarge {
+                size: rom.len(),
+                capacity,
+            });
+        }
+        self.memory[PROGRAM_START..PROGRAM_START + rom.len()].copy_from_slice(&rom);
+        return Ok(());
+    }
+
+    /// Captures the whole machine, quirks included, in a compact binary
+    /// format that `load_state` can restore.
+    pub fn save_state(&self) -> Vec<u8> {
+        return self.write_state(StateWriter::new());
+    }
+
+    pub(crate) fn write_state(&self, mut writer: StateWriter) -> Vec<u8> {
+        writer.bytes(state::MAGIC);
+        writer.u8(STATE_VERSION);
+        writer.u16(self.quirks.to_bits());
+        writer.u32(self.instructions_per_frame as u32);
+        writer.packed(&self.memory);
+        writer.u16(self.program_counter);
+        writer.u16(self.index_register);
+        writer.bytes(&self.registers);
+        writer.u8(self.stack.len() as u8);
+        for address in self.stack.iter() {
+            writer.u16(*address);
+        }
+        let keys = self
+            .keys
+            .iter()
+            .enumerate()
+            .fold(0u16, |keys, (index, pressed)| {
+                keys | (*pressed as u16) << index
+            });
+        writer.u16(keys);
+        writer.u8(self.delay_timer);
+        writer.u8(self.sound_timer);
+        writer.bool(self.waiting_for_vblank);
+        match self.key_wait {
+            None => writer.u8(0),
+            Some(KeyWait::Press) => writer.u8(1),
+            Some(KeyWait::Release(key)) => {
+                writer.u8(2);
+                writer.u8(key);
+            }
+        }
+        writer.bool(self.exited);
+        writer.bytes(&self.rpl_flags);
+        writer.bytes(&self.audio_pattern);
+        writer.u8(self.pitch);
+        writer.u64(self.seed);
+        let kind = self.random.kind().as_bytes();
+        writer.u8(kind.len() as u8);
+        writer.bytes(kind);
+        writer.u64(self.random.get_state());
+        self.display_buffer.write_state(&mut writer);
+        return writer.finish();
+    }
+
+    /// Restores a state from `save_state`. Nothing changes if it is rejected.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let mut reader = StateReader::new(state);
+        if reader.bytes(state::MAGIC.len()) != Ok(state::MAGIC.as_slice()) {
+            return Err(StateError::NotASaveState);
+        }
+        let version = reader.u8()?;
+        if version == 0 || version > STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+
+        // The quirks took a byte until there were more than eight of them.
+        let quirk_bits = match version {
+            1..=3 => reader.u8()? as u16,
+            _ => reader.u16()?,
+        };
+        let quirks = Quirks::from_bits(quirk_bits).ok_or(StateError::Invalid("quirks"))?;
+        let instructions_per_frame = reader.u32()? as usize;
+        let memory = reader.packed(EXTENDED_MEMORY_SIZE)?;
+        let memory_size = if quirks.extended_memory {
+            EXTENDED_MEMORY_SIZE
+        } else {
+            MEMORY_SIZE
+        };
+        if memory.len() != memory_size {
+            return Err(StateError::Invalid("memory size"));
+        }
+        let program_counter = reader.u16()?;
+        let index_register = reader.u16()?;
+        let registers = reader.array()?;
+        let depth = reader.u8()? as usize;
+        if depth > STACK_DEPTH {
+            return Err(StateError::Invalid("stack depth"));
+        }
+        let mut stack = Vec::with_capacity(STACK_DEPTH);
+        for _ in 0..depth {
+            stack.push(reader.u16()?);
         }
+        let key_bits = reader.u16()?;
+        let mut keys = [false; 16];
+        for (index, pressed) in keys.iter_mut().enumerate() {
+            *pressed = key_bits & (1 << index) != 0;
+        }
+        let delay_timer = reader.u8()?;
+        let sound_timer = reader.u8()?;
+        let waiting_for_vblank = reader.bool()?;
+        let key_wait = match reader.u8()? {
+            0 => None,
+            1 => Some(KeyWait::Press),
+            2 => match reader.u8()? {
+                key if key < 16 => Some(KeyWait::Release(key)),
+                _ => return Err(StateError::Invalid("key")),
+            },
+            _ => return Err(StateError::Invalid("key wait")),
+        };
+        let exited = reader.bool()?;
+        let rpl_flags = reader.array()?;
+        let audio_pattern = reader.array()?;
+        let pitch = reader.u8()?;
+        let mut seed = self.seed;
+        let random_state = match version {
+            1 => None,
+            // Only the default generator could be saved and resumed then.
+            2 => Some((SeededRandom::KIND, reader.u64()?)),
+            _ => {
+                seed = reader.u64()?;
+                let length = reader.u8()? as usize;
+                let kind = std::str::from_utf8(reader.bytes(length)?)
+                    .map_err(|_| StateError::Invalid("random source"))?;
+                Some((kind, reader.u64()?))
+            }
+        };
+        let display_buffer = DisplayBuffer::read_state(&mut reader)?;
+        if !reader.is_at_end() {
+            return Err(StateError::Invalid("length"));
+        }
+
+        let mut random = std::mem::replace(&mut self.random, Box::new(SeededRandom::new(0)));
+        let tracer = self.tracer.take();
+        let profiler = self.profiler.take();
+        let coverage = self.coverage.take();
+        // Another kind of generator could not make sense of the state, so it
+        // carries on from where it is.
+        if let Some((kind, state)) = random_state {
+            if kind == random.kind() {
+                random.set_state(state);
+            }
+        }
+        *self = Chip8 {
+            memory,
+            program_counter,
+            display_buffer,
+            stack,
+            registers,
+            index_register,
+            keys,
+
+            delay_timer,
+            sound_timer,
+            sounding: self.sounding,
+            waiting_for_vblank,
+            key_wait,
+            exited,
+            memory_accesses: Vec::new(),
+            rpl_flags,
+            audio_pattern,
+            pitch,
+            random,
+            seed,
+            cycles: self.cycles,
+            tracer,
+            profiler,
+            coverage,
+
+            quirks,
+            instructions_per_frame,
+        };
+        return Ok(());
+    }
+
+    /// Goes back `frames` frames in `buffer`, which then forgets the frames
+    /// that came after. Returns whether there was anything to go back to.
+    /// The keys stay as they are, since they are whatever the player is
+    /// holding now rather than what they held back then.
+    pub fn rewind(&mut self, buffer: &mut RewindBuffer, frames: usize) -> Result<bool, StateError> {
+        return match buffer.rewind(frames)? {
+            Some(snapshot) => {
+                let keys = self.keys;
+                self.load_state(&snapshot)?;
+                self.keys = keys;
+                Ok(true)
+            }
+            None => Ok(false),
+        };
     }
 
-    fn get_byte_from_memory(&self, address: u16) -> u8 {
-        return self.memory[address as usize];
+    fn get_byte_from_memory(&self, address: usize) -> Option<u8> {
+        return self.memory.get(address).copied();
     }
 
-    fn set_byte_in_memory(&mut self, address: u16, value: u8) {
-        self.memory[address as usize] = value;
+    fn set_byte_in_memory(&mut self, address: usize, value: u8) -> Option<()> {
+        let byte = self.memory.get_mut(address)?;
+        *byte = value;
+        self.memory_accesses.push(MemoryAccess::Write(address));
+        return Some(());
+    }
+
+    /// Like `get_byte_from_memory`, but recorded as a data read of the
+    /// current instruction.
+    fn read_byte_from_memory(&mut self, address: usize) -> Option<u8> {
+        let value = self.get_byte_from_memory(address)?;
+        self.memory_accesses.push(MemoryAccess::Read(address));
+        return Some(value);
     }
 
     fn get_register_value(&self, index: u8) -> u8 {
@@ -84,265 +588,553 @@ impl Chip8 {
     }
 
     fn get_key_pressed(&self, index: u8) -> bool {
-        return self.keys[index as usize];
+        return self.keys[(index & 0xF) as usize];
+    }
+
+    /// The size of the instruction following the current one, which is four
+    /// bytes for the XO-CHIP F000 NNNN long load and two for everything else.
+    fn next_instruction_length(&self) -> u16 {
+        let next = self.program_counter as usize + 2;
+        let is_long_load = self.get_byte_from_memory(next) == Some(0xF0)
+            && self.get_byte_from_memory(next + 1) == Some(0x00);
+        return if is_long_load { 4 } else { 2 };
     }
 
-    pub fn execute_cycle<F>(&mut self, wait_for_input: F)
-    where
-        F: FnOnce() -> u8,
-    {
+    /// The registers X to Y inclusive, in descending order when Y < X.
+    fn register_range(x: RegisterNumber, y: RegisterNumber) -> Vec<RegisterNumber> {
+        if x <= y {
+            return (x..=y).collect();
+        }
+        return (y..=x).rev().collect();
+    }
+
+    /// Runs one 60 Hz frame: the configured number of instructions followed
+    /// by a timer tick.
+    pub fn run_frame(&mut self) -> Result<(), Chip8Error> {
+        for _ in 0..self.instructions_per_frame {
+            self.execute_cycle()?;
+        }
+        self.tick_timers();
+        return Ok(());
+    }
+
+    /// Calls the subroutine at `address` as if a CALL sat at the program
+    /// counter, and runs until it returns, leaving the program counter just
+    /// past that imaginary CALL. Fails with `CycleLimit` after `limit`
+    /// instructions, so a subroutine that never returns cannot hang a test.
+    /// Fails with `Exited` if the ROM exits with 00FD instead of returning.
+    /// Timers only tick when the display wait quirk holds up a draw.
+    /// Returns how many instructions ran.
+    pub fn call(&mut self, address: Address, limit: usize) -> Result<usize, Chip8Error> {
+        if self.stack.len() == STACK_DEPTH {
+            return Err(Chip8Error::StackOverflow {
+                program_counter: self.program_counter,
+                opcode: Instruction::Call(address).encode(),
+            });
+        }
+        let depth = self.stack.len();
+        self.stack.push(self.program_counter);
+        self.program_counter = address;
+        let mut cycles = 0;
+        while self.stack.len() > depth {
+            if self.exited {
+                return Err(Chip8Error::Exited {
+                    program_counter: self.program_counter,
+                });
+            }
+            if cycles == limit {
+                return Err(Chip8Error::CycleLimit {
+                    program_counter: self.program_counter,
+                    limit,
+                });
+            }
+            if self.waiting_for_vblank {
+                self.tick_timers();
+            }
+            self.execute_cycle()?;
+            cycles += 1;
+        }
+        return Ok(cycles);
+    }
+
+    /// Decrements the delay and sound timers. Must be called at 60 Hz,
+    /// independently of how many instructions are executed per second.
+    pub fn tick_timers(&mut self) {
+        if self.delay_timer > 0 {
+            self.delay_timer -= 1;
+        }
+        self.sounding = self.sound_timer > 0;
+        if self.sound_timer > 0 {
+            self.sound_timer -= 1;
+        }
+        self.waiting_for_vblank = false;
+    }
+
+    pub fn execute_cycle(&mut self) -> Result<(), Chip8Error> {
+        if self.tracer.is_none() && self.profiler.is_none() && self.coverage.is_none() {
+            return self.execute_instruction();
+        }
+        let record = self.trace_record();
+        let result = self.execute_instruction();
+        if let (Some(record), Some(profiler)) = (&record, self.profiler.as_mut()) {
+            profiler.record(record.program_counter, record.instruction, self.stack.len());
+        }
+        if let (Some(record), Some(coverage)) = (&record, self.coverage.as_mut()) {
+            coverage.record(
+                record.program_counter,
+                record.instruction,
+                self.program_counter,
+            );
+        }
+        if let (Some(mut record), Some(tracer)) = (record, self.tracer.as_mut()) {
+            for access in &self.memory_accesses {
+                if let MemoryAccess::Write(address) = access {
+                    record
+                        .memory_writes
+                        .push((*address as Address, self.memory[*address]));
+                }
+            }
+            tracer.trace(&record);
+        }
+        return result;
+    }
+
+    /// The machine as the next instruction is about to execute, or `None`
+    /// when no instruction will.
+    fn trace_record(&self) -> Option<TraceRecord> {
+        if self.waiting_for_vblank || self.exited {
+            return None;
+        }
+        let program_counter = self.program_counter;
         let opcode = u16::from_be_bytes([
-            self.get_byte_from_memory(self.program_counter),
-            self.get_byte_from_memory(self.program_counter + 1),
+            self.get_byte_from_memory(program_counter as usize)?,
+            self.get_byte_from_memory(program_counter as usize + 1)?,
         ]);
+        return Some(TraceRecord {
+            cycle: self.cycles,
+            program_counter,
+            opcode,
+            instruction: Chip8::parse_instruction(opcode)?,
+            registers: self.registers,
+            index_register: self.index_register,
+            stack_pointer: self.stack.len(),
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            memory_writes: Vec::new(),
+        });
+    }
 
-        match Chip8::parse_instruction(opcode) {
+    /// How many screen pixels a scroll by `pixels` hires pixels moves.
+    fn scroll_distance(&self, pixels: usize) -> usize {
+        if self.display_buffer.is_hires() || !self.quirks.lores_scrolls_hires_pixels {
+            return pixels;
+        }
+        return pixels / 2;
+    }
+
+    fn execute_instruction(&mut self) -> Result<(), Chip8Error> {
+        self.memory_accesses.clear();
+        if self.waiting_for_vblank || self.exited {
+            return Ok(());
+        }
+
+        let program_counter = self.program_counter;
+        let opcode = match (
+            self.get_byte_from_memory(program_counter as usize),
+            self.get_byte_from_memory(program_counter as usize + 1),
+        ) {
+            (Some(high), Some(low)) => u16::from_be_bytes([high, low]),
+            _ => return Err(Chip8Error::ProgramCounterOutOfBounds { program_counter }),
+        };
+        let out_of_bounds = |address: usize| Chip8Error::MemoryOutOfBounds {
+            program_counter,
+            opcode,
+            address,
+        };
+
+        let instruction = match Chip8::parse_instruction(opcode) {
+            Some(instruction) => instruction,
+            None => {
+                return Err(Chip8Error::UnknownOpcode {
+                    program_counter,
+                    opcode,
+                })
+            }
+        };
+
+        self.cycles += 1;
+
+        match instruction {
             Instruction::ClearDisplay => {
-                self.display_buffer = [[false; 64]; 32];
-                self.program_counter += 2;
+                self.display_buffer.clear();
+                self.program_counter = self.program_counter.wrapping_add(2);
+            }
+            Instruction::ScrollUp(rows) => {
+                self.display_buffer
+                    .scroll_up(self.scroll_distance(rows as usize));
+                self.program_counter = self.program_counter.wrapping_add(2);
+            }
+            Instruction::ScrollDown(rows) => {
+                self.display_buffer
+                    .scroll_down(self.scroll_distance(rows as usize));
+                self.program_counter = self.program_counter.wrapping_add(2);
+            }
+            Instruction::ScrollRight => {
+                self.display_buffer.scroll_right(self.scroll_distance(4));
+                self.program_counter = self.program_counter.wrapping_add(2);
+            }
+            Instruction::ScrollLeft => {
+                self.display_buffer.scroll_left(self.scroll_distance(4));
+                self.program_counter = self.program_counter.wrapping_add(2);
+            }
+            Instruction::Exit => {
+                self.exited = true;
+            }
+            Instruction::LowRes => {
+                self.display_buffer.set_hires(false);
+                self.program_counter = self.program_counter.wrapping_add(2);
+            }
+            Instruction::HighRes => {
+                self.display_buffer.set_hires(true);
+                self.program_counter = self.program_counter.wrapping_add(2);
             }
             Instruction::Return => {
-                let address = self.stack.pop().unwrap();
+                let address = self.stack.pop().ok_or(Chip8Error::StackUnderflow {
+                    program_counter,
+                    opcode,
+                })?;
                 self.program_counter = address;
-                self.program_counter += 2;
+                self.program_counter = self.program_counter.wrapping_add(2);
             }
             Instruction::Jump(address) => {
                 self.program_counter = address;
             }
             Instruction::Call(address) => {
-                self.stack.push(self.program_counter.clone());
+                if self.stack.len() == STACK_DEPTH {
+                    return Err(Chip8Error::StackOverflow {
+                        program_counter,
+                        opcode,
+                    });
+                }
+                self.stack.push(self.program_counter);
                 self.program_counter = address;
             }
             Instruction::SkipEqualK(register, value) => {
                 if self.get_register_value(register) == value {
-                    self.program_counter += 2;
+                    self.program_counter = self
+                        .program_counter
+                        .wrapping_add(self.next_instruction_length());
                 }
-                self.program_counter += 2;
+                self.program_counter = self.program_counter.wrapping_add(2);
             }
             Instruction::SkipNotEqualK(register, value) => {
                 if self.get_register_value(register) != value {
-                    self.program_counter += 2;
+                    self.program_counter = self
+                        .program_counter
+                        .wrapping_add(self.next_instruction_length());
                 }
-                self.program_counter += 2;
+                self.program_counter = self.program_counter.wrapping_add(2);
+            }
+            Instruction::SaveRange(x, y) => {
+                for (offset, register) in Chip8::register_range(x, y).into_iter().enumerate() {
+                    let address = self.index_register as usize + offset;
+                    self.set_byte_in_memory(address, self.get_register_value(register))
+                        .ok_or_else(|| out_of_bounds(address))?;
+                }
+                self.program_counter = self.program_counter.wrapping_add(2);
+            }
+            Instruction::LoadRange(x, y) => {
+                for (offset, register) in Chip8::register_range(x, y).into_iter().enumerate() {
+                    let address = self.index_register as usize + offset;
+                    let value = self
+                        .read_byte_from_memory(address)
+                        .ok_or_else(|| out_of_bounds(address))?;
+                    self.set_register_value(register, value);
+                }
+                self.program_counter = self.program_counter.wrapping_add(2);
             }
             Instruction::SkipEqual(x, y) => {
                 if self.get_register_value(x) == self.get_register_value(y) {
-                    self.program_counter += 2;
+                    self.program_counter = self
+                        .program_counter
+                        .wrapping_add(self.next_instruction_length());
                 }
-                self.program_counter += 2;
+                self.program_counter = self.program_counter.wrapping_add(2);
             }
             Instruction::SetK(register, value) => {
                 self.set_register_value(register, value);
-                self.program_counter += 2;
+                self.program_counter = self.program_counter.wrapping_add(2);
             }
             Instruction::AddK(register, value) => {
                 self.set_register_value(
                     register,
                     self.get_register_value(register).wrapping_add(value),
                 );
-                self.program_counter += 2;
+                self.program_counter = self.program_counter.wrapping_add(2);
             }
             Instruction::Set(x, y) => {
                 self.set_register_value(x, self.get_register_value(y));
-                self.program_counter += 2;
+                self.program_counter = self.program_counter.wrapping_add(2);
             }
             Instruction::Or(x, y) => {
                 self.set_register_value(x, self.get_register_value(x) | self.get_register_value(y));
-                self.program_counter += 2;
+                if self.quirks.vf_reset {
+                    self.set_register_value(0xF, 0);
+                }
+                self.program_counter = self.program_counter.wrapping_add(2);
             }
             Instruction::And(x, y) => {
                 self.set_register_value(x, self.get_register_value(x) & self.get_register_value(y));
-                self.program_counter += 2;
+                if self.quirks.vf_reset {
+                    self.set_register_value(0xF, 0);
+                }
+                self.program_counter = self.program_counter.wrapping_add(2);
             }
             Instruction::XOr(x, y) => {
                 self.set_register_value(x, self.get_register_value(x) ^ self.get_register_value(y));
-                self.program_counter += 2;
+                if self.quirks.vf_reset {
+                    self.set_register_value(0xF, 0);
+                }
+                self.program_counter = self.program_counter.wrapping_add(2);
             }
+            // The flag is written after the result, so with X=F it wins.
             Instruction::Add(x, y) => {
-                let is_carry = self
+                let (result, is_carry) = self
                     .get_register_value(x)
-                    .checked_add(self.get_register_value(y))
-                    == None;
-
-                self.set_register_value(
-                    x,
-                    self.get_register_value(x)
-                        .wrapping_add(self.get_register_value(y)),
-                );
-                if is_carry {
-                    self.set_register_value(0xF, 1);
-                }
-                self.program_counter += 2;
+                    .overflowing_add(self.get_register_value(y));
+                self.set_register_value(x, result);
+                self.set_register_value(0xF, is_carry as u8);
+                self.program_counter = self.program_counter.wrapping_add(2);
             }
             Instruction::Sub(x, y) => {
-                self.set_register_value(
-                    0xF,
-                    if self.get_register_value(x) < self.get_register_value(y) {
-                        0
-                    } else {
-                        1
-                    },
-                );
-                self.set_register_value(
-                    x,
-                    self.get_register_value(x)
-                        .wrapping_sub(self.get_register_value(y)),
-                );
-                self.program_counter += 2;
+                let (result, is_borrow) = self
+                    .get_register_value(x)
+                    .overflowing_sub(self.get_register_value(y));
+                self.set_register_value(x, result);
+                self.set_register_value(0xF, !is_borrow as u8);
+                self.program_counter = self.program_counter.wrapping_add(2);
             }
-            Instruction::ShiftRight(x) => {
-                self.set_register_value(0xF, self.get_register_value(x) % 2);
-                self.set_register_value(x, self.get_register_value(x) >> 1);
-                self.program_counter += 2;
+            Instruction::ShiftRight(x, y) => {
+                let value = self.get_register_value(if self.quirks.shift_uses_vy { y } else { x });
+                self.set_register_value(x, value >> 1);
+                self.set_register_value(0xF, value & 0x01);
+                self.program_counter = self.program_counter.wrapping_add(2);
             }
             Instruction::SubInv(x, y) => {
-                self.set_register_value(
-                    16,
-                    if self.get_register_value(y) < self.get_register_value(x) {
-                        0
-                    } else {
-                        1
-                    },
-                );
-                self.set_register_value(
-                    x,
-                    self.get_register_value(y)
-                        .wrapping_sub(self.get_register_value(x)),
-                );
-                self.program_counter += 2;
+                let (result, is_borrow) = self
+                    .get_register_value(y)
+                    .overflowing_sub(self.get_register_value(x));
+                self.set_register_value(x, result);
+                self.set_register_value(0xF, !is_borrow as u8);
+                self.program_counter = self.program_counter.wrapping_add(2);
             }
-            Instruction::ShiftLeft(x) => {
-                self.set_register_value(
-                    0xF,
-                    if self.get_register_value(x) > (u8::MAX / 2) {
-                        1
-                    } else {
-                        0
-                    },
-                );
-                self.set_register_value(x, self.get_register_value(x) << 1);
-                self.program_counter += 2;
+            Instruction::ShiftLeft(x, y) => {
+                let value = self.get_register_value(if self.quirks.shift_uses_vy { y } else { x });
+                self.set_register_value(x, value << 1);
+                self.set_register_value(0xF, value >> 7);
+                self.program_counter = self.program_counter.wrapping_add(2);
             }
             Instruction::SkipNotEqual(x, y) => {
                 if self.get_register_value(x) != self.get_register_value(y) {
-                    self.program_counter += 2;
+                    self.program_counter = self
+                        .program_counter
+                        .wrapping_add(self.next_instruction_length());
                 }
-                self.program_counter += 2;
+                self.program_counter = self.program_counter.wrapping_add(2);
             }
             Instruction::LoadI(address) => {
                 self.index_register = address;
-                self.program_counter += 2;
+                self.program_counter = self.program_counter.wrapping_add(2);
             }
-            Instruction::LongJump(_address) => {
-                panic!("GotoB has not been implemented")
+            Instruction::LoadLongI => {
+                let address = program_counter as usize + 2;
+                let (high, low) = match (
+                    self.get_byte_from_memory(address),
+                    self.get_byte_from_memory(address + 1),
+                ) {
+                    (Some(high), Some(low)) => (high, low),
+                    _ => return Err(out_of_bounds(address)),
+                };
+                self.index_register = u16::from_be_bytes([high, low]);
+                self.program_counter = self.program_counter.wrapping_add(4);
+            }
+            Instruction::LongJump(address) => {
+                // BXNN on CHIP-48/SUPER-CHIP: the high nibble of the address doubles
+                // as the register holding the offset.
+                let register = if self.quirks.jump_with_vx {
+                    ((address & 0x0F00) >> 8) as u8
+                } else {
+                    0
+                };
+                self.program_counter = address + self.get_register_value(register) as u16;
             }
             Instruction::Rand(x, value) => {
-                let random_number: u8 = rand::random();
+                let random_number = self.random.next_byte();
                 self.set_register_value(x, value & random_number);
-                self.program_counter += 2;
+                self.program_counter = self.program_counter.wrapping_add(2);
             }
             Instruction::Draw(x, y, height) => {
-                let x = self.get_register_value(x);
-                let y = self.get_register_value(y);
-                self.set_register_value(0xF, 0);
-                for i in 0..height {
-                    let pixel = self.get_byte_from_memory(self.index_register + (i as u16));
-                    for j in 0..8 {
-                        if (y + i) < 32 {
-                            if (pixel & (0x80 >> j)) != 0 {
-                                if self.display_buffer[((y + i) as usize)][(x + j) as usize] {
-                                    self.set_register_value(0xF, 1);
-                                }
-                                self.display_buffer[(y + i) as usize][(x + j) as usize] ^= true;
-                            }
-                        }
-                    }
-                }
-                self.program_counter += 2;
+                // DXY0 draws a 16x16 SUPER-CHIP sprite, two bytes per row.
+                let (wide, length) = if height == 0 {
+                    (true, 32)
+                } else {
+                    (false, height as usize)
+                };
+                let length = length * self.display_buffer.selected_plane_count();
+                let start = self.index_register as usize;
+                let sprite = self
+                    .memory
+                    .get(start..start + length)
+                    .ok_or_else(|| out_of_bounds(start.max(self.memory.len())))?;
+                let collision = self.display_buffer.draw_sprite(
+                    self.get_register_value(x) as usize,
+                    self.get_register_value(y) as usize,
+                    sprite,
+                    wide,
+                    self.quirks.clip_sprites,
+                );
+                self.memory_accesses
+                    .extend((start..start + length).map(MemoryAccess::Read));
+                self.set_register_value(0xF, collision as u8);
+                self.waiting_for_vblank = self.quirks.display_wait;
+                self.program_counter = self.program_counter.wrapping_add(2);
             }
             Instruction::SkipPressed(x) => {
                 if self.get_key_pressed(self.get_register_value(x)) {
-                    self.program_counter += 2;
+                    self.program_counter = self
+                        .program_counter
+                        .wrapping_add(self.next_instruction_length());
                 }
-                self.program_counter += 2;
+                self.program_counter = self.program_counter.wrapping_add(2);
             }
             Instruction::SkipNotPressed(x) => {
                 if !self.get_key_pressed(self.get_register_value(x)) {
-                    self.program_counter += 2;
+                    self.program_counter = self
+                        .program_counter
+                        .wrapping_add(self.next_instruction_length());
                 }
-                self.program_counter += 2;
+                self.program_counter = self.program_counter.wrapping_add(2);
+            }
+            Instruction::SelectPlanes(planes) => {
+                self.display_buffer.select_planes(planes);
+                self.program_counter = self.program_counter.wrapping_add(2);
+            }
+            Instruction::LoadAudioPattern => {
+                let start = self.index_register as usize;
+                let pattern = self
+                    .memory
+                    .get(start..start + self.audio_pattern.len())
+                    .ok_or_else(|| out_of_bounds(start.max(self.memory.len())))?;
+                self.audio_pattern.copy_from_slice(pattern);
+                self.memory_accesses
+                    .extend((start..start + pattern.len()).map(MemoryAccess::Read));
+                self.program_counter = self.program_counter.wrapping_add(2);
             }
             Instruction::GetTimer(x) => {
                 self.set_register_value(x, self.delay_timer);
-                self.program_counter += 2;
+                self.program_counter = self.program_counter.wrapping_add(2);
             }
             Instruction::WaitKey(x) => {
-                let index = wait_for_input();
-                self.set_register_value(x, index);
-                self.program_counter += 2;
+                // The program counter stays on FX0A, so this re-runs every cycle
+                // until a key has gone down and come back up again.
+                self.key_wait = match self.key_wait.unwrap_or(KeyWait::Press) {
+                    KeyWait::Press => match self.keys.iter().position(|pressed| *pressed) {
+                        Some(key) => Some(KeyWait::Release(key as u8)),
+                        None => Some(KeyWait::Press),
+                    },
+                    KeyWait::Release(key) if self.get_key_pressed(key) => {
+                        Some(KeyWait::Release(key))
+                    }
+                    KeyWait::Release(key) => {
+                        self.set_register_value(x, key);
+                        self.program_counter = self.program_counter.wrapping_add(2);
+                        None
+                    }
+                };
             }
             Instruction::SetTimer(x) => {
                 self.delay_timer = self.get_register_value(x);
-                self.program_counter += 2;
+                self.program_counter = self.program_counter.wrapping_add(2);
             }
             Instruction::SetSoundTimer(x) => {
                 self.sound_timer = self.get_register_value(x);
-                self.program_counter += 2;
+                self.program_counter = self.program_counter.wrapping_add(2);
             }
             Instruction::AddToI(x) => {
-                self.index_register += self.get_register_value(x) as u16;
-                self.program_counter += 2;
+                self.index_register = self
+                    .index_register
+                    .wrapping_add(self.get_register_value(x) as u16);
+                self.program_counter = self.program_counter.wrapping_add(2);
             }
             Instruction::LoadHexGlyph(x) => {
                 self.index_register = self.get_register_value(x) as u16 * 5;
-                self.program_counter += 2;
+                self.program_counter = self.program_counter.wrapping_add(2);
+            }
+            Instruction::LoadBigHexGlyph(x) => {
+                self.index_register =
+                    (BIG_FONT_START + self.get_register_value(x) as usize * 10) as Address;
+                self.program_counter = self.program_counter.wrapping_add(2);
             }
             Instruction::StoreBCD(x) => {
-                self.set_byte_in_memory(self.index_register, self.get_register_value(x) / 100);
-                self.set_byte_in_memory(
-                    self.index_register + 1,
-                    (self.get_register_value(x) / 10) % 10,
-                );
-                self.set_byte_in_memory(self.index_register + 2, self.get_register_value(x) % 10);
-                self.program_counter += 2;
+                let value = self.get_register_value(x);
+                let digits = [value / 100, (value / 10) % 10, value % 10];
+                for (i, digit) in digits.into_iter().enumerate() {
+                    let address = self.index_register as usize + i;
+                    self.set_byte_in_memory(address, digit)
+                        .ok_or_else(|| out_of_bounds(address))?;
+                }
+                self.program_counter = self.program_counter.wrapping_add(2);
             }
             Instruction::StoreRegisters(x) => {
                 for i in 0..(x + 1) {
-                    self.set_byte_in_memory(
-                        self.index_register + (i as u16),
-                        self.get_register_value(i),
-                    )
+                    let address = self.index_register as usize + i as usize;
+                    self.set_byte_in_memory(address, self.get_register_value(i))
+                        .ok_or_else(|| out_of_bounds(address))?;
                 }
-                self.program_counter += 2;
+                self.index_register = self
+                    .index_register
+                    .wrapping_add(self.quirks.load_store_increment(x));
+                self.program_counter = self.program_counter.wrapping_add(2);
             }
             Instruction::LoadRegisters(x) => {
                 for i in 0..(x + 1) {
-                    self.set_register_value(
-                        i,
-                        self.get_byte_from_memory(self.index_register + (i as u16)),
-                    );
+                    let address = self.index_register as usize + i as usize;
+                    let value = self
+                        .read_byte_from_memory(address)
+                        .ok_or_else(|| out_of_bounds(address))?;
+                    self.set_register_value(i, value);
                 }
-                self.program_counter += 2;
+                self.index_register = self
+                    .index_register
+                    .wrapping_add(self.quirks.load_store_increment(x));
+                self.program_counter = self.program_counter.wrapping_add(2);
+            }
+            Instruction::SetPitch(x) => {
+                self.pitch = self.get_register_value(x);
+                self.program_counter = self.program_counter.wrapping_add(2);
+            }
+            Instruction::StoreFlags(x) => {
+                let count = x as usize + 1;
+                self.rpl_flags[..count].copy_from_slice(&self.registers[..count]);
+                self.program_counter = self.program_counter.wrapping_add(2);
+            }
+            Instruction::LoadFlags(x) => {
+                let count = x as usize + 1;
+                self.registers[..count].copy_from_slice(&self.rpl_flags[..count]);
+                self.program_counter = self.program_counter.wrapping_add(2);
             }
         }
-
-        if self.delay_timer > 0 {
-            self.delay_timer -= 1;
-        }
-        if self.sound_timer == 1 {
-            println!("BEEP!");
-        }
-        if self.sound_timer > 0 {
-            self.sound_timer -= 1;
-        }
+        return Ok(());
     }
 
-    fn parse_instruction(opcode: u16) -> Instruction {
+    /// Decodes an opcode, returning `None` for anything no supported
+    /// platform defines.
+    pub fn parse_instruction(opcode: u16) -> Option<Instruction> {
         let nibbles = [
             (opcode & 0xF000) >> 12,
             (opcode & 0x0F00) >> 8,
             (opcode & 0x00F0) >> 4,
-            (opcode & 0x000F) >> 0,
+            opcode & 0x000F,
         ];
         let x = nibbles[1] as u8;
         let y = nibbles[2] as u8;
@@ -350,59 +1142,106 @@ impl Chip8 {
         let nn = ((nibbles[2] << 4) + nibbles[3]) as u8;
         let nnn = (nibbles[1] << 8) + (nibbles[2] << 4) + nibbles[3];
         match nibbles {
-            [0x0, 0x0, 0xE, 0x0] => return Instruction::ClearDisplay,
-            [0x0, 0x0, 0xE, 0xE] => return Instruction::Return,
-            [0x1, _, _, _] => return Instruction::Jump(nnn),
-            [0x2, _, _, _] => return Instruction::Call(nnn),
-            [0x3, _, _, _] => return Instruction::SkipEqualK(x, nn),
-            [0x4, _, _, _] => return Instruction::SkipNotEqualK(x, nn),
-            [0x5, _, _, 0x0] => return Instruction::SkipEqual(x, y),
-            [0x6, _, _, _] => return Instruction::SetK(x, nn),
-            [0x7, _, _, _] => return Instruction::AddK(x, nn),
-            [0x8, _, _, 0x0] => return Instruction::Set(x, y),
-            [0x8, _, _, 0x1] => return Instruction::Or(x, y),
-            [0x8, _, _, 0x2] => return Instruction::And(x, y),
-            [0x8, _, _, 0x3] => return Instruction::XOr(x, y),
-            [0x8, _, _, 0x4] => return Instruction::Add(x, y),
-            [0x8, _, _, 0x5] => return Instruction::Sub(x, y),
-            [0x8, _, _, 0x6] => return Instruction::ShiftRight(x),
-            [0x8, _, _, 0x7] => return Instruction::SubInv(x, y),
-            [0x8, _, _, 0xE] => return Instruction::ShiftLeft(x),
-            [0x9, _, _, 0x0] => return Instruction::SkipNotEqual(x, y),
-            [0xA, _, _, _] => return Instruction::LoadI(nnn),
-            [0xB, _, _, _] => return Instruction::LongJump(nnn),
-            [0xC, _, _, _] => return Instruction::Rand(x, nn),
-            [0xD, _, _, _] => return Instruction::Draw(x, y, n),
-            [0xE, _, 0x9, 0xE] => return Instruction::SkipPressed(x),
-            [0xE, _, 0xA, 0x1] => return Instruction::SkipNotPressed(x),
-            [0xF, _, 0x0, 0x7] => return Instruction::GetTimer(x),
-            [0xF, _, 0x0, 0xA] => return Instruction::WaitKey(x),
-            [0xF, _, 0x1, 0x5] => return Instruction::SetTimer(x),
-            [0xF, _, 0x1, 0x8] => return Instruction::SetSoundTimer(x),
-            [0xF, _, 0x1, 0xE] => return Instruction::AddToI(x),
-            [0xF, _, 0x2, 0x9] => return Instruction::LoadHexGlyph(x),
-            [0xF, _, 0x3, 0x3] => return Instruction::StoreBCD(x),
-            [0xF, _, 0x5, 0x5] => return Instruction::StoreRegisters(x),
-            [0xF, _, 0x6, 0x5] => return Instruction::LoadRegisters(x),
-
-            _ => panic!("Unexpected opcode: {opcode:#06X}"),
+            [0x0, 0x0, 0xC, _] => return Some(Instruction::ScrollDown(n)),
+            [0x0, 0x0, 0xD, _] => return Some(Instruction::ScrollUp(n)),
+            [0x0, 0x0, 0xE, 0x0] => return Some(Instruction::ClearDisplay),
+            [0x0, 0x0, 0xE, 0xE] => return Some(Instruction::Return),
+            [0x0, 0x0, 0xF, 0xB] => return Some(Instruction::ScrollRight),
+            [0x0, 0x0, 0xF, 0xC] => return Some(Instruction::ScrollLeft),
+            [0x0, 0x0, 0xF, 0xD] => return Some(Instruction::Exit),
+            [0x0, 0x0, 0xF, 0xE] => return Some(Instruction::LowRes),
+            [0x0, 0x0, 0xF, 0xF] => return Some(Instruction::HighRes),
+            [0x1, _, _, _] => return Some(Instruction::Jump(nnn)),
+            [0x2, _, _, _] => return Some(Instruction::Call(nnn)),
+            [0x3, _, _, _] => return Some(Instruction::SkipEqualK(x, nn)),
+            [0x4, _, _, _] => return Some(Instruction::SkipNotEqualK(x, nn)),
+            [0x5, _, _, 0x0] => return Some(Instruction::SkipEqual(x, y)),
+            [0x5, _, _, 0x2] => return Some(Instruction::SaveRange(x, y)),
+            [0x5, _, _, 0x3] => return Some(Instruction::LoadRange(x, y)),
+            [0x6, _, _, _] => return Some(Instruction::SetK(x, nn)),
+            [0x7, _, _, _] => return Some(Instruction::AddK(x, nn)),
+            [0x8, _, _, 0x0] => return Some(Instruction::Set(x, y)),
+            [0x8, _, _, 0x1] => return Some(Instruction::Or(x, y)),
+            [0x8, _, _, 0x2] => return Some(Instruction::And(x, y)),
+            [0x8, _, _, 0x3] => return Some(Instruction::XOr(x, y)),
+            [0x8, _, _, 0x4] => return Some(Instruction::Add(x, y)),
+            [0x8, _, _, 0x5] => return Some(Instruction::Sub(x, y)),
+            [0x8, _, _, 0x6] => return Some(Instruction::ShiftRight(x, y)),
+            [0x8, _, _, 0x7] => return Some(Instruction::SubInv(x, y)),
+            [0x8, _, _, 0xE] => return Some(Instruction::ShiftLeft(x, y)),
+            [0x9, _, _, 0x0] => return Some(Instruction::SkipNotEqual(x, y)),
+            [0xA, _, _, _] => return Some(Instruction::LoadI(nnn)),
+            [0xB, _, _, _] => return Some(Instruction::LongJump(nnn)),
+            [0xC, _, _, _] => return Some(Instruction::Rand(x, nn)),
+            [0xD, _, _, _] => return Some(Instruction::Draw(x, y, n)),
+            [0xE, _, 0x9, 0xE] => return Some(Instruction::SkipPressed(x)),
+            [0xE, _, 0xA, 0x1] => return Some(Instruction::SkipNotPressed(x)),
+            [0xF, 0x0, 0x0, 0x0] => return Some(Instruction::LoadLongI),
+            [0xF, _, 0x0, 0x1] => return Some(Instruction::SelectPlanes(x)),
+            [0xF, 0x0, 0x0, 0x2] => return Some(Instruction::LoadAudioPattern),
+            [0xF, _, 0x0, 0x7] => return Some(Instruction::GetTimer(x)),
+            [0xF, _, 0x0, 0xA] => return Some(Instruction::WaitKey(x)),
+            [0xF, _, 0x1, 0x5] => return Some(Instruction::SetTimer(x)),
+            [0xF, _, 0x1, 0x8] => return Some(Instruction::SetSoundTimer(x)),
+            [0xF, _, 0x1, 0xE] => return Some(Instruction::AddToI(x)),
+            [0xF, _, 0x2, 0x9] => return Some(Instruction::LoadHexGlyph(x)),
+            [0xF, _, 0x3, 0x0] => return Some(Instruction::LoadBigHexGlyph(x)),
+            [0xF, _, 0x3, 0x3] => return Some(Instruction::StoreBCD(x)),
+            [0xF, _, 0x3, 0xA] => return Some(Instruction::SetPitch(x)),
+            [0xF, _, 0x5, 0x5] => return Some(Instruction::StoreRegisters(x)),
+            [0xF, _, 0x6, 0x5] => return Some(Instruction::LoadRegisters(x)),
+            [0xF, _, 0x7, 0x5] => return Some(Instruction::StoreFlags(x)),
+            [0xF, _, 0x8, 0x5] => return Some(Instruction::LoadFlags(x)),
+
+            _ => return None,
         }
     }
 }
 
-type Address = u16;
+/// A data access to memory, by address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryAccess {
+    Read(usize),
+    Write(usize),
+}
+
+impl MemoryAccess {
+    pub fn address(&self) -> usize {
+        return match self {
+            MemoryAccess::Read(address) | MemoryAccess::Write(address) => *address,
+        };
+    }
+}
 
-type RegisterNumber = u8;
+/// Progress of an FX0A wait-for-key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum KeyWait {
+    Press,
+    Release(u8),
+}
 
-#[derive(Debug, PartialEq)]
-enum Instruction {
+pub type Address = u16;
+
+pub type RegisterNumber = u8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
     ClearDisplay,
     Return,
+    ScrollDown(u8),
+    ScrollUp(u8),
+    ScrollRight,
+    ScrollLeft,
+    Exit,
+    LowRes,
+    HighRes,
     Jump(Address),
     Call(Address),
     SkipEqualK(RegisterNumber, u8),
     SkipNotEqualK(RegisterNumber, u8),
     SkipEqual(RegisterNumber, RegisterNumber),
+    SaveRange(RegisterNumber, RegisterNumber),
+    LoadRange(RegisterNumber, RegisterNumber),
     SetK(RegisterNumber, u8),
     AddK(RegisterNumber, u8),
     Set(RegisterNumber, RegisterNumber),
@@ -411,51 +1250,484 @@ enum Instruction {
     XOr(RegisterNumber, RegisterNumber),
     Add(RegisterNumber, RegisterNumber),
     Sub(RegisterNumber, RegisterNumber),
-    ShiftRight(RegisterNumber),
+    ShiftRight(RegisterNumber, RegisterNumber),
     SubInv(RegisterNumber, RegisterNumber),
-    ShiftLeft(RegisterNumber),
+    ShiftLeft(RegisterNumber, RegisterNumber),
     SkipNotEqual(RegisterNumber, RegisterNumber),
     LoadI(Address),
+    /// F000 NNNN: the address is the 16-bit word after the opcode.
+    LoadLongI,
     LongJump(Address),
     Rand(RegisterNumber, u8),
     Draw(RegisterNumber, RegisterNumber, u8),
     SkipPressed(RegisterNumber),
     SkipNotPressed(RegisterNumber),
+    SelectPlanes(u8),
+    LoadAudioPattern,
     GetTimer(RegisterNumber),
     WaitKey(RegisterNumber),
     SetTimer(RegisterNumber),
     SetSoundTimer(RegisterNumber),
     AddToI(RegisterNumber),
     LoadHexGlyph(RegisterNumber),
+    LoadBigHexGlyph(RegisterNumber),
     StoreBCD(RegisterNumber),
+    SetPitch(RegisterNumber),
     StoreRegisters(RegisterNumber),
     LoadRegisters(RegisterNumber),
+    StoreFlags(RegisterNumber),
+    LoadFlags(RegisterNumber),
+}
+
+impl Instruction {
+    /// How many bytes the instruction takes, counting the address word that
+    /// follows an F000.
+    pub fn length(&self) -> usize {
+        return match self {
+            Instruction::LoadLongI => 4,
+            _ => 2,
+        };
+    }
+
+    /// The opcode for this instruction, the inverse of `Chip8::parse_instruction`.
+    /// Operands are masked to the width of their field.
+    pub fn encode(&self) -> u16 {
+        let xy = |high: u16, x: u8, y: u8, low: u16| {
+            return high << 12 | (x as u16 & 0xF) << 8 | (y as u16 & 0xF) << 4 | low;
+        };
+        let xnn = |high: u16, x: u8, nn: u8| return high << 12 | (x as u16 & 0xF) << 8 | nn as u16;
+        let fx = |x: u8, low: u16| return 0xF000 | (x as u16 & 0xF) << 8 | low;
+        return match *self {
+            Instruction::ClearDisplay => 0x00E0,
+            Instruction::Return => 0x00EE,
+            Instruction::ScrollDown(n) => 0x00C0 | (n as u16 & 0xF),
+            Instruction::ScrollUp(n) => 0x00D0 | (n as u16 & 0xF),
+            Instruction::ScrollRight => 0x00FB,
+            Instruction::ScrollLeft => 0x00FC,
+            Instruction::Exit => 0x00FD,
+            Instruction::LowRes => 0x00FE,
+            Instruction::HighRes => 0x00FF,
+            Instruction::Jump(nnn) => 0x1000 | (nnn & 0xFFF),
+            Instruction::Call(nnn) => 0x2000 | (nnn & 0xFFF),
+            Instruction::SkipEqualK(x, nn) => xnn(0x3, x, nn),
+            Instruction::SkipNotEqualK(x, nn) => xnn(0x4, x, nn),
+            Instruction::SkipEqual(x, y) => xy(0x5, x, y, 0x0),
+            Instruction::SaveRange(x, y) => xy(0x5, x, y, 0x2),
+            Instruction::LoadRange(x, y) => xy(0x5, x, y, 0x3),
+            Instruction::SetK(x, nn) => xnn(0x6, x, nn),
+            Instruction::AddK(x, nn) => xnn(0x7, x, nn),
+            Instruction::Set(x, y) => xy(0x8, x, y, 0x0),
+            Instruction::Or(x, y) => xy(0x8, x, y, 0x1),
+            Instruction::And(x, y) => xy(0x8, x, y, 0x2),
+            Instruction::XOr(x, y) => xy(0x8, x, y, 0x3),
+            Instruction::Add(x, y) => xy(0x8, x, y, 0x4),
+            Instruction::Sub(x, y) => xy(0x8, x, y, 0x5),
+            Instruction::ShiftRight(x, y) => xy(0x8, x, y, 0x6),
+            Instruction::SubInv(x, y) => xy(0x8, x, y, 0x7),
+            Instruction::ShiftLeft(x, y) => xy(0x8, x, y, 0xE),
+            Instruction::SkipNotEqual(x, y) => xy(0x9, x, y, 0x0),
+            Instruction::LoadI(nnn) => 0xA000 | (nnn & 0xFFF),
+            Instruction::LoadLongI => 0xF000,
+            Instruction::LongJump(nnn) => 0xB000 | (nnn & 0xFFF),
+            Instruction::Rand(x, nn) => xnn(0xC, x, nn),
+            Instruction::Draw(x, y, n) => xy(0xD, x, y, n as u16 & 0xF),
+            Instruction::SkipPressed(x) => xnn(0xE, x, 0x9E),
+            Instruction::SkipNotPressed(x) => xnn(0xE, x, 0xA1),
+            Instruction::SelectPlanes(n) => fx(n, 0x01),
+            Instruction::LoadAudioPattern => 0xF002,
+            Instruction::GetTimer(x) => fx(x, 0x07),
+            Instruction::WaitKey(x) => fx(x, 0x0A),
+            Instruction::SetTimer(x) => fx(x, 0x15),
+            Instruction::SetSoundTimer(x) => fx(x, 0x18),
+            Instruction::AddToI(x) => fx(x, 0x1E),
+            Instruction::LoadHexGlyph(x) => fx(x, 0x29),
+            Instruction::LoadBigHexGlyph(x) => fx(x, 0x30),
+            Instruction::StoreBCD(x) => fx(x, 0x33),
+            Instruction::SetPitch(x) => fx(x, 0x3A),
+            Instruction::StoreRegisters(x) => fx(x, 0x55),
+            Instruction::LoadRegisters(x) => fx(x, 0x65),
+            Instruction::StoreFlags(x) => fx(x, 0x75),
+            Instruction::LoadFlags(x) => fx(x, 0x85),
+        };
+    }
 }
 
 #[test]
 fn instruction_parsing() {
-    assert_eq!(Chip8::parse_instruction(0x00E0), Instruction::ClearDisplay);
+    assert_eq!(
+        Chip8::parse_instruction(0x00E0),
+        Some(Instruction::ClearDisplay)
+    );
 
-    assert_eq!(Chip8::parse_instruction(0x00EE), Instruction::Return);
+    assert_eq!(Chip8::parse_instruction(0x00EE), Some(Instruction::Return));
 
-    assert_eq!(Chip8::parse_instruction(0x1000), Instruction::Jump(0x0000));
-    assert_eq!(Chip8::parse_instruction(0x1234), Instruction::Jump(0x0234));
-    assert_eq!(Chip8::parse_instruction(0x1FFF), Instruction::Jump(0x0FFF));
+    assert_eq!(
+        Chip8::parse_instruction(0x1000),
+        Some(Instruction::Jump(0x0000))
+    );
+    assert_eq!(
+        Chip8::parse_instruction(0x1234),
+        Some(Instruction::Jump(0x0234))
+    );
+    assert_eq!(
+        Chip8::parse_instruction(0x1FFF),
+        Some(Instruction::Jump(0x0FFF))
+    );
 
-    assert_eq!(Chip8::parse_instruction(0x2000), Instruction::Call(0x0000));
-    assert_eq!(Chip8::parse_instruction(0x2234), Instruction::Call(0x0234));
-    assert_eq!(Chip8::parse_instruction(0x2FFF), Instruction::Call(0x0FFF));
+    assert_eq!(
+        Chip8::parse_instruction(0x2000),
+        Some(Instruction::Call(0x0000))
+    );
+    assert_eq!(
+        Chip8::parse_instruction(0x2234),
+        Some(Instruction::Call(0x0234))
+    );
+    assert_eq!(
+        Chip8::parse_instruction(0x2FFF),
+        Some(Instruction::Call(0x0FFF))
+    );
 
     assert_eq!(
         Chip8::parse_instruction(0x3000),
-        Instruction::SkipEqualK(0, 0x00)
+        Some(Instruction::SkipEqualK(0, 0x00))
     );
     assert_eq!(
         Chip8::parse_instruction(0x3234),
-        Instruction::SkipEqualK(2, 0x34)
+        Some(Instruction::SkipEqualK(2, 0x34))
     );
     assert_eq!(
         Chip8::parse_instruction(0x3FFF),
-        Instruction::SkipEqualK(15, 0xFF)
+        Some(Instruction::SkipEqualK(15, 0xFF))
+    );
+}
+
+#[test]
+fn shift_quirk() {
+    // 6105 6203 8126: V1 = 5, V2 = 3, V1 >>= 1 (or V1 = V2 >> 1)
+    let rom = vec![0x61, 0x05, 0x62, 0x03, 0x81, 0x26];
+
+    let mut chip8 = Chip8::new(Quirks::SUPER_CHIP);
+    chip8.load_rom(rom.clone()).unwrap();
+    for _ in 0..3 {
+        chip8.execute_cycle().unwrap();
+    }
+    assert_eq!(chip8.get_register_value(1), 2);
+    assert_eq!(chip8.get_register_value(0xF), 1);
+
+    let mut chip8 = Chip8::new(Quirks::COSMAC_VIP);
+    chip8.load_rom(rom).unwrap();
+    for _ in 0..3 {
+        chip8.execute_cycle().unwrap();
+    }
+    assert_eq!(chip8.get_register_value(1), 1);
+    assert_eq!(chip8.get_register_value(0xF), 1);
+}
+
+#[test]
+fn arithmetic_flags() {
+    // VF = 0xFF, V1 = 1, then each of 8F14, 8F15, 8F17 and, with X=2, 8214
+    // without a carry, which has to clear the flag left by the one before.
+    let cases: [(u16, u8); 4] = [(0x8F14, 1), (0x8F15, 1), (0x8F17, 0), (0x8214, 0)];
+    for (opcode, flag) in cases {
+        let mut chip8 = Chip8::new(Quirks::SUPER_CHIP);
+        let [high, low] = opcode.to_be_bytes();
+        chip8
+            .load_rom(vec![0x6F, 0xFF, 0x61, 0x01, 0x62, 0x01, high, low])
+            .unwrap();
+        for _ in 0..4 {
+            chip8.execute_cycle().unwrap();
+        }
+        assert_eq!(chip8.get_register_value(0xF), flag, "{opcode:04X}");
+    }
+}
+
+#[test]
+fn jump_with_offset() {
+    // 6004 6302 B310: V0 = 4, V3 = 2, jump to 0x310 + V0 (or + V3)
+    let rom = vec![0x60, 0x04, 0x63, 0x02, 0xB3, 0x10];
+
+    let mut chip8 = Chip8::new(Quirks::COSMAC_VIP);
+    chip8.load_rom(rom.clone()).unwrap();
+    for _ in 0..3 {
+        chip8.execute_cycle().unwrap();
+    }
+    assert_eq!(chip8.program_counter, 0x314);
+
+    let mut chip8 = Chip8::new(Quirks::SUPER_CHIP);
+    chip8.load_rom(rom).unwrap();
+    for _ in 0..3 {
+        chip8.execute_cycle().unwrap();
+    }
+    assert_eq!(chip8.program_counter, 0x312);
+}
+
+#[test]
+fn faults() {
+    let mut chip8 = Chip8::new(Quirks::default());
+    chip8.load_rom(vec![0x00, 0xEE]).unwrap();
+    assert_eq!(
+        chip8.execute_cycle(),
+        Err(Chip8Error::StackUnderflow {
+            program_counter: 0x200,
+            opcode: 0x00EE
+        })
     );
+
+    let mut chip8 = Chip8::new(Quirks::default());
+    chip8.load_rom(vec![0xAF, 0xFF, 0xF2, 0x55]).unwrap();
+    chip8.execute_cycle().unwrap();
+    assert_eq!(
+        chip8.execute_cycle(),
+        Err(Chip8Error::MemoryOutOfBounds {
+            program_counter: 0x202,
+            opcode: 0xF255,
+            address: 0x1000
+        })
+    );
+
+    let mut chip8 = Chip8::new(Quirks::default());
+    assert_eq!(
+        chip8.load_rom(vec![0; 4096]),
+        Err(Chip8Error::RomTooLarge {
+            size: 4096,
+            capacity: 3584
+        })
+    );
+}
+
+#[test]
+fn program_counter_wraps() {
+    // 3000 at 0xFFFC skips the 6001 at 0xFFFE and lands on 0x0000; 6002 at
+    // 0xFFFE steps there too.
+    let mut chip8 = Chip8::new(Quirks::XO_CHIP);
+    chip8.set_memory(0xFFFC, &[0x30, 0x00, 0x60, 0x01]);
+    chip8.set_program_counter(0xFFFC);
+    chip8.execute_cycle().unwrap();
+    assert_eq!(chip8.get_program_counter(), 0x0000);
+
+    chip8.set_memory(0xFFFE, &[0x60, 0x02]);
+    chip8.set_program_counter(0xFFFE);
+    chip8.execute_cycle().unwrap();
+    assert_eq!(chip8.get_program_counter(), 0x0000);
+    assert_eq!(chip8.get_register_value(0), 2);
+}
+
+#[test]
+fn timers_tick_per_frame() {
+    // 6005 F015 1202: V0 = 5, delay timer = V0, loop forever
+    let rom = vec![0x60, 0x05, 0xF0, 0x15, 0x12, 0x04];
+
+    let mut chip8 = Chip8::new(Quirks::default());
+    chip8.load_rom(rom).unwrap();
+    for _ in 0..20 {
+        chip8.execute_cycle().unwrap();
+    }
+    assert_eq!(chip8.delay_timer, 5);
+
+    chip8.run_frame().unwrap();
+    chip8.run_frame().unwrap();
+    assert_eq!(chip8.delay_timer, 3);
+}
+
+#[test]
+fn super_chip_display() {
+    // 00FF A050 6000 D000 00C2 00FB: hires, I = big "0", draw 16x16 at 0,0,
+    // scroll down 2 and right 4
+    let rom = vec![
+        0x00, 0xFF, 0xA0, 0x50, 0x60, 0x00, 0xD0, 0x00, 0x00, 0xC2, 0x00, 0xFB,
+    ];
+
+    let mut chip8 = Chip8::new(Quirks::SUPER_CHIP);
+    chip8.load_rom(rom).unwrap();
+    chip8.execute_cycle().unwrap();
+    assert_eq!(chip8.get_display_buffer().width(), 128);
+    chip8.execute_cycle().unwrap();
+    chip8.execute_cycle().unwrap();
+    chip8.execute_cycle().unwrap();
+    // The top row of the big "0" is 0x3C followed by 0x7E.
+    assert!(!chip8.get_display_buffer().get_pixel(1, 0));
+    assert!(chip8.get_display_buffer().get_pixel(2, 0));
+    assert!(chip8.get_display_buffer().get_pixel(9, 0));
+
+    chip8.execute_cycle().unwrap();
+    chip8.execute_cycle().unwrap();
+    assert!(!chip8.get_display_buffer().get_pixel(6, 0));
+    assert!(chip8.get_display_buffer().get_pixel(6, 2));
+}
+
+#[test]
+fn lores_scrolls() {
+    // 6000 A000 D005: draw the small "0" at 0,0 in lores
+    // 00FB 00C4 00FC 00D4 00FC: right, down 4, left, up 4, left
+    let rom = vec![
+        0x60, 0x00, 0xA0, 0x00, 0xD0, 0x05, 0x00, 0xFB, 0x00, 0xC4, 0x00, 0xFC, 0x00, 0xD4, 0x00,
+        0xFC,
+    ];
+
+    // SUPER-CHIP moves hires pixels, two to the right and two rows down.
+    let mut chip8 = Chip8::new(Quirks::SUPER_CHIP);
+    chip8.load_rom(rom.clone()).unwrap();
+    for _ in 0..5 {
+        chip8.execute_cycle().unwrap();
+    }
+    let display = chip8.get_display_buffer();
+    // The top row of "0" is 0xF0.
+    assert!(!display.get_pixel(1, 2) && display.get_pixel(2, 2));
+    assert!(display.get_pixel(5, 2) && !display.get_pixel(6, 2));
+    assert!(!display.get_pixel(2, 1));
+    for _ in 0..3 {
+        chip8.execute_cycle().unwrap();
+    }
+    let display = chip8.get_display_buffer();
+    assert!(display.get_pixel(0, 0) && display.get_pixel(1, 0));
+    assert!(!display.get_pixel(2, 0));
+
+    // Octo moves whole lores pixels, four across and four down.
+    let mut chip8 = Chip8::new(Quirks::XO_CHIP);
+    chip8.load_rom(rom).unwrap();
+    for _ in 0..5 {
+        chip8.execute_cycle().unwrap();
+    }
+    let display = chip8.get_display_buffer();
+    assert!(!display.get_pixel(3, 4) && display.get_pixel(4, 4));
+    assert!(display.get_pixel(7, 4) && !display.get_pixel(8, 4));
+    assert!(!display.get_pixel(4, 3));
+    chip8.execute_cycle().unwrap();
+    chip8.execute_cycle().unwrap();
+    let display = chip8.get_display_buffer();
+    assert!(display.get_pixel(0, 0) && display.get_pixel(3, 0));
+    assert!(!display.get_pixel(4, 0));
+    // The last scroll takes the whole sprite off the left edge.
+    chip8.execute_cycle().unwrap();
+    assert!(!chip8.get_display_buffer().get_pixel(0, 0));
+}
+
+#[test]
+fn xo_chip_extensions() {
+    // F000 8000: I = 0x8000
+    // 6001 6102 6203 5022: V0..V2 = 1, 2, 3, save V0..V2 at I
+    // 5233 : load V2..V3 from I, so V2 = 1 and V3 = 2
+    // 3001 F000 1234 6A0A: skip over the whole long load, VA = 10
+    let rom = vec![
+        0xF0, 0x00, 0x80, 0x00, 0x60, 0x01, 0x61, 0x02, 0x62, 0x03, 0x50, 0x22, 0x52, 0x33, 0x30,
+        0x01, 0xF0, 0x00, 0x12, 0x34, 0x6A, 0x0A,
+    ];
+
+    let mut chip8 = Chip8::new(Quirks::XO_CHIP);
+    chip8.load_rom(rom).unwrap();
+    for _ in 0..8 {
+        chip8.execute_cycle().unwrap();
+    }
+    assert_eq!(chip8.index_register, 0x8000);
+    assert_eq!(&chip8.memory[0x8000..0x8003], &[1, 2, 3]);
+    assert_eq!(chip8.get_register_value(2), 1);
+    assert_eq!(chip8.get_register_value(3), 2);
+    assert_eq!(chip8.get_register_value(0xA), 10);
+}
+
+#[test]
+fn wait_for_key_release() {
+    // F30A: wait for a key and store it in V3
+    let mut chip8 = Chip8::new(Quirks::default());
+    chip8.load_rom(vec![0xF3, 0x0A]).unwrap();
+    chip8.execute_cycle().unwrap();
+    assert!(chip8.is_waiting_for_key());
+
+    chip8.set_key(0xB, true);
+    chip8.execute_cycle().unwrap();
+    chip8.execute_cycle().unwrap();
+    assert_eq!(chip8.program_counter, 0x200);
+
+    chip8.set_key(0xB, false);
+    chip8.execute_cycle().unwrap();
+    assert!(!chip8.is_waiting_for_key());
+    assert_eq!(chip8.get_register_value(3), 0xB);
+    assert_eq!(chip8.program_counter, 0x202);
+}
+
+#[test]
+fn instruction_encoding() {
+    for opcode in 0..=0xFFFF {
+        if let Some(instruction) = Chip8::parse_instruction(opcode) {
+            assert_eq!(instruction.encode(), opcode, "{instruction:?}");
+        }
+    }
+}
+
+#[test]
+fn save_and_load_state() {
+    // 6005 A20A F033 2208 00EE: V0 = 5, I = 0x20A, BCD V0, call a return
+    let mut chip8 = Chip8::new(Quirks::XO_CHIP);
+    chip8
+        .load_rom(vec![
+            0x60, 0x05, 0xA2, 0x0A, 0xF0, 0x33, 0x22, 0x08, 0x00, 0xEE,
+        ])
+        .unwrap();
+    for _ in 0..4 {
+        chip8.execute_cycle().unwrap();
+    }
+    chip8.set_key(0xA, true);
+    let state = chip8.save_state();
+
+    let mut restored = Chip8::new(Quirks::default());
+    restored.load_state(&state).unwrap();
+    assert_eq!(restored.save_state(), state);
+    assert_eq!(restored.get_quirks(), Quirks::XO_CHIP);
+    assert_eq!(restored.get_stack(), &[0x206]);
+    assert!(restored.get_keys()[0xA]);
+    assert_eq!(&restored.get_memory()[0x20A..0x20D], &[0, 0, 5]);
+    // Mostly empty 64 KiB of memory packs down to little more than the ROM.
+    assert!(state.len() < 512);
+
+    assert_eq!(
+        restored.load_state(&state[..state.len() - 1]),
+        Err(StateError::Truncated)
+    );
+    let mut future = state.clone();
+    future[4] = 99;
+    assert_eq!(
+        restored.load_state(&future),
+        Err(StateError::UnsupportedVersion(99))
+    );
+    assert_eq!(restored.get_program_counter(), 0x208);
+}
+
+#[test]
+fn seeded_and_scripted_random() {
+    use crate::random::ScriptedRandom;
+
+    // C0FF C1FF: two random bytes
+    let rom = vec![0xC0, 0xFF, 0xC1, 0xFF];
+    let run = |chip8: &mut Chip8| {
+        chip8.load_rom(rom.clone()).unwrap();
+        chip8.execute_cycle().unwrap();
+        chip8.execute_cycle().unwrap();
+        return chip8.get_registers()[0..2].to_vec();
+    };
+
+    let mut first = Config::new().seed(42).build();
+    let mut second = Config::new().seed(42).build();
+    assert_eq!(run(&mut first), run(&mut second));
+    assert_eq!(first.get_seed(), 42);
+
+    let mut scripted = Chip8::new(Quirks::default());
+    scripted.set_random_source(Box::new(ScriptedRandom::new(vec![0x12, 0x34, 0x56])));
+    assert_eq!(run(&mut scripted), vec![0x12, 0x34]);
+
+    // A state brings its seed along, but only resumes the same kind of
+    // generator.
+    let state = scripted.save_state();
+    let rerun = |chip8: &mut Chip8| {
+        chip8.load_state(&state).unwrap();
+        chip8.set_program_counter(0x200);
+        return run(chip8);
+    };
+    let mut resumed = Chip8::new(Quirks::default());
+    resumed.set_random_source(Box::new(ScriptedRandom::new(vec![0x12, 0x34, 0x56])));
+    assert_eq!(rerun(&mut resumed), vec![0x56, 0x12]);
+    assert_eq!(resumed.get_seed(), scripted.get_seed());
+
+    let mut seeded = Config::new().seed(42).build();
+    assert_eq!(rerun(&mut seeded), run(&mut Config::new().seed(42).build()));
+    assert_eq!(seeded.get_seed(), scripted.get_seed());
 }
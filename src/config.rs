@@ -0,0 +1,60 @@
+use crate::chip8::Chip8;
+use crate::quirks::Quirks;
+
+/// Builder for a `Chip8` with everything a frontend may want to tune.
+///
+/// ```
+/// use chip_8_interpreter::{Config, Quirks};
+///
+/// let chip8 = Config::new()
+///     .quirks(Quirks::SUPER_CHIP)
+///     .instructions_per_frame(30)
+///     .build();
+/// assert_eq!(chip8.get_instructions_per_frame(), 30);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Config {
+    pub quirks: Quirks,
+    pub instructions_per_frame: usize,
+    /// Seeds the CXNN random number generator; `None` picks a fresh seed.
+    pub seed: Option<u64>,
+}
+
+impl Config {
+    pub const DEFAULT_INSTRUCTIONS_PER_FRAME: usize = 10;
+
+    pub fn new() -> Config {
+        return Config {
+            quirks: Quirks::default(),
+            instructions_per_frame: Config::DEFAULT_INSTRUCTIONS_PER_FRAME,
+            seed: None,
+        };
+    }
+
+    pub fn quirks(mut self, quirks: Quirks) -> Config {
+        self.quirks = quirks;
+        return self;
+    }
+
+    /// How many instructions `Chip8::run_frame` executes per 60 Hz frame.
+    pub fn instructions_per_frame(mut self, instructions_per_frame: usize) -> Config {
+        self.instructions_per_frame = instructions_per_frame;
+        return self;
+    }
+
+    /// Makes CXNN produce the same sequence on every run.
+    pub fn seed(mut self, seed: u64) -> Config {
+        self.seed = Some(seed);
+        return self;
+    }
+
+    pub fn build(self) -> Chip8 {
+        return Chip8::with_config(self);
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        return Config::new();
+    }
+}
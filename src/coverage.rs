@@ -0,0 +1,204 @@
+//! Which parts of a ROM ran: every byte fetched as part of an instruction,
+//! how often each instruction ran, and which way each skip went.
+//!
+//! `Coverage::lcov` writes the result in the lcov tracefile format that
+//! genhtml and most editors read. With an assembler or Octo source map the
+//! lines are the program's source lines; without one each instruction is
+//! listed against its address in the ROM, so line 512 is the code at 0x200.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use crate::assembler::SourceMap;
+use crate::chip8::{Address, Instruction, PROGRAM_START};
+use crate::disassembler::Disassembly;
+
+/// How often a skip instruction skipped and how often it fell through.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BranchCoverage {
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+/// The most times any instruction on a source line ran, and how each skip
+/// on it went, `None` for a skip that never ran.
+type LineCoverage = (u64, Vec<Option<BranchCoverage>>);
+
+/// Collects coverage. Attach one with `Chip8::set_coverage`.
+#[derive(Debug, Clone, Default)]
+pub struct Coverage {
+    executed: BTreeSet<Address>,
+    hits: BTreeMap<Address, u64>,
+    branches: BTreeMap<Address, BranchCoverage>,
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        return Coverage::default();
+    }
+
+    /// Counts one execution of `instruction` at `address`, after which the
+    /// program counter was `next`.
+    pub fn record(&mut self, address: Address, instruction: Instruction, next: Address) {
+        for offset in 0..instruction.length() as Address {
+            self.executed.insert(address.wrapping_add(offset));
+        }
+        *self.hits.entry(address).or_default() += 1;
+        if is_skip(&instruction) {
+            let branch = self.branches.entry(address).or_default();
+            if next != address.wrapping_add(2) {
+                branch.taken += 1;
+            } else {
+                branch.not_taken += 1;
+            }
+        }
+    }
+
+    /// Whether the byte at `address` was fetched as part of an instruction.
+    pub fn is_executed(&self, address: Address) -> bool {
+        return self.executed.contains(&address);
+    }
+
+    /// One flag per byte of a ROM of `length` bytes loaded at 0x200.
+    pub fn byte_map(&self, length: usize) -> Vec<bool> {
+        return (0..length)
+            .map(|offset| self.is_executed((PROGRAM_START + offset) as Address))
+            .collect();
+    }
+
+    pub fn get_hits(&self, address: Address) -> u64 {
+        return self.hits.get(&address).copied().unwrap_or(0);
+    }
+
+    /// The directions a skip at `address` went, if it ever ran.
+    pub fn get_branch(&self, address: Address) -> Option<BranchCoverage> {
+        return self.branches.get(&address).copied();
+    }
+
+    /// An lcov tracefile for `rom`. Without a source map, or for code the
+    /// map does not cover, lines are reported against `rom_name`.
+    pub fn lcov(&self, rom: &[u8], rom_name: &str, source_map: Option<&SourceMap>) -> String {
+        // Everything the disassembler can reach, plus whatever ran that it
+        // could not, like code behind a computed jump.
+        let mut code: BTreeMap<Address, Option<Instruction>> = match Disassembly::new(rom) {
+            Ok(disassembly) => disassembly
+                .instructions()
+                .map(|decoded| (decoded.address, Some(decoded.instruction)))
+                .collect(),
+            Err(_) => BTreeMap::new(),
+        };
+        for address in self.hits.keys() {
+            code.entry(*address).or_insert(None);
+        }
+
+        let mut files: BTreeMap<String, BTreeMap<usize, LineCoverage>> = BTreeMap::new();
+        for (address, instruction) in code {
+            let (file, line) = match source_map.and_then(|map| map.get(&address)) {
+                Some(location) => (location.file.clone(), location.line),
+                None => (rom_name.to_string(), address as usize),
+            };
+            let entry = files.entry(file).or_default().entry(line).or_default();
+            entry.0 = entry.0.max(self.get_hits(address));
+            let is_skip = instruction.map_or(self.branches.contains_key(&address), |instruction| {
+                is_skip(&instruction)
+            });
+            if is_skip {
+                entry.1.push(self.get_branch(address));
+            }
+        }
+
+        let mut lcov = String::new();
+        for (file, lines) in files {
+            let _ = writeln!(lcov, "TN:\nSF:{file}");
+            let (mut branches, mut branches_hit) = (0, 0);
+            for (line, (_, skips)) in &lines {
+                for (block, branch) in skips.iter().enumerate() {
+                    let counts = match branch {
+                        Some(branch) => {
+                            [branch.taken, branch.not_taken].map(|count| count.to_string())
+                        }
+                        None => [String::from("-"), String::from("-")],
+                    };
+                    for (index, count) in counts.iter().enumerate() {
+                        let _ = writeln!(lcov, "BRDA:{line},{block},{index},{count}");
+                        branches += 1;
+                        branches_hit += (count != "-" && count != "0") as usize;
+                    }
+                }
+            }
+            let _ = writeln!(lcov, "BRF:{branches}\nBRH:{branches_hit}");
+            for (line, (hits, _)) in &lines {
+                let _ = writeln!(lcov, "DA:{line},{hits}");
+            }
+            let hit = lines.values().filter(|(hits, _)| *hits > 0).count();
+            let _ = writeln!(lcov, "LF:{}\nLH:{hit}\nend_of_record", lines.len());
+        }
+        return lcov;
+    }
+}
+
+/// The instructions that skip the next one on a condition.
+fn is_skip(instruction: &Instruction) -> bool {
+    return matches!(
+        instruction,
+        Instruction::SkipEqualK(..)
+            | Instruction::SkipNotEqualK(..)
+            | Instruction::SkipEqual(..)
+            | Instruction::SkipNotEqual(..)
+            | Instruction::SkipPressed(..)
+            | Instruction::SkipNotPressed(..)
+    );
+}
+
+#[test]
+fn collects_coverage() {
+    use crate::assembler::assemble;
+    use crate::chip8::Chip8;
+    use crate::quirks::Quirks;
+
+    let assembly = assemble(
+        "loop:\n\
+         \tADD V0, 1\n\
+         \tSE V0, 3\n\
+         \tJP loop\n\
+         \tSKP V1\n\
+         \tJP done\n\
+         \tCLS\n\
+         done:\n\
+         \tJP done\n",
+    )
+    .unwrap();
+    let mut chip8 = Chip8::new(Quirks::SUPER_CHIP);
+    chip8.load_rom(assembly.rom.clone()).unwrap();
+    chip8.set_coverage(Coverage::new());
+    for _ in 0..12 {
+        chip8.execute_cycle().unwrap();
+    }
+    let coverage = chip8.take_coverage().unwrap();
+
+    assert_eq!(coverage.get_hits(0x200), 3);
+    assert_eq!(
+        coverage.get_branch(0x202),
+        Some(BranchCoverage {
+            taken: 1,
+            not_taken: 2
+        })
+    );
+    assert_eq!(
+        coverage.get_branch(0x206),
+        Some(BranchCoverage {
+            taken: 0,
+            not_taken: 1
+        })
+    );
+    let map = coverage.byte_map(assembly.rom.len());
+    assert_eq!(
+        map,
+        [vec![true; 10], vec![false; 2], vec![true; 2]].concat()
+    );
+
+    let lcov = coverage.lcov(&assembly.rom, "test.ch8", Some(&assembly.source_map));
+    assert!(lcov.contains("BRDA:3,0,0,1\nBRDA:3,0,1,2\nBRDA:5,0,0,0\nBRDA:5,0,1,1\nBRF:4\nBRH:3\n"));
+    assert!(lcov.contains("DA:2,3\nDA:3,3\nDA:4,2\nDA:5,1\nDA:6,1\nDA:7,0\nDA:9,"));
+    assert!(lcov.contains("LF:7\nLH:6\nend_of_record"));
+}
@@ -0,0 +1,712 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use crate::assembler::SourceMap;
+use crate::chip8::{Address, Chip8, Instruction, MemoryAccess};
+use crate::disassembler::{self, Syntax};
+use crate::error::Chip8Error;
+
+/// Cycle limit for REPL commands that run until a condition, so a ROM that
+/// never gets there cannot hang the prompt.
+pub const DEFAULT_RUN_LIMIT: usize = 10_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Access,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub addresses: RangeInclusive<usize>,
+    pub kind: WatchKind,
+}
+
+impl Watchpoint {
+    fn matches(&self, access: &MemoryAccess) -> bool {
+        let kind_matches = matches!(
+            (self.kind, access),
+            (WatchKind::Access, _)
+                | (WatchKind::Read, MemoryAccess::Read(_))
+                | (WatchKind::Write, MemoryAccess::Write(_))
+        );
+        return kind_matches && self.addresses.contains(&access.address());
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Register {
+    V(u8),
+    I,
+}
+
+impl Register {
+    fn value(&self, chip8: &Chip8) -> u16 {
+        return match self {
+            Register::V(index) => chip8.get_registers()[*index as usize] as u16,
+            Register::I => chip8.get_index_register(),
+        };
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            Register::V(index) => write!(f, "V{index:X}"),
+            Register::I => write!(f, "I"),
+        };
+    }
+}
+
+/// Why the debugger handed control back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StopReason {
+    Stepped,
+    Breakpoint(Address),
+    Watchpoint(MemoryAccess),
+    RegisterChanged {
+        register: Register,
+        old: u16,
+        new: u16,
+    },
+    ReachedAddress(Address),
+    Returned,
+    Exited,
+    Error(Chip8Error),
+    LimitReached,
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            StopReason::Stepped => write!(f, "stepped"),
+            StopReason::Breakpoint(address) => write!(f, "breakpoint at {address:03X}"),
+            StopReason::Watchpoint(MemoryAccess::Read(address)) => {
+                write!(f, "watchpoint: read from {address:03X}")
+            }
+            StopReason::Watchpoint(MemoryAccess::Write(address)) => {
+                write!(f, "watchpoint: write to {address:03X}")
+            }
+            StopReason::RegisterChanged { register, old, new } => {
+                write!(f, "{register} changed from {old:02X} to {new:02X}")
+            }
+            StopReason::ReachedAddress(address) => write!(f, "reached {address:03X}"),
+            StopReason::Returned => write!(f, "returned from subroutine"),
+            StopReason::Exited => write!(f, "program exited"),
+            StopReason::Error(error) => write!(f, "error: {error}"),
+            StopReason::LimitReached => write!(f, "cycle limit reached"),
+        };
+    }
+}
+
+/// What a REPL command produced: text to show the user, and whether the
+/// frontend should resume running freely.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandOutput {
+    pub text: String,
+    pub resume: bool,
+    pub quit: bool,
+}
+
+/// Breakpoints, watchpoints and stepping on top of a `Chip8`.
+///
+/// All execution goes through the debugger so it can check conditions after
+/// every instruction. It also takes over the 60 Hz timer tick, counting
+/// instructions so the timers advance at the same rate as under
+/// `Chip8::run_frame` however the program is stepped.
+#[derive(Debug, Clone, Default)]
+pub struct Debugger {
+    breakpoints: BTreeSet<Address>,
+    watchpoints: Vec<Watchpoint>,
+    register_watches: BTreeSet<Register>,
+    cycles_in_frame: usize,
+    /// Whole frames run, counted at each timer tick.
+    frames: u64,
+    /// Set while something outside counts frames, such as a movie, so
+    /// instructions only run a frame at a time through `run_frame`.
+    frames_only: bool,
+    /// A breakpoint we just stopped on, which should not fire again until
+    /// the program counter has moved off it.
+    suppressed_breakpoint: Option<Address>,
+    /// Where each instruction came from, when the program was built from source.
+    source_map: SourceMap,
+}
+
+impl Debugger {
+    pub fn new() -> Debugger {
+        return Debugger::default();
+    }
+
+    pub fn set_source_map(&mut self, source_map: SourceMap) {
+        self.source_map = source_map;
+    }
+
+    pub fn add_breakpoint(&mut self, address: Address) {
+        self.breakpoints.insert(address);
+    }
+
+    pub fn remove_breakpoint(&mut self, address: Address) -> bool {
+        return self.breakpoints.remove(&address);
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = &Address> {
+        return self.breakpoints.iter();
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    /// Removes every watchpoint covering `address`.
+    pub fn remove_watchpoint(&mut self, address: usize) -> bool {
+        let before = self.watchpoints.len();
+        self.watchpoints
+            .retain(|watchpoint| !watchpoint.addresses.contains(&address));
+        return self.watchpoints.len() != before;
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        return &self.watchpoints;
+    }
+
+    pub fn watch_register(&mut self, register: Register) {
+        self.register_watches.insert(register);
+    }
+
+    pub fn unwatch_register(&mut self, register: Register) -> bool {
+        return self.register_watches.remove(&register);
+    }
+
+    /// Executes exactly one instruction.
+    pub fn step(&mut self, chip8: &mut Chip8) -> StopReason {
+        let reason = self.execute(chip8).unwrap_or(StopReason::Stepped);
+        return self.stop(chip8, reason);
+    }
+
+    /// Steps, but runs a 2NNN call through to its return.
+    pub fn step_over(&mut self, chip8: &mut Chip8, limit: usize) -> StopReason {
+        let instruction = Chip8::parse_instruction(Debugger::current_opcode(chip8));
+        let length = match instruction {
+            Some(instruction @ Instruction::Call(_)) => instruction.length(),
+            _ => return self.step(chip8),
+        };
+        let return_address = chip8.get_program_counter().wrapping_add(length as Address);
+        let depth = chip8.get_stack().len();
+        return self.run(chip8, limit, |chip8| {
+            if chip8.get_program_counter() == return_address && chip8.get_stack().len() == depth {
+                return Some(StopReason::Stepped);
+            }
+            return None;
+        });
+    }
+
+    /// Runs until the current subroutine returns to its caller.
+    pub fn step_out(&mut self, chip8: &mut Chip8, limit: usize) -> StopReason {
+        let depth = chip8.get_stack().len();
+        if depth == 0 {
+            return self.step(chip8);
+        }
+        return self.run(chip8, limit, |chip8| {
+            if chip8.get_stack().len() < depth {
+                return Some(StopReason::Returned);
+            }
+            return None;
+        });
+    }
+
+    pub fn run_to(&mut self, chip8: &mut Chip8, address: Address, limit: usize) -> StopReason {
+        return self.run(chip8, limit, |chip8| {
+            if chip8.get_program_counter() == address {
+                return Some(StopReason::ReachedAddress(address));
+            }
+            return None;
+        });
+    }
+
+    /// Runs until a breakpoint, watchpoint or error.
+    pub fn resume(&mut self, chip8: &mut Chip8, limit: usize) -> StopReason {
+        return self.run(chip8, limit, |_| None);
+    }
+
+    /// How many 60 Hz frames have finished under the debugger.
+    pub fn get_frame_count(&self) -> u64 {
+        return self.frames;
+    }
+
+    /// Whether a breakpoint or step stopped partway through a frame.
+    pub fn is_mid_frame(&self) -> bool {
+        return self.cycles_in_frame > 0;
+    }
+
+    /// Refuses the stepping commands, leaving `run_frame` as the only way
+    /// to run instructions.
+    pub fn set_frames_only(&mut self, frames_only: bool) {
+        self.frames_only = frames_only;
+    }
+
+    /// Runs the rest of the current 60 Hz frame, for frontends that keep
+    /// calling this in place of `Chip8::run_frame` while the debugger is
+    /// attached. Returns `None` when the frame completed undisturbed.
+    pub fn run_frame(&mut self, chip8: &mut Chip8) -> Option<StopReason> {
+        let remaining = chip8
+            .get_instructions_per_frame()
+            .saturating_sub(self.cycles_in_frame)
+            .max(1);
+        return match self.run(chip8, remaining, |_| None) {
+            StopReason::LimitReached => None,
+            reason => Some(reason),
+        };
+    }
+
+    fn run<F>(&mut self, chip8: &mut Chip8, limit: usize, done: F) -> StopReason
+    where
+        F: Fn(&Chip8) -> Option<StopReason>,
+    {
+        for _ in 0..limit {
+            let program_counter = chip8.get_program_counter();
+            if self.breakpoints.contains(&program_counter)
+                && self.suppressed_breakpoint != Some(program_counter)
+            {
+                return self.stop(chip8, StopReason::Breakpoint(program_counter));
+            }
+            if let Some(reason) = self.execute(chip8) {
+                return self.stop(chip8, reason);
+            }
+            if let Some(reason) = done(chip8) {
+                return self.stop(chip8, reason);
+            }
+        }
+        return StopReason::LimitReached;
+    }
+
+    fn stop(&mut self, chip8: &Chip8, reason: StopReason) -> StopReason {
+        self.suppressed_breakpoint = Some(chip8.get_program_counter());
+        return reason;
+    }
+
+    fn execute(&mut self, chip8: &mut Chip8) -> Option<StopReason> {
+        let before: Vec<(Register, u16)> = self
+            .register_watches
+            .iter()
+            .map(|register| (*register, register.value(chip8)))
+            .collect();
+
+        if let Err(error) = chip8.execute_cycle() {
+            return Some(StopReason::Error(error));
+        }
+        self.cycles_in_frame += 1;
+        if self.cycles_in_frame >= chip8.get_instructions_per_frame() {
+            chip8.tick_timers();
+            self.cycles_in_frame = 0;
+            self.frames += 1;
+        }
+        if self.suppressed_breakpoint != Some(chip8.get_program_counter()) {
+            self.suppressed_breakpoint = None;
+        }
+
+        if chip8.has_exited() {
+            return Some(StopReason::Exited);
+        }
+        for access in chip8.get_memory_accesses() {
+            if self
+                .watchpoints
+                .iter()
+                .any(|watchpoint| watchpoint.matches(access))
+            {
+                return Some(StopReason::Watchpoint(*access));
+            }
+        }
+        for (register, old) in before {
+            let new = register.value(chip8);
+            if new != old {
+                return Some(StopReason::RegisterChanged { register, old, new });
+            }
+        }
+        return None;
+    }
+
+    fn current_opcode(chip8: &Chip8) -> u16 {
+        let address = chip8.get_program_counter() as usize;
+        let memory = chip8.get_memory();
+        return match memory.get(address..address + 2) {
+            Some(bytes) => u16::from_be_bytes([bytes[0], bytes[1]]),
+            None => 0,
+        };
+    }
+
+    /// One line describing the instruction at the program counter.
+    pub fn describe_current(chip8: &Chip8) -> String {
+        let address = chip8.get_program_counter();
+        let opcode = Debugger::current_opcode(chip8);
+        return match Chip8::parse_instruction(opcode) {
+            Some(instruction) => format!(
+                "{address:03X}: {opcode:04X}  {}",
+                disassembler::mnemonic(&instruction, Syntax::Classic)
+            ),
+            None => format!("{address:03X}: {opcode:04X}  ???"),
+        };
+    }
+
+    /// The current instruction, followed by its source line when known.
+    pub fn describe_location(&self, chip8: &Chip8) -> String {
+        let description = Debugger::describe_current(chip8);
+        return match self.source_map.get(&chip8.get_program_counter()) {
+            Some(location) => format!("{description}  ({location})"),
+            None => description,
+        };
+    }
+
+    pub fn describe_registers(chip8: &Chip8) -> String {
+        let registers = chip8.get_registers();
+        let mut text = String::new();
+        for (index, value) in registers.iter().enumerate() {
+            text.push_str(&format!("V{index:X}={value:02X} "));
+            if index % 8 == 7 {
+                text.push('\n');
+            }
+        }
+        text.push_str(&format!(
+            "I={:04X} PC={:04X} SP={} DT={:02X} ST={:02X}",
+            chip8.get_index_register(),
+            chip8.get_program_counter(),
+            chip8.get_stack().len(),
+            chip8.get_delay_timer(),
+            chip8.get_sound_timer(),
+        ));
+        return text;
+    }
+
+    /// Runs one line of the command-line REPL.
+    pub fn execute_command(&mut self, chip8: &mut Chip8, line: &str) -> CommandOutput {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (command, args) = match words.split_first() {
+            Some((command, args)) => (*command, args),
+            None => ("step", &[][..]),
+        };
+
+        let mut resume = false;
+        let mut quit = false;
+        let steps = matches!(
+            command,
+            "s" | "step" | "n" | "next" | "f" | "finish" | "u" | "until"
+        );
+        let text = match (command, args) {
+            _ if steps && self.frames_only => String::from(
+                "stepping is disabled while whole frames are being counted, 'c' to continue",
+            ),
+            ("s" | "step", []) => self.report(chip8, |debugger, chip8| debugger.step(chip8)),
+            ("s" | "step", [count]) => match count.parse::<usize>() {
+                Ok(count) => self.report(chip8, |debugger, chip8| {
+                    let mut reason = StopReason::Stepped;
+                    for _ in 0..count {
+                        reason = debugger.step(chip8);
+                        if reason != StopReason::Stepped {
+                            break;
+                        }
+                    }
+                    return reason;
+                }),
+                Err(_) => format!("invalid count '{count}'"),
+            },
+            ("n" | "next", []) => self.report(chip8, |debugger, chip8| {
+                debugger.step_over(chip8, DEFAULT_RUN_LIMIT)
+            }),
+            ("f" | "finish", []) => self.report(chip8, |debugger, chip8| {
+                debugger.step_out(chip8, DEFAULT_RUN_LIMIT)
+            }),
+            ("u" | "until", [address]) => match parse_address(address) {
+                Some(address) => self.report(chip8, |debugger, chip8| {
+                    debugger.run_to(chip8, address as Address, DEFAULT_RUN_LIMIT)
+                }),
+                None => format!("invalid address '{address}'"),
+            },
+            ("c" | "continue", []) => {
+                resume = true;
+                String::from("continuing")
+            }
+            ("b" | "break", [address]) => match parse_address(address) {
+                Some(address) => {
+                    self.add_breakpoint(address as Address);
+                    format!("breakpoint at {address:03X}")
+                }
+                None => format!("invalid address '{address}'"),
+            },
+            ("d" | "delete", [address]) => match parse_address(address) {
+                Some(address) if self.remove_breakpoint(address as Address) => {
+                    format!("deleted breakpoint at {address:03X}")
+                }
+                _ => format!("no breakpoint at '{address}'"),
+            },
+            ("w" | "watch", [target]) => self.watch(target, WatchKind::Access),
+            ("w" | "watch", ["r", target]) => self.watch(target, WatchKind::Read),
+            ("w" | "watch", ["w", target]) => self.watch(target, WatchKind::Write),
+            ("w" | "watch", ["rw", target]) => self.watch(target, WatchKind::Access),
+            ("unwatch", [target]) => match (parse_register(target), parse_address(target)) {
+                (Some(register), _) if self.unwatch_register(register) => {
+                    format!("no longer watching {register}")
+                }
+                (None, Some(address)) if self.remove_watchpoint(address) => {
+                    format!("removed watchpoints covering {address:03X}")
+                }
+                _ => format!("nothing watched at '{target}'"),
+            },
+            ("i" | "info", []) => self.describe_breaks(),
+            ("r" | "regs", []) => Debugger::describe_registers(chip8),
+            ("stack", []) => chip8
+                .get_stack()
+                .iter()
+                .rev()
+                .map(|address| format!("{address:03X}"))
+                .collect::<Vec<String>>()
+                .join("\n"),
+            ("m" | "mem", [address]) => describe_memory(chip8, address, "16"),
+            ("m" | "mem", [address, length]) => describe_memory(chip8, address, length),
+            ("l" | "list", []) => self.describe_location(chip8),
+            ("q" | "quit", []) => {
+                quit = true;
+                String::from("quitting")
+            }
+            ("h" | "help", []) => String::from(HELP),
+            _ => format!("unknown command '{}', try 'help'", line.trim()),
+        };
+        return CommandOutput { text, resume, quit };
+    }
+
+    fn report<F>(&mut self, chip8: &mut Chip8, run: F) -> String
+    where
+        F: FnOnce(&mut Debugger, &mut Chip8) -> StopReason,
+    {
+        let reason = run(self, chip8);
+        return format!("{reason}\n{}", self.describe_location(chip8));
+    }
+
+    fn watch(&mut self, target: &str, kind: WatchKind) -> String {
+        if let Some(register) = parse_register(target) {
+            self.watch_register(register);
+            return format!("watching {register}");
+        }
+        let (start, end) = match target.split_once('-') {
+            Some((start, end)) => (parse_address(start), parse_address(end)),
+            None => (parse_address(target), parse_address(target)),
+        };
+        return match (start, end) {
+            (Some(start), Some(end)) if start <= end => {
+                self.add_watchpoint(Watchpoint {
+                    addresses: start..=end,
+                    kind,
+                });
+                format!("watching {start:03X}-{end:03X} for {kind:?}")
+            }
+            _ => format!("invalid watch target '{target}'"),
+        };
+    }
+
+    fn describe_breaks(&self) -> String {
+        let mut lines = Vec::new();
+        for address in &self.breakpoints {
+            lines.push(format!("breakpoint {address:03X}"));
+        }
+        for watchpoint in &self.watchpoints {
+            lines.push(format!(
+                "watchpoint {:03X}-{:03X} {:?}",
+                watchpoint.addresses.start(),
+                watchpoint.addresses.end(),
+                watchpoint.kind
+            ));
+        }
+        for register in &self.register_watches {
+            lines.push(format!("watching {register}"));
+        }
+        if lines.is_empty() {
+            return String::from("no breakpoints or watchpoints");
+        }
+        return lines.join("\n");
+    }
+}
+
+const HELP: &str = "\
+s, step [n]          execute one (or n) instructions
+n, next              step over a 2NNN call
+f, finish            run until the current subroutine returns
+u, until <addr>      run until the program counter reaches addr
+c, continue          resume running in the window
+b, break <addr>      set a breakpoint
+d, delete <addr>     remove a breakpoint
+w, watch [r|w|rw] <addr>[-<addr>]   break on memory access
+w, watch <V0-VF|I>   break when a register changes
+unwatch <addr|reg>   remove a watchpoint
+i, info              list breakpoints and watchpoints
+r, regs              show registers
+stack                show the call stack
+m, mem <addr> [len]  dump memory
+l, list              show the current instruction
+q, quit              exit the emulator";
+
+/// Parses a hexadecimal address, with or without a `0x` or `$` prefix.
+pub fn parse_address(text: &str) -> Option<usize> {
+    let digits = text
+        .trim_start_matches("0x")
+        .trim_start_matches("0X")
+        .trim_start_matches('$');
+    return usize::from_str_radix(digits, 16).ok();
+}
+
+fn parse_register(text: &str) -> Option<Register> {
+    let upper = text.to_ascii_uppercase();
+    if upper == "I" {
+        return Some(Register::I);
+    }
+    let index = upper.strip_prefix('V')?;
+    return match u8::from_str_radix(index, 16) {
+        Ok(index) if index < 16 => Some(Register::V(index)),
+        _ => None,
+    };
+}
+
+fn describe_memory(chip8: &Chip8, address: &str, length: &str) -> String {
+    let (start, length) = match (parse_address(address), length.parse::<usize>()) {
+        (Some(start), Ok(length)) => (start, length),
+        _ => return format!("invalid memory range '{address} {length}'"),
+    };
+    let memory = chip8.get_memory();
+    let end = start.saturating_add(length).min(memory.len());
+    if start >= end {
+        return format!("{start:03X} is outside of memory");
+    }
+    let mut lines = Vec::new();
+    for (row, bytes) in memory[start..end].chunks(8).enumerate() {
+        let hex: Vec<String> = bytes.iter().map(|byte| format!("{byte:02X}")).collect();
+        lines.push(format!("{:03X}: {}", start + row * 8, hex.join(" ")));
+    }
+    return lines.join("\n");
+}
+
+#[test]
+fn breakpoints_and_stepping() {
+    use crate::quirks::Quirks;
+
+    // 200: 2206  call 206
+    // 202: 6101  V1 = 1
+    // 204: 1204  jump 204
+    // 206: 6005  V0 = 5
+    // 208: A300  I = 300
+    // 20A: F055  store V0 at I
+    // 20C: 00EE  return
+    let rom = vec![
+        0x22, 0x06, 0x61, 0x01, 0x12, 0x04, 0x60, 0x05, 0xA3, 0x00, 0xF0, 0x55, 0x00, 0xEE,
+    ];
+    let mut chip8 = Chip8::new(Quirks::SUPER_CHIP);
+    chip8.load_rom(rom).unwrap();
+    let mut debugger = Debugger::new();
+
+    assert_eq!(debugger.step_over(&mut chip8, 100), StopReason::Stepped);
+    assert_eq!(chip8.get_program_counter(), 0x202);
+
+    let mut chip8 = Chip8::new(Quirks::SUPER_CHIP);
+    chip8
+        .load_rom(vec![
+            0x22, 0x06, 0x61, 0x01, 0x12, 0x04, 0x60, 0x05, 0xA3, 0x00, 0xF0, 0x55, 0x00, 0xEE,
+        ])
+        .unwrap();
+    debugger.add_breakpoint(0x208);
+    assert_eq!(
+        debugger.resume(&mut chip8, 100),
+        StopReason::Breakpoint(0x208)
+    );
+    debugger.add_watchpoint(Watchpoint {
+        addresses: 0x300..=0x300,
+        kind: WatchKind::Write,
+    });
+    assert_eq!(
+        debugger.resume(&mut chip8, 100),
+        StopReason::Watchpoint(MemoryAccess::Write(0x300))
+    );
+    assert_eq!(debugger.step_out(&mut chip8, 100), StopReason::Returned);
+    assert_eq!(chip8.get_program_counter(), 0x202);
+
+    debugger.watch_register(Register::V(1));
+    assert_eq!(
+        debugger.resume(&mut chip8, 100),
+        StopReason::RegisterChanged {
+            register: Register::V(1),
+            old: 0,
+            new: 1
+        }
+    );
+}
+
+#[test]
+fn counts_whole_frames() {
+    use crate::config::Config;
+
+    // 6001 6002 6003 6004 1208: four loads, then spin
+    let mut chip8 = Config::new().instructions_per_frame(4).build();
+    chip8
+        .load_rom(vec![
+            0x60, 0x01, 0x60, 0x02, 0x60, 0x03, 0x60, 0x04, 0x12, 0x08,
+        ])
+        .unwrap();
+    let mut debugger = Debugger::new();
+    debugger.add_breakpoint(0x204);
+    assert_eq!(
+        debugger.run_frame(&mut chip8),
+        Some(StopReason::Breakpoint(0x204))
+    );
+    assert!(debugger.is_mid_frame());
+    assert_eq!(debugger.get_frame_count(), 0);
+    assert_eq!(debugger.run_frame(&mut chip8), None);
+    assert!(!debugger.is_mid_frame());
+    assert_eq!(debugger.get_frame_count(), 1);
+
+    debugger.set_frames_only(true);
+    let output = debugger.execute_command(&mut chip8, "step");
+    assert!(output.text.starts_with("stepping is disabled"));
+    assert_eq!(chip8.get_program_counter(), 0x208);
+}
+
+#[test]
+fn steps_over_calls_at_the_top_of_memory() {
+    use crate::quirks::Quirks;
+
+    // FFFE: 2300  call 300, returning to 0000
+    // 0300: 00EE  return
+    let mut chip8 = Chip8::new(Quirks::XO_CHIP);
+    chip8.set_memory(0xFFFE, &[0x23, 0x00]);
+    chip8.set_memory(0x300, &[0x00, 0xEE]);
+    chip8.set_program_counter(0xFFFE);
+    let mut debugger = Debugger::new();
+    assert_eq!(debugger.step_over(&mut chip8, 100), StopReason::Stepped);
+    assert_eq!(chip8.get_program_counter(), 0x0000);
+    assert!(chip8.get_stack().is_empty());
+
+    // A breakpoint inside the call stops the step over there.
+    chip8.set_program_counter(0xFFFE);
+    debugger.add_breakpoint(0x300);
+    assert_eq!(
+        debugger.step_over(&mut chip8, 100),
+        StopReason::Breakpoint(0x300)
+    );
+    assert_eq!(chip8.get_stack(), &[0xFFFE]);
+}
+
+#[test]
+fn dumps_memory() {
+    use crate::quirks::Quirks;
+
+    let mut chip8 = Chip8::new(Quirks::SUPER_CHIP);
+    chip8.set_memory(0x300, &[0xDE, 0xAD, 0xBE, 0xEF, 1, 2, 3, 4, 5]);
+    let mut debugger = Debugger::new();
+    let output = debugger.execute_command(&mut chip8, "mem 300 9");
+    assert_eq!(output.text, "300: DE AD BE EF 01 02 03 04\n308: 05");
+    let output = debugger.execute_command(&mut chip8, "m $FFE 100");
+    assert_eq!(output.text, "FFE: 00 00");
+    let output = debugger.execute_command(&mut chip8, "m 300 18446744073709551615");
+    assert!(output.text.starts_with("300: DE AD"));
+    assert!(output.text.ends_with("FF8: 00 00 00 00 00 00 00 00"));
+    let output = debugger.execute_command(&mut chip8, "m 1000");
+    assert_eq!(output.text, "1000 is outside of memory");
+    let output = debugger.execute_command(&mut chip8, "m 300 lots");
+    assert_eq!(output.text, "invalid memory range '300 lots'");
+}
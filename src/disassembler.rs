@@ -0,0 +1,486 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::chip8::{self, Address, Chip8, Instruction, EXTENDED_MEMORY_SIZE};
+use crate::error::Chip8Error;
+
+const PROGRAM_START: Address = chip8::PROGRAM_START as Address;
+
+/// How instructions are spelled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Syntax {
+    /// Cowgod's technical reference mnemonics: `LD V0, 0x05`.
+    Classic,
+    /// Octo's assembly language: `v0 := 0x05`.
+    Octo,
+}
+
+impl Syntax {
+    pub fn from_name(name: &str) -> Option<Syntax> {
+        return match name.to_ascii_lowercase().as_str() {
+            "classic" => Some(Syntax::Classic),
+            "octo" => Some(Syntax::Octo),
+            _ => None,
+        };
+    }
+}
+
+/// An instruction found by following the control flow of a ROM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodedInstruction {
+    pub address: Address,
+    pub opcode: u16,
+    pub instruction: Instruction,
+    /// The address word of an XO-CHIP F000 NNNN, which is four bytes long.
+    pub long_address: Option<Address>,
+}
+
+impl DecodedInstruction {
+    pub fn length(&self) -> usize {
+        return if self.long_address.is_some() { 4 } else { 2 };
+    }
+}
+
+/// A ROM split into code and data, with generated labels.
+pub struct Disassembly {
+    rom: Vec<u8>,
+    code: BTreeMap<Address, DecodedInstruction>,
+    labels: BTreeMap<Address, String>,
+}
+
+impl Disassembly {
+    /// Walks every path reachable from the entry point, so bytes that are
+    /// only ever read as sprites or tables are left as data. Fails for a
+    /// ROM too large for even XO-CHIP's 64 KiB of memory.
+    pub fn new(rom: &[u8]) -> Result<Disassembly, Chip8Error> {
+        let capacity = EXTENDED_MEMORY_SIZE - PROGRAM_START as usize;
+        if rom.len() > capacity {
+            return Err(Chip8Error::RomTooLarge {
+                size: rom.len(),
+                capacity,
+            });
+        }
+        let mut code = BTreeMap::new();
+        let mut jump_targets = BTreeSet::new();
+        let mut call_targets = BTreeSet::new();
+        let mut data_targets = BTreeSet::new();
+
+        let mut pending = vec![PROGRAM_START];
+        while let Some(address) = pending.pop() {
+            if code.contains_key(&address) {
+                continue;
+            }
+            let decoded = match decode(rom, address) {
+                Some(decoded) => decoded,
+                None => continue,
+            };
+            code.insert(address, decoded);
+
+            // Nothing follows an instruction at the very end of memory.
+            let next = address.checked_add(decoded.length() as Address);
+            match decoded.instruction {
+                Instruction::Jump(target) => {
+                    jump_targets.insert(target);
+                    pending.push(target);
+                }
+                // The offset is only known at run time, so all we can do is
+                // assume the first entry of the jump table is code.
+                Instruction::LongJump(target) => {
+                    jump_targets.insert(target);
+                    pending.push(target);
+                }
+                Instruction::Call(target) => {
+                    call_targets.insert(target);
+                    pending.push(target);
+                    pending.extend(next);
+                }
+                Instruction::Return | Instruction::Exit => {}
+                Instruction::SkipEqualK(..)
+                | Instruction::SkipNotEqualK(..)
+                | Instruction::SkipEqual(..)
+                | Instruction::SkipNotEqual(..)
+                | Instruction::SkipPressed(..)
+                | Instruction::SkipNotPressed(..) => {
+                    if let Some(next) = next {
+                        pending.push(next);
+                        let skipped = decode(rom, next).map_or(2, |decoded| decoded.length());
+                        pending.extend(next.checked_add(skipped as Address));
+                    }
+                }
+                Instruction::LoadI(target) => {
+                    data_targets.insert(target);
+                    pending.extend(next);
+                }
+                _ => {
+                    if let Some(target) = decoded.long_address {
+                        data_targets.insert(target);
+                    }
+                    pending.extend(next);
+                }
+            }
+        }
+
+        let mut disassembly = Disassembly {
+            rom: rom.to_vec(),
+            code,
+            labels: BTreeMap::new(),
+        };
+        let named_targets = [
+            (data_targets, "data"),
+            (jump_targets, "label"),
+            (call_targets, "sub"),
+        ];
+        for (targets, prefix) in named_targets {
+            for target in targets {
+                if disassembly.can_label(target) {
+                    disassembly
+                        .labels
+                        .insert(target, format!("{prefix}_{target:03x}"));
+                }
+            }
+        }
+        disassembly
+            .labels
+            .insert(PROGRAM_START, String::from("main"));
+        return Ok(disassembly);
+    }
+
+    pub fn instructions(&self) -> impl Iterator<Item = &DecodedInstruction> {
+        return self.code.values();
+    }
+
+    pub fn labels(&self) -> &BTreeMap<Address, String> {
+        return &self.labels;
+    }
+
+    /// One past the last address of the ROM, which can be 0x10000.
+    pub fn end(&self) -> usize {
+        return PROGRAM_START as usize + self.rom.len();
+    }
+
+    /// Labels can only go on addresses inside the ROM that do not fall in
+    /// the middle of an instruction.
+    fn can_label(&self, address: Address) -> bool {
+        if address < PROGRAM_START || address as usize >= self.end() {
+            return false;
+        }
+        return !self.code.values().any(|decoded| {
+            address > decoded.address
+                && (address as usize) < decoded.address as usize + decoded.length()
+        });
+    }
+
+    /// Whether the instruction at `address` can be printed as an
+    /// instruction, rather than as bytes, without swallowing a label or the
+    /// start of another instruction.
+    fn emit_as_code(&self, address: Address) -> Option<&DecodedInstruction> {
+        let decoded = self.code.get(&address)?;
+        let end = address as usize + decoded.length();
+        if end > self.end() {
+            return None;
+        }
+        let overlaps = ((address as usize + 1)..end).any(|inner| {
+            self.labels.contains_key(&(inner as Address))
+                || self.code.contains_key(&(inner as Address))
+        });
+        return if overlaps { None } else { Some(decoded) };
+    }
+
+    fn byte(&self, address: Address) -> u8 {
+        return self.rom[(address - PROGRAM_START) as usize];
+    }
+
+    fn label_for(&self, address: Address) -> String {
+        return match self.labels.get(&address) {
+            Some(label) => label.clone(),
+            None => format_address(address),
+        };
+    }
+
+    /// An annotated listing with addresses and raw opcodes.
+    pub fn listing(&self, syntax: Syntax) -> String {
+        let mut lines = Vec::new();
+        let mut next = PROGRAM_START as usize;
+        while next < self.end() {
+            let address = next as Address;
+            if let Some(label) = self.labels.get(&address) {
+                lines.push(match syntax {
+                    Syntax::Classic => format!("{label}:"),
+                    Syntax::Octo => format!(": {label}"),
+                });
+            }
+            match self.emit_as_code(address) {
+                Some(decoded) => {
+                    let text =
+                        format_instruction(decoded, syntax, &|target| self.label_for(target));
+                    let raw = match decoded.long_address {
+                        Some(long) => format!("{:04X} {long:04X}", decoded.opcode),
+                        None => format!("{:04X}     ", decoded.opcode),
+                    };
+                    lines.push(format!("{address:03X}: {raw}  {text}"));
+                    next += decoded.length();
+                }
+                None => {
+                    let byte = self.byte(address);
+                    lines.push(format!(
+                        "{address:03X}: {byte:02X}         {}",
+                        format_byte(byte, syntax)
+                    ));
+                    next += 1;
+                }
+            }
+        }
+        return lines.join("\n") + "\n";
+    }
+
+    /// Octo source that assembles back to exactly the original ROM.
+    pub fn octo_source(&self) -> String {
+        let mut lines = Vec::new();
+        let mut data: Vec<String> = Vec::new();
+        let mut next = PROGRAM_START as usize;
+        while next < self.end() {
+            let address = next as Address;
+            let label = self.labels.get(&address);
+            let decoded = self.emit_as_code(address);
+            if !data.is_empty() && (label.is_some() || decoded.is_some() || data.len() == 8) {
+                lines.push(format!("\t{}", data.join(" ")));
+                data.clear();
+            }
+            if let Some(label) = label {
+                lines.push(format!(": {label}"));
+            }
+            match decoded {
+                Some(decoded) => {
+                    let text =
+                        format_instruction(decoded, Syntax::Octo, &|target| self.label_for(target));
+                    lines.push(format!("\t{text}"));
+                    next += decoded.length();
+                }
+                None => {
+                    data.push(format!("0x{:02X}", self.byte(address)));
+                    next += 1;
+                }
+            }
+        }
+        if !data.is_empty() {
+            lines.push(format!("\t{}", data.join(" ")));
+        }
+        return lines.join("\n") + "\n";
+    }
+}
+
+/// Decodes the instruction at `address`, which must lie within the ROM.
+fn decode(rom: &[u8], address: Address) -> Option<DecodedInstruction> {
+    let offset = (address as usize).checked_sub(PROGRAM_START as usize)?;
+    let bytes = rom.get(offset..offset + 2)?;
+    let opcode = u16::from_be_bytes([bytes[0], bytes[1]]);
+    let instruction = Chip8::parse_instruction(opcode)?;
+    let long_address = match instruction {
+        Instruction::LoadLongI => {
+            let bytes = rom.get(offset + 2..offset + 4)?;
+            Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+        }
+        _ => None,
+    };
+    return Some(DecodedInstruction {
+        address,
+        opcode,
+        instruction,
+        long_address,
+    });
+}
+
+fn format_address(address: Address) -> String {
+    return format!("0x{address:03X}");
+}
+
+fn format_byte(byte: u8, syntax: Syntax) -> String {
+    return match syntax {
+        Syntax::Classic => format!("DB 0x{byte:02X}"),
+        Syntax::Octo => format!("0x{byte:02X}"),
+    };
+}
+
+/// Spells an instruction on its own, with plain numeric addresses.
+pub fn mnemonic(instruction: &Instruction, syntax: Syntax) -> String {
+    let decoded = DecodedInstruction {
+        address: 0,
+        opcode: 0,
+        instruction: *instruction,
+        long_address: None,
+    };
+    return format_instruction(&decoded, syntax, &|target| format_address(target));
+}
+
+fn format_instruction(
+    decoded: &DecodedInstruction,
+    syntax: Syntax,
+    label: &dyn Fn(Address) -> String,
+) -> String {
+    let long = decoded.long_address.unwrap_or(0);
+    return match syntax {
+        Syntax::Classic => format_classic(&decoded.instruction, long, label),
+        Syntax::Octo => format_octo(&decoded.instruction, long, label),
+    };
+}
+
+fn format_classic(
+    instruction: &Instruction,
+    long: Address,
+    label: &dyn Fn(Address) -> String,
+) -> String {
+    return match *instruction {
+        Instruction::ClearDisplay => String::from("CLS"),
+        Instruction::Return => String::from("RET"),
+        Instruction::ScrollDown(n) => format!("SCD {n}"),
+        Instruction::ScrollUp(n) => format!("SCU {n}"),
+        Instruction::ScrollRight => String::from("SCR"),
+        Instruction::ScrollLeft => String::from("SCL"),
+        Instruction::Exit => String::from("EXIT"),
+        Instruction::LowRes => String::from("LOW"),
+        Instruction::HighRes => String::from("HIGH"),
+        Instruction::Jump(address) => format!("JP {}", label(address)),
+        Instruction::Call(address) => format!("CALL {}", label(address)),
+        Instruction::SkipEqualK(x, nn) => format!("SE V{x:X}, 0x{nn:02X}"),
+        Instruction::SkipNotEqualK(x, nn) => format!("SNE V{x:X}, 0x{nn:02X}"),
+        Instruction::SkipEqual(x, y) => format!("SE V{x:X}, V{y:X}"),
+        Instruction::SaveRange(x, y) => format!("SAVE V{x:X}, V{y:X}"),
+        Instruction::LoadRange(x, y) => format!("LOAD V{x:X}, V{y:X}"),
+        Instruction::SetK(x, nn) => format!("LD V{x:X}, 0x{nn:02X}"),
+        Instruction::AddK(x, nn) => format!("ADD V{x:X}, 0x{nn:02X}"),
+        Instruction::Set(x, y) => format!("LD V{x:X}, V{y:X}"),
+        Instruction::Or(x, y) => format!("OR V{x:X}, V{y:X}"),
+        Instruction::And(x, y) => format!("AND V{x:X}, V{y:X}"),
+        Instruction::XOr(x, y) => format!("XOR V{x:X}, V{y:X}"),
+        Instruction::Add(x, y) => format!("ADD V{x:X}, V{y:X}"),
+        Instruction::Sub(x, y) => format!("SUB V{x:X}, V{y:X}"),
+        Instruction::ShiftRight(x, y) => format!("SHR V{x:X}, V{y:X}"),
+        Instruction::SubInv(x, y) => format!("SUBN V{x:X}, V{y:X}"),
+        Instruction::ShiftLeft(x, y) => format!("SHL V{x:X}, V{y:X}"),
+        Instruction::SkipNotEqual(x, y) => format!("SNE V{x:X}, V{y:X}"),
+        Instruction::LoadI(address) => format!("LD I, {}", label(address)),
+        Instruction::LoadLongI => format!("LD I, LONG {}", label(long)),
+        Instruction::LongJump(address) => format!("JP V0, {}", label(address)),
+        Instruction::Rand(x, nn) => format!("RND V{x:X}, 0x{nn:02X}"),
+        Instruction::Draw(x, y, n) => format!("DRW V{x:X}, V{y:X}, {n}"),
+        Instruction::SkipPressed(x) => format!("SKP V{x:X}"),
+        Instruction::SkipNotPressed(x) => format!("SKNP V{x:X}"),
+        Instruction::SelectPlanes(n) => format!("PLANE {n}"),
+        Instruction::LoadAudioPattern => String::from("AUDIO"),
+        Instruction::GetTimer(x) => format!("LD V{x:X}, DT"),
+        Instruction::WaitKey(x) => format!("LD V{x:X}, K"),
+        Instruction::SetTimer(x) => format!("LD DT, V{x:X}"),
+        Instruction::SetSoundTimer(x) => format!("LD ST, V{x:X}"),
+        Instruction::AddToI(x) => format!("ADD I, V{x:X}"),
+        Instruction::LoadHexGlyph(x) => format!("LD F, V{x:X}"),
+        Instruction::LoadBigHexGlyph(x) => format!("LD HF, V{x:X}"),
+        Instruction::StoreBCD(x) => format!("LD B, V{x:X}"),
+        Instruction::SetPitch(x) => format!("PITCH V{x:X}"),
+        Instruction::StoreRegisters(x) => format!("LD [I], V{x:X}"),
+        Instruction::LoadRegisters(x) => format!("LD V{x:X}, [I]"),
+        Instruction::StoreFlags(x) => format!("LD R, V{x:X}"),
+        Instruction::LoadFlags(x) => format!("LD V{x:X}, R"),
+    };
+}
+
+/// Octo spells skips as the condition under which the *next* instruction
+/// runs, so each skip is written as the opposite comparison.
+fn format_octo(
+    instruction: &Instruction,
+    long: Address,
+    label: &dyn Fn(Address) -> String,
+) -> String {
+    return match *instruction {
+        Instruction::ClearDisplay => String::from("clear"),
+        Instruction::Return => String::from("return"),
+        Instruction::ScrollDown(n) => format!("scroll-down {n}"),
+        Instruction::ScrollUp(n) => format!("scroll-up {n}"),
+        Instruction::ScrollRight => String::from("scroll-right"),
+        Instruction::ScrollLeft => String::from("scroll-left"),
+        Instruction::Exit => String::from("exit"),
+        Instruction::LowRes => String::from("lores"),
+        Instruction::HighRes => String::from("hires"),
+        Instruction::Jump(address) => format!("jump {}", label(address)),
+        Instruction::Call(address) => format!(":call {}", label(address)),
+        Instruction::SkipEqualK(x, nn) => format!("if v{x:x} != 0x{nn:02X} then"),
+        Instruction::SkipNotEqualK(x, nn) => format!("if v{x:x} == 0x{nn:02X} then"),
+        Instruction::SkipEqual(x, y) => format!("if v{x:x} != v{y:x} then"),
+        Instruction::SaveRange(x, y) => format!("save v{x:x} - v{y:x}"),
+        Instruction::LoadRange(x, y) => format!("load v{x:x} - v{y:x}"),
+        Instruction::SetK(x, nn) => format!("v{x:x} := 0x{nn:02X}"),
+        Instruction::AddK(x, nn) => format!("v{x:x} += 0x{nn:02X}"),
+        Instruction::Set(x, y) => format!("v{x:x} := v{y:x}"),
+        Instruction::Or(x, y) => format!("v{x:x} |= v{y:x}"),
+        Instruction::And(x, y) => format!("v{x:x} &= v{y:x}"),
+        Instruction::XOr(x, y) => format!("v{x:x} ^= v{y:x}"),
+        Instruction::Add(x, y) => format!("v{x:x} += v{y:x}"),
+        Instruction::Sub(x, y) => format!("v{x:x} -= v{y:x}"),
+        Instruction::ShiftRight(x, y) => format!("v{x:x} >>= v{y:x}"),
+        Instruction::SubInv(x, y) => format!("v{x:x} =- v{y:x}"),
+        Instruction::ShiftLeft(x, y) => format!("v{x:x} <<= v{y:x}"),
+        Instruction::SkipNotEqual(x, y) => format!("if v{x:x} == v{y:x} then"),
+        Instruction::LoadI(address) => format!("i := {}", label(address)),
+        Instruction::LoadLongI => format!("i := long {}", label(long)),
+        Instruction::LongJump(address) => format!("jump0 {}", label(address)),
+        Instruction::Rand(x, nn) => format!("v{x:x} := random 0x{nn:02X}"),
+        Instruction::Draw(x, y, n) => format!("sprite v{x:x} v{y:x} {n}"),
+        Instruction::SkipPressed(x) => format!("if v{x:x} -key then"),
+        Instruction::SkipNotPressed(x) => format!("if v{x:x} key then"),
+        Instruction::SelectPlanes(n) => format!("plane {n}"),
+        Instruction::LoadAudioPattern => String::from("audio"),
+        Instruction::GetTimer(x) => format!("v{x:x} := delay"),
+        Instruction::WaitKey(x) => format!("v{x:x} := key"),
+        Instruction::SetTimer(x) => format!("delay := v{x:x}"),
+        Instruction::SetSoundTimer(x) => format!("buzzer := v{x:x}"),
+        Instruction::AddToI(x) => format!("i += v{x:x}"),
+        Instruction::LoadHexGlyph(x) => format!("i := hex v{x:x}"),
+        Instruction::LoadBigHexGlyph(x) => format!("i := bighex v{x:x}"),
+        Instruction::StoreBCD(x) => format!("bcd v{x:x}"),
+        Instruction::SetPitch(x) => format!("pitch := v{x:x}"),
+        Instruction::StoreRegisters(x) => format!("save v{x:x}"),
+        Instruction::LoadRegisters(x) => format!("load v{x:x}"),
+        Instruction::StoreFlags(x) => format!("saveflags v{x:x}"),
+        Instruction::LoadFlags(x) => format!("loadflags v{x:x}"),
+    };
+}
+
+#[test]
+fn separates_code_from_data() {
+    // 200: A208  i := data_208
+    // 202: D015  sprite v0 v1 5
+    // 204: 2206  :call sub_206
+    // 206: 00EE  return
+    // 208: F0 90 F0 90 90  sprite data
+    let rom = vec![
+        0xA2, 0x08, 0xD0, 0x15, 0x22, 0x06, 0x00, 0xEE, 0xF0, 0x90, 0xF0, 0x90, 0x90,
+    ];
+    let disassembly = Disassembly::new(&rom).unwrap();
+
+    assert_eq!(
+        disassembly.octo_source(),
+        ": main\n\ti := data_208\n\tsprite v0 v1 5\n\t:call sub_206\n: sub_206\n\treturn\n: data_208\n\t0xF0 0x90 0xF0 0x90 0x90\n"
+    );
+    assert!(disassembly
+        .listing(Syntax::Classic)
+        .contains("204: 2206       CALL sub_206"));
+}
+
+#[test]
+fn rejects_oversized_roms() {
+    assert_eq!(
+        Disassembly::new(&vec![0; 66000]).err(),
+        Some(Chip8Error::RomTooLarge {
+            size: 66000,
+            capacity: 0xFE00
+        })
+    );
+
+    // Straight-line code up to a skip in the last word of memory, which has
+    // nowhere to go.
+    let mut rom = [0x60, 0x00].repeat(0x7F00);
+    rom[0xFDFE..].copy_from_slice(&[0x30, 0x00]);
+    let disassembly = Disassembly::new(&rom).unwrap();
+    assert_eq!(disassembly.end(), 0x10000);
+    assert!(disassembly
+        .listing(Syntax::Classic)
+        .ends_with("FFFE: 3000       SE V0, 0x00\n"));
+}
@@ -0,0 +1,264 @@
+use crate::error::StateError;
+use crate::state::{StateReader, StateWriter};
+
+pub const LORES_WIDTH: usize = 64;
+pub const LORES_HEIGHT: usize = 32;
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+
+/// Number of XO-CHIP bitplanes. Each pixel stores one bit per plane, so a
+/// pixel value is an index into a palette of `1 << PLANE_COUNT` colours.
+pub const PLANE_COUNT: usize = 2;
+const ALL_PLANES: u8 = (1 << PLANE_COUNT) - 1;
+
+/// The framebuffer, sized for the SUPER-CHIP high resolution mode.
+///
+/// In low resolution only the top-left 64x32 pixels are in use, so the
+/// coordinates a ROM draws to are always the coordinates stored here and
+/// the frontend scales according to `width()`/`height()`.
+///
+/// Drawing, clearing and scrolling only touch the planes selected with
+/// `select_planes`. CHIP-8 and SUPER-CHIP programs never change the
+/// selection and so only ever use the first plane.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct DisplayBuffer {
+    pixels: [[u8; HIRES_WIDTH]; HIRES_HEIGHT],
+    hires: bool,
+    planes: u8,
+}
+
+impl DisplayBuffer {
+    pub fn new() -> DisplayBuffer {
+        return DisplayBuffer {
+            pixels: [[0; HIRES_WIDTH]; HIRES_HEIGHT],
+            hires: false,
+            planes: 1,
+        };
+    }
+
+    pub fn width(&self) -> usize {
+        return if self.hires { HIRES_WIDTH } else { LORES_WIDTH };
+    }
+
+    pub fn height(&self) -> usize {
+        return if self.hires {
+            HIRES_HEIGHT
+        } else {
+            LORES_HEIGHT
+        };
+    }
+
+    pub fn is_hires(&self) -> bool {
+        return self.hires;
+    }
+
+    /// Whether the pixel is lit on any plane.
+    pub fn get_pixel(&self, x: usize, y: usize) -> bool {
+        return self.pixels[y][x] != 0;
+    }
+
+    /// The palette index of the pixel, with bit N set when plane N is lit.
+    pub fn get_colour(&self, x: usize, y: usize) -> u8 {
+        return self.pixels[y][x];
+    }
+
+    pub fn selected_planes(&self) -> u8 {
+        return self.planes;
+    }
+
+    pub fn select_planes(&mut self, planes: u8) {
+        self.planes = planes & ALL_PLANES;
+    }
+
+    /// Number of sprites a draw consumes, one per selected plane.
+    pub fn selected_plane_count(&self) -> usize {
+        return self.planes.count_ones() as usize;
+    }
+
+    /// Switches resolution, clearing every plane as SUPER-CHIP and XO-CHIP do.
+    pub fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.pixels = [[0; HIRES_WIDTH]; HIRES_HEIGHT];
+    }
+
+    pub fn clear(&mut self) {
+        for row in self.pixels.iter_mut() {
+            for pixel in row.iter_mut() {
+                *pixel &= !self.planes;
+            }
+        }
+    }
+
+    /// XORs a sprite onto the screen and returns whether any lit pixel was
+    /// turned off. Sprites are 8 pixels wide, or 16 when `wide` is set, in
+    /// which case each row takes two bytes. With several planes selected the
+    /// sprite data holds one sprite per plane, back to back.
+    pub fn draw_sprite(
+        &mut self,
+        x: usize,
+        y: usize,
+        sprite: &[u8],
+        wide: bool,
+        clip: bool,
+    ) -> bool {
+        let planes: Vec<u8> = (0..PLANE_COUNT)
+            .map(|plane| 1 << plane)
+            .filter(|mask| self.planes & mask != 0)
+            .collect();
+        if planes.is_empty() {
+            return false;
+        }
+
+        let mut collision = false;
+        let length = sprite.len() / planes.len();
+        for (mask, data) in planes.into_iter().zip(sprite.chunks(length)) {
+            collision |= self.draw_plane(x, y, data, wide, clip, mask);
+        }
+        return collision;
+    }
+
+    fn draw_plane(
+        &mut self,
+        x: usize,
+        y: usize,
+        sprite: &[u8],
+        wide: bool,
+        clip: bool,
+        mask: u8,
+    ) -> bool {
+        let (width, height) = (self.width(), self.height());
+        let bytes_per_row = if wide { 2 } else { 1 };
+        // The starting position always wraps; `clip` only decides what happens
+        // to the parts of the sprite that run off the edge.
+        let (x, y) = (x % width, y % height);
+        let mut collision = false;
+
+        for (i, row) in sprite.chunks(bytes_per_row).enumerate() {
+            let bits = row
+                .iter()
+                .fold(0u16, |bits, byte| (bits << 8) | *byte as u16);
+            let row_width = bytes_per_row * 8;
+            for j in 0..row_width {
+                if (bits >> (row_width - 1 - j)) & 1 == 0 {
+                    continue;
+                }
+                let (mut column, mut row) = (x + j, y + i);
+                if column >= width || row >= height {
+                    if clip {
+                        continue;
+                    }
+                    column %= width;
+                    row %= height;
+                }
+                collision |= self.pixels[row][column] & mask != 0;
+                self.pixels[row][column] ^= mask;
+            }
+        }
+        return collision;
+    }
+
+    /// Renders the visible screen as one line per row, with `.` for unlit
+    /// pixels and `#`, `+` and `*` for the three XO-CHIP plane combinations.
+    pub fn to_text(&self) -> String {
+        let mut text = String::with_capacity((self.width() + 1) * self.height());
+        for y in 0..self.height() {
+            for x in 0..self.width() {
+                text.push(['.', '#', '+', '*'][self.get_colour(x, y) as usize]);
+            }
+            text.push('\n');
+        }
+        return text;
+    }
+
+    pub fn scroll_up(&mut self, rows: usize) {
+        let (width, height) = (self.width(), self.height());
+        for y in 0..height {
+            for x in 0..width {
+                let source = if y + rows < height {
+                    self.pixels[y + rows][x]
+                } else {
+                    0
+                };
+                self.move_pixel(x, y, source);
+            }
+        }
+    }
+
+    pub fn scroll_down(&mut self, rows: usize) {
+        let (width, height) = (self.width(), self.height());
+        for y in (0..height).rev() {
+            for x in 0..width {
+                let source = if y >= rows {
+                    self.pixels[y - rows][x]
+                } else {
+                    0
+                };
+                self.move_pixel(x, y, source);
+            }
+        }
+    }
+
+    pub fn scroll_left(&mut self, columns: usize) {
+        let (width, height) = (self.width(), self.height());
+        for y in 0..height {
+            for x in 0..width {
+                let source = if x + columns < width {
+                    self.pixels[y][x + columns]
+                } else {
+                    0
+                };
+                self.move_pixel(x, y, source);
+            }
+        }
+    }
+
+    pub fn scroll_right(&mut self, columns: usize) {
+        let (width, height) = (self.width(), self.height());
+        for y in 0..height {
+            for x in (0..width).rev() {
+                let source = if x >= columns {
+                    self.pixels[y][x - columns]
+                } else {
+                    0
+                };
+                self.move_pixel(x, y, source);
+            }
+        }
+    }
+
+    pub(crate) fn write_state(&self, writer: &mut StateWriter) {
+        writer.bool(self.hires);
+        writer.u8(self.planes);
+        writer.packed(&self.pixels.concat());
+    }
+
+    pub(crate) fn read_state(reader: &mut StateReader) -> Result<DisplayBuffer, StateError> {
+        let mut display = DisplayBuffer::new();
+        display.hires = reader.bool()?;
+        display.planes = reader.u8()?;
+        let pixels = reader.packed(HIRES_WIDTH * HIRES_HEIGHT)?;
+        if display.planes & !ALL_PLANES != 0 {
+            return Err(StateError::Invalid("plane selection"));
+        }
+        if pixels.len() != HIRES_WIDTH * HIRES_HEIGHT
+            || pixels.iter().any(|pixel| pixel & !ALL_PLANES != 0)
+        {
+            return Err(StateError::Invalid("framebuffer"));
+        }
+        for (row, data) in display.pixels.iter_mut().zip(pixels.chunks(HIRES_WIDTH)) {
+            row.copy_from_slice(data);
+        }
+        return Ok(display);
+    }
+
+    /// Replaces the selected planes of a pixel with those of `source`.
+    fn move_pixel(&mut self, x: usize, y: usize, source: u8) {
+        self.pixels[y][x] = (self.pixels[y][x] & !self.planes) | (source & self.planes);
+    }
+}
+
+impl Default for DisplayBuffer {
+    fn default() -> DisplayBuffer {
+        return DisplayBuffer::new();
+    }
+}
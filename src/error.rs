@@ -0,0 +1,123 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::chip8::Address;
+
+/// Everything that can stop the interpreter from making progress.
+///
+/// Errors raised while executing carry the program counter and opcode of the
+/// offending instruction so a frontend can show where the ROM went wrong.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Chip8Error {
+    ProgramCounterOutOfBounds {
+        program_counter: Address,
+    },
+    UnknownOpcode {
+        program_counter: Address,
+        opcode: u16,
+    },
+    StackUnderflow {
+        program_counter: Address,
+        opcode: u16,
+    },
+    StackOverflow {
+        program_counter: Address,
+        opcode: u16,
+    },
+    MemoryOutOfBounds {
+        program_counter: Address,
+        opcode: u16,
+        address: usize,
+    },
+    RomTooLarge {
+        size: usize,
+        capacity: usize,
+    },
+    /// A subroutine run with `Chip8::call` did not return in time.
+    CycleLimit {
+        program_counter: Address,
+        limit: usize,
+    },
+    /// A subroutine run with `Chip8::call` exited with 00FD.
+    Exited {
+        program_counter: Address,
+    },
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Chip8Error::ProgramCounterOutOfBounds { program_counter } => write!(
+                f,
+                "program counter {program_counter:#05X} is outside of memory"
+            ),
+            Chip8Error::UnknownOpcode {
+                program_counter,
+                opcode,
+            } => write!(f, "unknown opcode {opcode:#06X} at {program_counter:#05X}"),
+            Chip8Error::StackUnderflow {
+                program_counter,
+                opcode,
+            } => write!(
+                f,
+                "return with an empty stack ({opcode:#06X} at {program_counter:#05X})"
+            ),
+            Chip8Error::StackOverflow {
+                program_counter,
+                opcode,
+            } => write!(
+                f,
+                "call with a full stack ({opcode:#06X} at {program_counter:#05X})"
+            ),
+            Chip8Error::MemoryOutOfBounds {
+                program_counter,
+                opcode,
+                address,
+            } => write!(
+                f,
+                "memory access out of bounds at {address:#06X} ({opcode:#06X} at {program_counter:#05X})"
+            ),
+            Chip8Error::RomTooLarge { size, capacity } => write!(
+                f,
+                "rom is {size} bytes but only {capacity} bytes of memory are available"
+            ),
+            Chip8Error::CycleLimit {
+                program_counter,
+                limit,
+            } => write!(
+                f,
+                "no return within {limit} cycles, stopped at {program_counter:#05X}"
+            ),
+            Chip8Error::Exited { program_counter } => {
+                write!(f, "exited at {program_counter:#05X} instead of returning")
+            }
+        }
+    }
+}
+
+impl Error for Chip8Error {}
+
+/// Why a save state could not be loaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateError {
+    NotASaveState,
+    UnsupportedVersion(u8),
+    Truncated,
+    /// A field held a value the interpreter could never have produced.
+    Invalid(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::NotASaveState => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(version) => {
+                write!(f, "unsupported save state version {version}")
+            }
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::Invalid(what) => write!(f, "save state has an invalid {what}"),
+        }
+    }
+}
+
+impl Error for StateError {}
@@ -0,0 +1,91 @@
+//! Gamepads, read through gilrs since the window does not report them.
+//!
+//! Buttons are named by where they sit on the pad rather than what they are
+//! labelled, so a binding means the same on every make of controller: the
+//! face buttons are `South`, `East`, `West` and `North`, with `South` being
+//! A on an Xbox pad and cross on a PlayStation one.
+
+use gilrs::{Button, EventType, Gilrs};
+
+/// The name bindings files use for `button`, or `None` for the ones the pad
+/// could not identify.
+pub fn button_name(button: Button) -> Option<&'static str> {
+    return match button {
+        Button::South => Some("South"),
+        Button::East => Some("East"),
+        Button::West => Some("West"),
+        Button::North => Some("North"),
+        Button::C => Some("C"),
+        Button::Z => Some("Z"),
+        Button::LeftTrigger => Some("LeftShoulder"),
+        Button::RightTrigger => Some("RightShoulder"),
+        Button::LeftTrigger2 => Some("LeftTrigger"),
+        Button::RightTrigger2 => Some("RightTrigger"),
+        Button::Select => Some("Select"),
+        Button::Start => Some("Start"),
+        Button::Mode => Some("Mode"),
+        Button::LeftThumb => Some("LeftStick"),
+        Button::RightThumb => Some("RightStick"),
+        Button::DPadUp => Some("DPadUp"),
+        Button::DPadDown => Some("DPadDown"),
+        Button::DPadLeft => Some("DPadLeft"),
+        Button::DPadRight => Some("DPadRight"),
+        Button::Unknown => None,
+    };
+}
+
+/// The host input pressed (`true`) or released (`false`) by a gamepad event.
+pub fn input_change(event: &EventType) -> Option<(String, bool)> {
+    let (button, pressed) = match event {
+        EventType::ButtonPressed(button, _) => (*button, true),
+        EventType::ButtonReleased(button, _) => (*button, false),
+        _ => return None,
+    };
+    return button_name(button).map(|name| (name.to_string(), pressed));
+}
+
+/// Every connected gamepad, and any plugged in later.
+pub struct Gamepads {
+    gilrs: Gilrs,
+}
+
+impl Gamepads {
+    pub fn open() -> Result<Gamepads, String> {
+        let gilrs = Gilrs::new().map_err(|error| format!("gamepads disabled: {error}"))?;
+        return Ok(Gamepads { gilrs });
+    }
+
+    /// The host inputs pressed and released since the last poll, in order.
+    /// Buttons on different pads share names, so two players can hold the
+    /// same key.
+    pub fn poll(&mut self) -> Vec<(String, bool)> {
+        let mut changes = Vec::new();
+        while let Some(event) = self.gilrs.next_event() {
+            changes.extend(input_change(&event.event));
+        }
+        return changes;
+    }
+}
+
+#[test]
+fn buttons_press_keys() {
+    use crate::bindings::{HostInputs, KeyBindings};
+
+    let mut inputs = HostInputs::new(KeyBindings::default());
+    let mut keys_for = |button: Button, pressed: bool| {
+        let name = button_name(button).unwrap();
+        return if pressed {
+            inputs.press(name)
+        } else {
+            inputs.release(name)
+        };
+    };
+    assert_eq!(keys_for(Button::DPadUp, true), vec![0x5]);
+    assert_eq!(keys_for(Button::DPadLeft, true), vec![0x7]);
+    assert_eq!(keys_for(Button::South, true), vec![0x6]);
+    assert_eq!(keys_for(Button::East, true), vec![0x4]);
+    assert_eq!(keys_for(Button::DPadUp, false), vec![0x5]);
+    assert!(keys_for(Button::Start, true).is_empty());
+    assert_eq!(button_name(Button::LeftTrigger), Some("LeftShoulder"));
+    assert_eq!(button_name(Button::Unknown), None);
+}
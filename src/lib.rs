@@ -0,0 +1,39 @@
+#![allow(clippy::needless_return)]
+
+//! A CHIP-8, SUPER-CHIP and XO-CHIP interpreter core.
+//!
+//! The crate has no opinion on windowing, input or audio: a frontend feeds
+//! keys in with `Chip8::set_key`, calls `Chip8::run_frame` at 60 Hz and reads
+//! the screen back from `Chip8::get_display_buffer`.
+
+pub mod assembler;
+pub mod audio;
+pub mod bindings;
+pub mod chip8;
+pub mod config;
+pub mod coverage;
+pub mod debugger;
+pub mod disassembler;
+pub mod display;
+pub mod error;
+#[cfg(feature = "frontend")]
+pub mod gamepad;
+pub mod hash;
+pub mod movie;
+pub mod octo;
+pub mod profile;
+pub mod quirks;
+pub mod random;
+pub mod rewind;
+mod state;
+pub mod testing;
+pub mod trace;
+
+pub use crate::chip8::{Address, Chip8, Instruction, MemoryAccess, RegisterNumber};
+pub use crate::config::Config;
+pub use crate::display::DisplayBuffer;
+pub use crate::error::{Chip8Error, StateError};
+pub use crate::movie::{Movie, MoviePlayer, MovieRecorder};
+pub use crate::quirks::Quirks;
+pub use crate::random::{RandomSource, ScriptedRandom, SeededRandom};
+pub use crate::rewind::RewindBuffer;
@@ -1,6 +1,13 @@
+#![allow(clippy::needless_return)]
+
 use std::env;
 use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
 use std::process::exit;
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
 
 extern crate glutin_window;
 extern crate graphics;
@@ -19,95 +26,462 @@ use piston::EventLoop;
 use piston::Key;
 use piston::PressEvent;
 use piston::ReleaseEvent;
-use piston::Window;
-
-use crate::chip8::Chip8;
 
-pub mod chip8;
+use chip_8_interpreter::audio::{
+    AudioSink, Buzzer, DeviceSink, WavSink, Waveform, DEFAULT_SAMPLE_RATE,
+};
+use chip_8_interpreter::bindings::{BindingsFile, HostInputs};
+use chip_8_interpreter::debugger::{parse_address, Debugger, StopReason};
+use chip_8_interpreter::gamepad::Gamepads;
+use chip_8_interpreter::hash::rom_hash;
+use chip_8_interpreter::octo::read_program;
+use chip_8_interpreter::{
+    Address, Chip8, Config, Movie, MoviePlayer, MovieRecorder, Quirks, RewindBuffer,
+};
 
 const BLACK: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
 const WHITE: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+const LIGHT_GREY: [f32; 4] = [0.67, 0.67, 0.67, 1.0];
+const DARK_GREY: [f32; 4] = [0.33, 0.33, 0.33, 1.0];
+
+/// Colours indexed by the XO-CHIP plane bits of a pixel.
+const PALETTE: [[f32; 4]; 4] = [BLACK, WHITE, LIGHT_GREY, DARK_GREY];
+
+const WINDOW_WIDTH: f64 = 640.0;
+const WINDOW_HEIGHT: f64 = 320.0;
+
+const FRAMES_PER_SECOND: u64 = 60;
+
+/// Breaks into the debugger REPL when running with `--debug`.
+const DEBUG_BREAK_KEY: Key = Key::F12;
+
+/// Held down to play the program backwards, one frame per frame.
+const REWIND_KEY: Key = Key::Backspace;
+/// How far back rewinding can go: 30 seconds.
+const REWIND_FRAMES: usize = 30 * FRAMES_PER_SECOND as usize;
+
+/// F1 to F9 load the numbered save state slots; with shift held they save.
+const SAVE_STATE_KEYS: [Key; 9] = [
+    Key::F1,
+    Key::F2,
+    Key::F3,
+    Key::F4,
+    Key::F5,
+    Key::F6,
+    Key::F7,
+    Key::F8,
+    Key::F9,
+];
+
+struct Options {
+    rom_path: String,
+    quirks: Quirks,
+    instructions_per_frame: usize,
+    seed: Option<u64>,
+    tone: f32,
+    waveform: Waveform,
+    volume: f32,
+    mute: bool,
+    wav_path: Option<String>,
+    debug: bool,
+    start_paused: bool,
+    breakpoints: Vec<Address>,
+    record_movie_path: Option<String>,
+    play_movie_path: Option<String>,
+    bindings_path: Option<String>,
+}
+
+fn parse_value<T: FromStr>(flag: &str, value: Option<String>) -> T {
+    let value = value.unwrap_or_default();
+    match value.parse() {
+        Ok(value) => return value,
+        Err(_) => {
+            println!("invalid value '{value}' for {flag}");
+            exit(1);
+        }
+    }
+}
+
+fn parse_options() -> Options {
+    let mut rom_path = None;
+    let mut options = Options {
+        rom_path: String::new(),
+        quirks: Quirks::default(),
+        instructions_per_frame: Config::DEFAULT_INSTRUCTIONS_PER_FRAME,
+        seed: None,
+        tone: 440.0,
+        waveform: Waveform::Square,
+        volume: 0.25,
+        mute: false,
+        wav_path: None,
+        debug: false,
+        start_paused: false,
+        breakpoints: Vec::new(),
+        record_movie_path: None,
+        play_movie_path: None,
+        bindings_path: None,
+    };
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--quirks" => {
+                let name = args.next().unwrap_or_default();
+                options.quirks = match Quirks::from_name(&name) {
+                    Some(quirks) => quirks,
+                    None => {
+                        let presets: Vec<&str> =
+                            Quirks::PRESETS.iter().map(|(name, _)| *name).collect();
+                        println!("unknown quirk preset '{name}', expected one of: {presets:?}");
+                        exit(1);
+                    }
+                }
+            }
+            "--ipf" => options.instructions_per_frame = parse_value(&arg, args.next()),
+            "--seed" => options.seed = Some(parse_value(&arg, args.next())),
+            "--tone" => options.tone = parse_value(&arg, args.next()),
+            "--waveform" => {
+                let name = args.next().unwrap_or_default();
+                options.waveform = match Waveform::from_name(&name) {
+                    Some(waveform) => waveform,
+                    None => {
+                        println!("unknown waveform '{name}', expected square, sine, triangle or sawtooth");
+                        exit(1);
+                    }
+                }
+            }
+            "--volume" => options.volume = parse_value(&arg, args.next()),
+            "--mute" => options.mute = true,
+            "--wav" => options.wav_path = args.next(),
+            "--record-movie" => options.record_movie_path = args.next(),
+            "--play-movie" => options.play_movie_path = args.next(),
+            "--bindings" => options.bindings_path = args.next(),
+            "--debug" => {
+                options.debug = true;
+                options.start_paused = true;
+            }
+            "--break" => {
+                let address = args.next().unwrap_or_default();
+                match parse_address(&address) {
+                    Some(address) => options.breakpoints.push(address as Address),
+                    None => {
+                        println!("invalid breakpoint address '{address}'");
+                        exit(1);
+                    }
+                }
+                options.debug = true;
+            }
+            _ => rom_path = Some(arg),
+        }
+    }
+
+    options.rom_path = match rom_path {
+        Some(rom_path) => rom_path,
+        None => {
+            println!("rom path not provided");
+            exit(1);
+        }
+    };
+    return options;
+}
+
+/// Reads REPL commands on a separate thread so the window keeps rendering
+/// while the debugger is waiting for input.
+fn spawn_command_reader() -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let sent = match line {
+                Ok(line) => sender.send(line).is_ok(),
+                Err(_) => false,
+            };
+            if !sent {
+                break;
+            }
+        }
+    });
+    return receiver;
+}
+
+fn show_prompt() {
+    print!("(chip8) ");
+    let _ = io::stdout().flush();
+}
+
+fn announce_stop(reason: &StopReason, debugger: &Debugger, chip8: &Chip8) {
+    println!("{reason}");
+    println!("{}", debugger.describe_location(chip8));
+    show_prompt();
+}
 
-fn map_key_to_index(key: Key) -> Option<u8> {
-    match &key {
-        Key::D1 => return Option::Some(0x1),
-        Key::D2 => return Option::Some(0x2),
-        Key::D3 => return Option::Some(0x3),
-        Key::D4 => return Option::Some(0xC),
-        Key::Q => return Option::Some(0x4),
-        Key::W => return Option::Some(0x5),
-        Key::E => return Option::Some(0x6),
-        Key::R => return Option::Some(0xD),
-        Key::A => return Option::Some(0x7),
-        Key::S => return Option::Some(0x8),
-        Key::D => return Option::Some(0x9),
-        Key::F => return Option::Some(0xE),
-        Key::Z => return Option::Some(0xA),
-        Key::X => return Option::Some(0x0),
-        Key::C => return Option::Some(0xB),
-        Key::V => return Option::Some(0xF),
-        _ => {
-            return Option::None;
+/// Reads the bindings file given with `--bindings`, or the user's
+/// `~/.config/chip8/bindings.toml` if there is one, or uses the default layout.
+fn read_bindings(path: Option<&str>) -> BindingsFile {
+    let default_path = env::var("HOME")
+        .map(|home| format!("{home}/.config/chip8/bindings.toml"))
+        .ok()
+        .filter(|path| Path::new(path).exists());
+    let path = match path.map(str::to_string).or(default_path) {
+        Some(path) => path,
+        None => return BindingsFile::default(),
+    };
+    match BindingsFile::load(&path) {
+        Ok(bindings) => return bindings,
+        Err(error) => {
+            println!("{error}");
+            exit(1);
         }
     }
 }
 
+/// Save states live next to the ROM: `game.ch8` keeps slot 1 in `game.state1`.
+fn state_path(rom_path: &str, slot: usize) -> PathBuf {
+    return Path::new(rom_path).with_extension(format!("state{slot}"));
+}
+
+fn save_state(chip8: &Chip8, rom_path: &str, slot: usize) {
+    let path = state_path(rom_path, slot);
+    match fs::write(&path, chip8.save_state()) {
+        Ok(()) => println!("saved state {slot} to {}", path.display()),
+        Err(error) => println!("error saving state to {}: {error}", path.display()),
+    }
+}
+
+/// Returns whether the state was loaded.
+fn load_state(chip8: &mut Chip8, rom_path: &str, slot: usize) -> bool {
+    let path = state_path(rom_path, slot);
+    let result = match fs::read(&path) {
+        Ok(state) => chip8.load_state(&state).map_err(|error| error.to_string()),
+        Err(error) => Err(error.to_string()),
+    };
+    match result {
+        Ok(()) => {
+            println!("loaded state {slot} from {}", path.display());
+            return true;
+        }
+        Err(error) => {
+            println!("error loading state from {}: {error}", path.display());
+            return false;
+        }
+    }
+}
+
+/// Passes a key change from the keyboard to the machine, noting it in the
+/// movie being recorded if there is one.
+fn set_key(chip8: &mut Chip8, recorder: &mut Option<MovieRecorder>, index: u8, pressed: bool) {
+    match recorder {
+        Some(recorder) => recorder.set_key(chip8, index, pressed),
+        None => chip8.set_key(index, pressed),
+    }
+}
+
+fn read_movie(path: &str, rom: &[u8]) -> Movie {
+    let movie = fs::read_to_string(path)
+        .map_err(|error| error.to_string())
+        .and_then(|text| Movie::parse(&text).map_err(|error| error.to_string()));
+    match movie {
+        Ok(movie) if movie.matches_rom(rom) => return movie,
+        Ok(_) => println!("{path} was recorded against a different ROM"),
+        Err(error) => println!("error reading {path}: {error}"),
+    }
+    exit(1);
+}
+
 fn main() {
     let opengl = OpenGL::V3_2;
 
-    let mut window: GlutinWindow = WindowSettings::new("Chip8 Emulator", [640, 320])
-        .graphics_api(opengl)
-        .resizable(false)
-        .samples(1)
-        .vsync(true)
-        .exit_on_esc(true)
-        .build()
-        .unwrap();
+    let mut window: GlutinWindow =
+        WindowSettings::new("Chip8 Emulator", [WINDOW_WIDTH, WINDOW_HEIGHT])
+            .graphics_api(opengl)
+            .resizable(false)
+            .samples(1)
+            .vsync(true)
+            .exit_on_esc(true)
+            .build()
+            .unwrap();
 
     let mut gl = GlGraphics::new(opengl);
 
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        println!("rom path not provided");
+    let options = parse_options();
+    let rom_path = options.rom_path;
+    println!("rom path: {rom_path}");
+
+    let program = match read_program(&rom_path) {
+        Ok(program) => program,
+        Err(error) => {
+            println!("{error}");
+            exit(1);
+        }
+    };
+
+    let bindings = read_bindings(options.bindings_path.as_deref()).for_rom(&rom_hash(&program.rom));
+    let mut inputs = HostInputs::new(bindings);
+    let mut gamepads = match Gamepads::open() {
+        Ok(gamepads) => Some(gamepads),
+        Err(error) => {
+            println!("{error}");
+            None
+        }
+    };
+    let movie = options
+        .play_movie_path
+        .map(|path| read_movie(&path, &program.rom));
+    let mut config = match &movie {
+        Some(movie) => movie.config(),
+        None => Config::new()
+            .quirks(options.quirks)
+            .instructions_per_frame(options.instructions_per_frame),
+    };
+    if let (Some(seed), None) = (options.seed, &movie) {
+        config = config.seed(seed);
+    }
+    let mut chip8 = config.build();
+    println!("seed: {}", chip8.get_seed());
+    let mut recorder = options
+        .record_movie_path
+        .as_ref()
+        .map(|_| MovieRecorder::new(&program.rom, &chip8));
+    let mut player = movie.map(MoviePlayer::new);
+    if let Err(error) = chip8.load_rom(program.rom) {
+        println!("{error}");
         exit(1);
     }
+    let mut halted = false;
 
-    let rom_path = &args[1];
-    println!("rom path: {rom_path}");
-
-    let rom = fs::read(rom_path).expect("error reading rom file");
+    let mut speaker = if options.mute {
+        None
+    } else {
+        match DeviceSink::open() {
+            Ok(sink) => Some(sink),
+            Err(error) => {
+                println!("audio disabled: {error}");
+                None
+            }
+        }
+    };
+    // The speaker and the WAV file share one buffer per frame, so both run
+    // at the device's rate.
+    let sample_rate = speaker
+        .as_ref()
+        .map_or(DEFAULT_SAMPLE_RATE, DeviceSink::get_sample_rate);
+    let mut buzzer = Buzzer::new(sample_rate)
+        .frequency(options.tone)
+        .waveform(options.waveform)
+        .volume(options.volume);
+    let mut recording = options
+        .wav_path
+        .map(|path| WavSink::create(&path, sample_rate).expect("error creating wav file"));
 
-    let mut chip8 = Chip8::new();
-    chip8.load_rom(rom);
+    let mut debugger = if options.debug {
+        let mut debugger = Debugger::new();
+        debugger.set_source_map(program.source_map);
+        for address in options.breakpoints {
+            debugger.add_breakpoint(address);
+        }
+        // A movie counts whole frames, which stepping would break up.
+        debugger.set_frames_only(recorder.is_some() || player.is_some());
+        Some(debugger)
+    } else {
+        None
+    };
+    let commands = if debugger.is_some() {
+        spawn_command_reader()
+    } else {
+        mpsc::channel().1
+    };
+    let mut paused = options.start_paused;
+    if let (true, Some(debugger)) = (paused, &debugger) {
+        println!("paused, type 'help' for debugger commands or 'c' to run");
+        println!("{}", debugger.describe_location(&chip8));
+        show_prompt();
+    }
 
     let mut events = Events::new(EventSettings::new());
-    events.set_ups(500);
+    events.set_ups(FRAMES_PER_SECOND);
     events.set_ups_reset(0);
 
+    let mut shift_held = false;
+    let mut rewinding = false;
+    let mut rewind = RewindBuffer::new(REWIND_FRAMES);
+    let mut pending_keys: Vec<(u8, bool)> = Vec::new();
+
     while let Some(e) = events.next(&mut window) {
         if let Some(Button::Keyboard(key)) = e.press_args() {
-            if let Some(index) = map_key_to_index(key) {
-                chip8.set_key(index, true);
+            if key == Key::LShift || key == Key::RShift {
+                shift_held = true;
+            }
+            // Jumping around in time would desynchronise the movie from the frames it counts.
+            let movie_active = recorder.is_some() || player.is_some();
+            if key == REWIND_KEY {
+                if movie_active {
+                    println!("rewinding is disabled while a movie is recording or playing");
+                } else {
+                    rewinding = true;
+                }
+            }
+            if let Some(slot) = SAVE_STATE_KEYS.iter().position(|slot_key| *slot_key == key) {
+                if shift_held {
+                    save_state(&chip8, &rom_path, slot + 1);
+                } else if movie_active {
+                    println!("loading states is disabled while a movie is recording or playing");
+                } else if load_state(&mut chip8, &rom_path, slot + 1) {
+                    // The state holds the keys held when it was saved.
+                    for (index, pressed) in inputs.get_held_keys().into_iter().enumerate() {
+                        chip8.set_key(index as u8, pressed);
+                    }
+                    halted = false;
+                }
+            }
+            if let (true, Some(debugger)) = (key == DEBUG_BREAK_KEY && !paused, &debugger) {
+                paused = true;
+                println!();
+                announce_stop(&StopReason::Stepped, debugger, &chip8);
             }
         }
         if let Some(Button::Keyboard(key)) = e.release_args() {
-            if let Some(index) = map_key_to_index(key) {
-                chip8.set_key(index, false);
+            if key == Key::LShift || key == Key::RShift {
+                shift_held = false;
+            }
+            if key == REWIND_KEY {
+                rewinding = false;
+            }
+        }
+        let mut changes = match (e.press_args(), e.release_args()) {
+            (Some(Button::Keyboard(key)), _) => vec![(format!("{key:?}"), true)],
+            (_, Some(Button::Keyboard(key))) => vec![(format!("{key:?}"), false)],
+            _ => Vec::new(),
+        };
+        // The window never reports gamepads, so they are polled once a frame.
+        if let (Some(_), Some(gamepads)) = (e.update_args(), gamepads.as_mut()) {
+            changes.extend(gamepads.poll());
+        }
+        for (host_input, pressed) in changes {
+            let keys = if pressed {
+                inputs.press(&host_input)
+            } else {
+                inputs.release(&host_input)
+            };
+            // A recorded key can only change where a frame starts, which is
+            // where playback will press it.
+            if recorder.is_some() {
+                pending_keys.extend(keys.into_iter().map(|index| (index, pressed)));
+            } else if player.is_none() {
+                for index in keys {
+                    set_key(&mut chip8, &mut recorder, index, pressed);
+                }
             }
         }
 
         if let Some(args) = e.render_args() {
+            let display = chip8.get_display_buffer();
+            let size = WINDOW_WIDTH / display.width() as f64;
             gl.draw(args.viewport(), |c, gl| {
                 clear(BLACK, gl);
-                for x in 0..64 {
-                    for y in 0..32 {
-                        let square = rectangle::square((x as f64) * 10.0, (y as f64) * 10.0, 10.0);
+                for x in 0..display.width() {
+                    for y in 0..display.height() {
+                        let square = rectangle::square((x as f64) * size, (y as f64) * size, size);
                         rectangle(
-                            if chip8.get_display_buffer()[y][x] == true {
-                                WHITE
-                            } else {
-                                BLACK
-                            },
+                            PALETTE[display.get_colour(x, y) as usize],
                             square,
                             c.transform,
                             gl,
@@ -118,14 +492,109 @@ fn main() {
         }
 
         if let Some(_args) = e.update_args() {
-            chip8.execute_cycle(|| loop {
-                let event = window.wait_event();
-                if let Some(Button::Keyboard(key)) = event.press_args() {
-                    if let Some(index) = map_key_to_index(key) {
-                        return index;
+            if rewinding && !paused {
+                match chip8.rewind(&mut rewind, 1) {
+                    Ok(_) => halted = false,
+                    Err(error) => println!("error rewinding: {error}"),
+                }
+                continue;
+            }
+            if halted {
+                continue;
+            }
+            let frame_start = !paused && !debugger.as_ref().is_some_and(Debugger::is_mid_frame);
+            if frame_start {
+                for (index, pressed) in pending_keys.drain(..) {
+                    set_key(&mut chip8, &mut recorder, index, pressed);
+                }
+            }
+            if let (true, Some(movie)) = (frame_start, player.as_mut()) {
+                movie.apply_frame(&mut chip8);
+                if movie.is_finished() {
+                    println!("movie finished after {} frames", movie.get_frame());
+                    player = None;
+                    if let (None, Some(debugger)) = (&recorder, debugger.as_mut()) {
+                        debugger.set_frames_only(false);
                     }
                 }
-            });
+            }
+            let frames_before = debugger.as_ref().map(Debugger::get_frame_count);
+            let result = match debugger.as_mut() {
+                Some(debugger) if paused => {
+                    let mut quit = false;
+                    for line in commands.try_iter() {
+                        let output = debugger.execute_command(&mut chip8, &line);
+                        println!("{}", output.text);
+                        quit |= output.quit;
+                        paused &= !output.resume;
+                        if paused {
+                            show_prompt();
+                        }
+                    }
+                    if quit || chip8.has_exited() {
+                        break;
+                    }
+                    continue;
+                }
+                Some(debugger) => {
+                    if let Some(reason) = debugger.run_frame(&mut chip8) {
+                        if reason != StopReason::Exited {
+                            paused = true;
+                            announce_stop(&reason, debugger, &chip8);
+                        }
+                    }
+                    Ok(())
+                }
+                None => chip8.run_frame(),
+            };
+            // A breakpoint can stop the debugger partway through a frame,
+            // which only counts once the rest of it has run.
+            let frame_finished = match (&debugger, frames_before) {
+                (Some(debugger), Some(before)) => debugger.get_frame_count() > before,
+                _ => result.is_ok(),
+            };
+            if frame_finished {
+                rewind.record(&chip8);
+                if let Some(recorder) = recorder.as_mut() {
+                    recorder.end_frame();
+                }
+            }
+            if speaker.is_some() || recording.is_some() {
+                let samples = buzzer.render_frame(&chip8);
+                if let Some(sink) = speaker.as_mut() {
+                    if let Err(error) = sink.write_samples(&samples) {
+                        println!("audio disabled: {error}");
+                        speaker = None;
+                    }
+                }
+                if let Some(sink) = recording.as_mut() {
+                    if let Err(error) = sink.write_samples(&samples) {
+                        println!("stopped recording audio: {error}");
+                        if let Some(recording) = recording.take() {
+                            recording.finish().expect("error writing wav file");
+                        }
+                    }
+                }
+            }
+            // Keep the last frame on screen so the state at the fault can be inspected.
+            if let Err(error) = result {
+                println!("halted: {error}");
+                halted = true;
+            }
+            if chip8.has_exited() {
+                break;
+            }
+        }
+    }
+
+    if let Some(recording) = recording {
+        recording.finish().expect("error writing wav file");
+    }
+    if let (Some(recorder), Some(path)) = (recorder, options.record_movie_path) {
+        let frames = recorder.get_frame();
+        match fs::write(&path, recorder.finish().to_text()) {
+            Ok(()) => println!("recorded {frames} frames to {path}"),
+            Err(error) => println!("error writing {path}: {error}"),
         }
     }
 }
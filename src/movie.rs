@@ -0,0 +1,312 @@
+//! Input movies: every `Chip8::set_key` call of a run, tagged with the frame
+//! it happened before, plus everything else needed to replay the run exactly.
+//!
+//! Movies are plain text so they can be attached to bug reports and edited
+//! by hand:
+//!
+//! ```text
+//! chip8-movie 1
+//! rom 2fd4e1c67a2d28fced849ee1bb76e7391b93eb12
+//! quirks 0x3f
+//! ipf 10
+//! seed 1234
+//! frames 600
+//! 12 5 press
+//! 20 5 release
+//! ```
+//!
+//! The header is followed by one `<frame> <key> press|release` line per key
+//! change, the same format `chip8-headless --keys` reads. `#` starts a comment.
+
+use std::fmt;
+
+use crate::chip8::Chip8;
+use crate::config::Config;
+use crate::hash::rom_hash;
+use crate::quirks::Quirks;
+
+const MOVIE_HEADER: &str = "chip8-movie";
+const MOVIE_VERSION: u32 = 1;
+
+/// A `set_key` call, applied before frame `frame` runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub frame: usize,
+    pub key: u8,
+    pub pressed: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MovieError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(f, "line {}: {}", self.line, self.message);
+    }
+}
+
+impl std::error::Error for MovieError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    /// `hash::rom_hash` of the ROM the movie was recorded against.
+    pub rom_hash: String,
+    pub quirks: Quirks,
+    pub instructions_per_frame: usize,
+    pub seed: u64,
+    /// Frames the recording ran for, which may be past the last key event.
+    pub frames: usize,
+    /// In the order the calls were made.
+    pub events: Vec<KeyEvent>,
+}
+
+impl Movie {
+    /// The configuration the movie was recorded with.
+    pub fn config(&self) -> Config {
+        return Config::new()
+            .quirks(self.quirks)
+            .instructions_per_frame(self.instructions_per_frame)
+            .seed(self.seed);
+    }
+
+    pub fn matches_rom(&self, rom: &[u8]) -> bool {
+        return self.rom_hash == rom_hash(rom);
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = format!(
+            "{MOVIE_HEADER} {MOVIE_VERSION}\nrom {}\nquirks {:#04x}\nipf {}\nseed {}\nframes {}\n",
+            self.rom_hash,
+            self.quirks.to_bits(),
+            self.instructions_per_frame,
+            self.seed,
+            self.frames,
+        );
+        for event in &self.events {
+            let action = if event.pressed { "press" } else { "release" };
+            text.push_str(&format!("{} {:x} {action}\n", event.frame, event.key));
+        }
+        return text;
+    }
+
+    pub fn parse(text: &str) -> Result<Movie, MovieError> {
+        let mut lines = meaningful_lines(text);
+        let mut header = |name: &str| -> Result<(usize, String), MovieError> {
+            let (number, line) = lines.next().ok_or_else(|| MovieError {
+                line: text.lines().count(),
+                message: format!("expected `{name}` before the end of the movie"),
+            })?;
+            return match line.split_once(char::is_whitespace) {
+                Some((key, value)) if key == name => Ok((number, value.trim().to_string())),
+                _ => Err(MovieError {
+                    line: number,
+                    message: format!("expected `{name} <value>`"),
+                }),
+            };
+        };
+        let invalid = |line: usize, what: &str| MovieError {
+            line,
+            message: format!("invalid {what}"),
+        };
+
+        let (line, version) = header(MOVIE_HEADER)?;
+        if version.parse() != Ok(MOVIE_VERSION) {
+            return Err(MovieError {
+                line,
+                message: format!("unsupported movie version {version}"),
+            });
+        }
+        let (_, rom_hash) = header("rom")?;
+        let (line, quirks) = header("quirks")?;
+        let quirks = quirks
+            .strip_prefix("0x")
+            .and_then(|bits| u16::from_str_radix(bits, 16).ok())
+            .and_then(Quirks::from_bits)
+            .ok_or_else(|| invalid(line, "quirks"))?;
+        let (line, ipf) = header("ipf")?;
+        let instructions_per_frame = ipf.parse().map_err(|_| invalid(line, "ipf"))?;
+        let (line, seed) = header("seed")?;
+        let seed = seed.parse().map_err(|_| invalid(line, "seed"))?;
+        let (line, frames) = header("frames")?;
+        let frames = frames.parse().map_err(|_| invalid(line, "frame count"))?;
+
+        let events = lines
+            .map(|(number, line)| parse_key_event(number, line))
+            .collect::<Result<Vec<KeyEvent>, MovieError>>()?;
+        return Ok(Movie {
+            rom_hash,
+            quirks,
+            instructions_per_frame,
+            seed,
+            frames,
+            events,
+        });
+    }
+}
+
+/// Reads a bare list of `<frame> <key> press|release` lines, sorted by frame.
+pub fn parse_key_events(text: &str) -> Result<Vec<KeyEvent>, MovieError> {
+    let mut events = meaningful_lines(text)
+        .map(|(number, line)| parse_key_event(number, line))
+        .collect::<Result<Vec<KeyEvent>, MovieError>>()?;
+    events.sort_by_key(|event| event.frame);
+    return Ok(events);
+}
+
+/// Non-empty lines with comments removed, with their 1-based line numbers.
+fn meaningful_lines(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    return text
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.split('#').next().unwrap_or_default().trim()))
+        .filter(|(_, line)| !line.is_empty());
+}
+
+fn parse_key_event(number: usize, line: &str) -> Result<KeyEvent, MovieError> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if let [frame, key, action] = fields.as_slice() {
+        let pressed = match action.to_ascii_lowercase().as_str() {
+            "press" => Some(true),
+            "release" => Some(false),
+            _ => None,
+        };
+        if let (Ok(frame), Ok(key), Some(pressed)) =
+            (frame.parse(), u8::from_str_radix(key, 16), pressed)
+        {
+            if key < 16 {
+                return Ok(KeyEvent {
+                    frame,
+                    key,
+                    pressed,
+                });
+            }
+        }
+    }
+    return Err(MovieError {
+        line: number,
+        message: "expected `<frame> <key> press|release`".to_string(),
+    });
+}
+
+/// Sits between the frontend's input handling and the machine, writing down
+/// each key change as it is passed on.
+#[derive(Debug, Clone)]
+pub struct MovieRecorder {
+    movie: Movie,
+}
+
+impl MovieRecorder {
+    /// Starts recording `chip8`, which must have just loaded `rom` and not
+    /// run any frames yet.
+    pub fn new(rom: &[u8], chip8: &Chip8) -> MovieRecorder {
+        return MovieRecorder {
+            movie: Movie {
+                rom_hash: rom_hash(rom),
+                quirks: chip8.get_quirks(),
+                instructions_per_frame: chip8.get_instructions_per_frame(),
+                seed: chip8.get_seed(),
+                frames: 0,
+                events: Vec::new(),
+            },
+        };
+    }
+
+    pub fn set_key(&mut self, chip8: &mut Chip8, key: u8, pressed: bool) {
+        self.movie.events.push(KeyEvent {
+            frame: self.movie.frames,
+            key,
+            pressed,
+        });
+        chip8.set_key(key, pressed);
+    }
+
+    /// Call after each frame the machine runs.
+    pub fn end_frame(&mut self) {
+        self.movie.frames += 1;
+    }
+
+    pub fn get_frame(&self) -> usize {
+        return self.movie.frames;
+    }
+
+    pub fn finish(self) -> Movie {
+        return self.movie;
+    }
+}
+
+/// Feeds a movie's key changes back in at the frames they were recorded.
+#[derive(Debug, Clone)]
+pub struct MoviePlayer {
+    movie: Movie,
+    frame: usize,
+    next_event: usize,
+}
+
+impl MoviePlayer {
+    pub fn new(movie: Movie) -> MoviePlayer {
+        return MoviePlayer {
+            movie,
+            frame: 0,
+            next_event: 0,
+        };
+    }
+
+    pub fn get_frame(&self) -> usize {
+        return self.frame;
+    }
+
+    /// Whether every recorded frame has been played.
+    pub fn is_finished(&self) -> bool {
+        return self.frame >= self.movie.frames;
+    }
+
+    /// Sets the keys for the frame about to run. Call once before each frame.
+    pub fn apply_frame(&mut self, chip8: &mut Chip8) {
+        while let Some(event) = self.movie.events.get(self.next_event) {
+            if event.frame > self.frame {
+                break;
+            }
+            chip8.set_key(event.key, event.pressed);
+            self.next_event += 1;
+        }
+        self.frame += 1;
+    }
+}
+
+#[test]
+fn records_and_plays_back_movies() {
+    // C00F E09E 7A01 1200: pick a random key, count in VA while it is up
+    let rom = vec![0xC0, 0x0F, 0xE0, 0x9E, 0x7A, 0x01, 0x12, 0x00];
+    let config = Config::new().quirks(Quirks::SUPER_CHIP).seed(99);
+    let mut chip8 = config.build();
+    chip8.load_rom(rom.clone()).unwrap();
+
+    let mut recorder = MovieRecorder::new(&rom, &chip8);
+    for frame in 0..30 {
+        for key in 0..16 {
+            if frame == 3 || frame == 20 {
+                recorder.set_key(&mut chip8, key, frame == 3);
+            }
+        }
+        chip8.run_frame().unwrap();
+        recorder.end_frame();
+    }
+    let movie = Movie::parse(&recorder.finish().to_text()).unwrap();
+    assert_eq!(movie.frames, 30);
+    assert_eq!(movie.seed, 99);
+    assert_eq!(movie.events.len(), 32);
+    assert!(movie.matches_rom(&rom));
+
+    let mut replay = movie.config().build();
+    replay.load_rom(rom).unwrap();
+    let mut player = MoviePlayer::new(movie);
+    while !player.is_finished() {
+        player.apply_frame(&mut replay);
+        replay.run_frame().unwrap();
+    }
+    assert_eq!(replay.save_state(), chip8.save_state());
+    assert!(replay.get_registers()[0xA] > 0);
+}
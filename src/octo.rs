@@ -0,0 +1,1109 @@
+//! A compiler for Octo, the high-level CHIP-8 assembly language.
+//!
+//! Supported: labels (`: name`), `:const`, `:alias`, `:macro` (with
+//! `CALLS`), `:calc`, `:byte`, `:org`, `:call`, `loop`/`while`/`again`,
+//! `if ... then` and `if ... begin ... else ... end`, the comparison
+//! operators `< > <= >=` (which clobber `vf`), and every SUPER-CHIP and
+//! XO-CHIP statement. As in Octo, a bare label is a subroutine call, a bare
+//! number is a data byte, and when `: main` is not the first thing in the
+//! program a `jump main` is placed at 0x200.
+
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::Path;
+
+use crate::assembler::{Assembly, AssemblyError, SourceLocation, SourceMap};
+use crate::chip8::{self, Address, Instruction, RegisterNumber};
+
+const PROGRAM_START: usize = chip8::PROGRAM_START;
+
+/// Stops a macro that expands into itself from running forever.
+const MAX_MACRO_EXPANSIONS: usize = 100_000;
+
+pub fn compile(source: &str) -> Result<Assembly, AssemblyError> {
+    return Compiler::new("<source>", source).run();
+}
+
+pub fn compile_file<P: AsRef<Path>>(path: P) -> Result<Assembly, AssemblyError> {
+    let name = path.as_ref().display().to_string();
+    let source = fs::read_to_string(path.as_ref()).map_err(|io_error| AssemblyError {
+        location: SourceLocation {
+            file: name.clone(),
+            line: 1,
+            column: 1,
+        },
+        message: format!("cannot read {name}: {io_error}"),
+    })?;
+    return Compiler::new(&name, &source).run();
+}
+
+/// Reads a program for the interpreter, compiling it first when the file is
+/// Octo source (`.8o`). A plain ROM comes back with an empty source map.
+pub fn read_program<P: AsRef<Path>>(path: P) -> Result<Assembly, String> {
+    let path = path.as_ref();
+    let is_source = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("8o"));
+    if is_source {
+        return compile_file(path).map_err(|error| error.to_string());
+    }
+    return match fs::read(path) {
+        Ok(rom) => Ok(Assembly {
+            rom,
+            source_map: SourceMap::new(),
+        }),
+        Err(error) => Err(format!("error reading {}: {error}", path.display())),
+    };
+}
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    location: SourceLocation,
+}
+
+#[derive(Debug, Clone)]
+struct Macro {
+    parameters: Vec<String>,
+    body: Vec<Token>,
+    calls: usize,
+}
+
+/// A jump or address operand to fill in once the label is defined.
+#[derive(Debug, Clone)]
+struct Fixup {
+    address: usize,
+    name: String,
+    long: bool,
+    location: SourceLocation,
+}
+
+#[derive(Debug, Clone)]
+enum Block {
+    Loop {
+        start: usize,
+        breaks: Vec<usize>,
+    },
+    /// The jump over the body of an `if ... begin`, or over the `else` part.
+    If {
+        jump: usize,
+        location: SourceLocation,
+    },
+    Else {
+        jump: usize,
+        location: SourceLocation,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    Greater,
+    LessEqual,
+    GreaterEqual,
+    Key,
+    NotKey,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Operand {
+    Register(RegisterNumber),
+    Value(u8),
+}
+
+struct Compiler {
+    tokens: VecDeque<Token>,
+    end: SourceLocation,
+    rom: Vec<u8>,
+    here: usize,
+    source_map: SourceMap,
+    labels: HashMap<String, usize>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, RegisterNumber>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    blocks: Vec<Block>,
+    /// Whether 0x200 still holds the placeholder for `jump main`.
+    main_jump: bool,
+    expansions: usize,
+}
+
+fn error<T>(location: &SourceLocation, message: String) -> Result<T, AssemblyError> {
+    return Err(AssemblyError {
+        location: location.clone(),
+        message,
+    });
+}
+
+fn tokenize(file: &str, source: &str) -> VecDeque<Token> {
+    let mut tokens = VecDeque::new();
+    for (index, line) in source.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or_default();
+        let mut start = None;
+        let characters: Vec<char> = line.chars().collect();
+        for (column, character) in characters.iter().chain([' '].iter()).enumerate() {
+            match (character.is_whitespace(), start) {
+                (false, None) => start = Some(column),
+                (true, Some(first)) => {
+                    tokens.push_back(Token {
+                        text: characters[first..column].iter().collect(),
+                        location: SourceLocation {
+                            file: file.to_string(),
+                            line: index + 1,
+                            column: first + 1,
+                        },
+                    });
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+    }
+    return tokens;
+}
+
+fn parse_number(text: &str) -> Option<f64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()? as f64
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()? as f64
+    } else if digits.starts_with(|character: char| character.is_ascii_digit()) {
+        digits.parse::<f64>().ok()?
+    } else {
+        return None;
+    };
+    return Some(if negative { -value } else { value });
+}
+
+impl Compiler {
+    fn new(file: &str, source: &str) -> Compiler {
+        let lines = source.lines().count().max(1);
+        return Compiler {
+            tokens: tokenize(file, source),
+            end: SourceLocation {
+                file: file.to_string(),
+                line: lines,
+                column: 1,
+            },
+            rom: vec![0, 0],
+            here: PROGRAM_START + 2,
+            source_map: SourceMap::new(),
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            blocks: Vec::new(),
+            main_jump: true,
+            expansions: 0,
+        };
+    }
+
+    fn run(mut self) -> Result<Assembly, AssemblyError> {
+        while let Some(token) = self.tokens.pop_front() {
+            self.statement(token)?;
+        }
+
+        if let Some(block) = self.blocks.last() {
+            let (what, location) = match block {
+                Block::Loop { .. } => ("loop without again", &self.end),
+                Block::If { location, .. } | Block::Else { location, .. } => {
+                    ("begin without end", location)
+                }
+            };
+            return error(location, String::from(what));
+        }
+        if self.main_jump {
+            let main = match self.labels.get("main") {
+                Some(main) => *main,
+                None => return error(&self.end, String::from("no main label defined")),
+            };
+            self.rom[0..2].copy_from_slice(&(0x1000 | main as u16).to_be_bytes());
+        }
+        for fixup in std::mem::take(&mut self.fixups) {
+            let address = match self.labels.get(&fixup.name) {
+                Some(address) => *address,
+                None => return error(&fixup.location, format!("undefined name '{}'", fixup.name)),
+            };
+            let offset = fixup.address - PROGRAM_START;
+            if fixup.long {
+                self.rom[offset..offset + 2].copy_from_slice(&(address as u16).to_be_bytes());
+            } else {
+                if address > 0xFFF {
+                    return error(
+                        &fixup.location,
+                        format!("'{}' is beyond 0xFFF, use i := long", fixup.name),
+                    );
+                }
+                self.rom[offset] |= (address >> 8) as u8;
+                self.rom[offset + 1] = address as u8;
+            }
+        }
+        return Ok(Assembly {
+            rom: self.rom,
+            source_map: self.source_map,
+        });
+    }
+
+    fn next(&mut self, after: &SourceLocation) -> Result<Token, AssemblyError> {
+        return match self.tokens.pop_front() {
+            Some(token) => Ok(token),
+            None => error(after, String::from("unexpected end of file")),
+        };
+    }
+
+    fn expect(&mut self, text: &str, after: &SourceLocation) -> Result<Token, AssemblyError> {
+        let token = self.next(after)?;
+        if token.text != text {
+            return error(
+                &token.location,
+                format!("expected '{text}', found '{}'", token.text),
+            );
+        }
+        return Ok(token);
+    }
+
+    fn peek_is(&self, text: &str) -> bool {
+        return self.tokens.front().is_some_and(|token| token.text == text);
+    }
+
+    fn emit_byte(&mut self, byte: u8, location: &SourceLocation) -> Result<(), AssemblyError> {
+        if self.here >= chip8::EXTENDED_MEMORY_SIZE {
+            return error(location, String::from("program does not fit in memory"));
+        }
+        let offset = self.here - PROGRAM_START;
+        if offset >= self.rom.len() {
+            self.rom.resize(offset + 1, 0);
+        }
+        self.rom[offset] = byte;
+        self.here += 1;
+        return Ok(());
+    }
+
+    fn emit(
+        &mut self,
+        instruction: Instruction,
+        location: &SourceLocation,
+    ) -> Result<usize, AssemblyError> {
+        if self.here + 2 > chip8::EXTENDED_MEMORY_SIZE {
+            return error(location, String::from("program does not fit in memory"));
+        }
+        let address = self.here;
+        self.source_map.insert(address as Address, location.clone());
+        for byte in instruction.encode().to_be_bytes() {
+            self.emit_byte(byte, location)?;
+        }
+        return Ok(address);
+    }
+
+    fn patch_jump(&mut self, jump: usize, target: usize) {
+        let offset = jump - PROGRAM_START;
+        let opcode = Instruction::Jump(target as Address).encode();
+        self.rom[offset..offset + 2].copy_from_slice(&opcode.to_be_bytes());
+    }
+
+    fn define_label(&mut self, token: &Token) -> Result<(), AssemblyError> {
+        if self.labels.contains_key(&token.text) {
+            return error(
+                &token.location,
+                format!("label '{}' is already defined", token.text),
+            );
+        }
+        // A program that starts with main does not need the jump to it.
+        if token.text == "main" && self.main_jump && self.here == PROGRAM_START + 2 {
+            self.rom.clear();
+            self.here = PROGRAM_START;
+            self.main_jump = false;
+        }
+        self.labels.insert(token.text.clone(), self.here);
+        return Ok(());
+    }
+
+    fn register(&self, token: &Token) -> Option<RegisterNumber> {
+        if let Some(register) = self.aliases.get(&token.text) {
+            return Some(*register);
+        }
+        let digit = token.text.strip_prefix(['v', 'V'])?;
+        if digit.len() != 1 {
+            return None;
+        }
+        return u8::from_str_radix(digit, 16).ok();
+    }
+
+    fn expect_register(&mut self, after: &SourceLocation) -> Result<RegisterNumber, AssemblyError> {
+        let token = self.next(after)?;
+        return match self.register(&token) {
+            Some(register) => Ok(register),
+            None => error(
+                &token.location,
+                format!("expected a register, found '{}'", token.text),
+            ),
+        };
+    }
+
+    /// A number, constant or already defined label.
+    fn number(&self, token: &Token) -> Option<f64> {
+        if let Some(value) = parse_number(&token.text) {
+            return Some(value);
+        }
+        if let Some(value) = self.constants.get(&token.text) {
+            return Some(*value);
+        }
+        return self.labels.get(&token.text).map(|address| *address as f64);
+    }
+
+    fn value(&self, token: &Token, minimum: i64, maximum: i64) -> Result<i64, AssemblyError> {
+        let value = match self.number(token) {
+            Some(value) => value.floor() as i64,
+            None => {
+                return error(
+                    &token.location,
+                    format!("expected a number, found '{}'", token.text),
+                )
+            }
+        };
+        if value < minimum || value > maximum {
+            return error(
+                &token.location,
+                format!("value {value} is out of range, expected {minimum} to {maximum}"),
+            );
+        }
+        return Ok(value);
+    }
+
+    fn byte(&self, token: &Token) -> Result<u8, AssemblyError> {
+        return Ok(self.value(token, -128, 255)? as u8);
+    }
+
+    fn nibble(&self, token: &Token) -> Result<u8, AssemblyError> {
+        return Ok(self.value(token, 0, 15)? as u8);
+    }
+
+    /// An address operand, which may name a label defined further down.
+    fn address(&mut self, token: &Token, long: bool) -> Result<Address, AssemblyError> {
+        if self.number(token).is_none() && self.register(token).is_none() {
+            let operand = if long { self.here + 2 } else { self.here };
+            self.fixups.push(Fixup {
+                address: operand,
+                name: token.text.clone(),
+                long,
+                location: token.location.clone(),
+            });
+            return Ok(0);
+        }
+        let maximum = if long { 0xFFFF } else { 0xFFF };
+        return Ok(self.value(token, 0, maximum)? as Address);
+    }
+
+    fn operand(&mut self, after: &SourceLocation) -> Result<(Operand, Token), AssemblyError> {
+        let token = self.next(after)?;
+        if let Some(register) = self.register(&token) {
+            return Ok((Operand::Register(register), token));
+        }
+        return Ok((Operand::Value(self.byte(&token)?), token));
+    }
+
+    fn statement(&mut self, token: Token) -> Result<(), AssemblyError> {
+        let at = token.location.clone();
+        if let Some(x) = self.register(&token) {
+            return self.assignment(x, &at);
+        }
+        match token.text.as_str() {
+            ":" => {
+                let name = self.next(&at)?;
+                self.define_label(&name)?;
+            }
+            ":const" => {
+                let name = self.next(&at)?;
+                let value = self.next(&name.location)?;
+                let value = match self.number(&value) {
+                    Some(value) => value,
+                    None => {
+                        return error(
+                            &value.location,
+                            format!("unknown constant '{}'", value.text),
+                        )
+                    }
+                };
+                self.constants.insert(name.text, value);
+            }
+            ":alias" => {
+                let name = self.next(&at)?;
+                let register = self.expect_register(&name.location)?;
+                self.aliases.insert(name.text, register);
+            }
+            ":calc" => {
+                let name = self.next(&at)?;
+                let value = self.calc(&name.location)?;
+                self.constants.insert(name.text, value);
+            }
+            ":byte" => {
+                let value = if self.peek_is("{") {
+                    self.calc(&at)?.floor() as i64
+                } else {
+                    let value = self.next(&at)?;
+                    self.value(&value, -128, 255)?
+                };
+                let address = self.here as Address;
+                self.emit_byte(value as u8, &at)?;
+                self.source_map.insert(address, at);
+            }
+            ":org" => {
+                let address = self.next(&at)?;
+                let address = self.value(&address, PROGRAM_START as i64, 0xFFFF)?;
+                self.here = address as usize;
+            }
+            ":call" => {
+                let target = self.next(&at)?;
+                let address = self.address(&target, false)?;
+                self.emit(Instruction::Call(address), &at)?;
+            }
+            ":macro" => self.define_macro(&at)?,
+            "return" | ";" => {
+                self.emit(Instruction::Return, &at)?;
+            }
+            "clear" => {
+                self.emit(Instruction::ClearDisplay, &at)?;
+            }
+            "exit" => {
+                self.emit(Instruction::Exit, &at)?;
+            }
+            "lores" => {
+                self.emit(Instruction::LowRes, &at)?;
+            }
+            "hires" => {
+                self.emit(Instruction::HighRes, &at)?;
+            }
+            "scroll-left" => {
+                self.emit(Instruction::ScrollLeft, &at)?;
+            }
+            "scroll-right" => {
+                self.emit(Instruction::ScrollRight, &at)?;
+            }
+            "scroll-down" | "scroll-up" => {
+                let rows = self.next(&at)?;
+                let rows = self.nibble(&rows)?;
+                let instruction = if token.text == "scroll-down" {
+                    Instruction::ScrollDown(rows)
+                } else {
+                    Instruction::ScrollUp(rows)
+                };
+                self.emit(instruction, &at)?;
+            }
+            "audio" => {
+                self.emit(Instruction::LoadAudioPattern, &at)?;
+            }
+            "plane" => {
+                let planes = self.next(&at)?;
+                let planes = self.value(&planes, 0, 3)? as u8;
+                self.emit(Instruction::SelectPlanes(planes), &at)?;
+            }
+            "bcd" => {
+                let x = self.expect_register(&at)?;
+                self.emit(Instruction::StoreBCD(x), &at)?;
+            }
+            "saveflags" => {
+                let x = self.expect_register(&at)?;
+                self.emit(Instruction::StoreFlags(x), &at)?;
+            }
+            "loadflags" => {
+                let x = self.expect_register(&at)?;
+                self.emit(Instruction::LoadFlags(x), &at)?;
+            }
+            "save" | "load" => {
+                let x = self.expect_register(&at)?;
+                let save = token.text == "save";
+                let instruction = if self.peek_is("-") {
+                    self.expect("-", &at)?;
+                    let y = self.expect_register(&at)?;
+                    if save {
+                        Instruction::SaveRange(x, y)
+                    } else {
+                        Instruction::LoadRange(x, y)
+                    }
+                } else if save {
+                    Instruction::StoreRegisters(x)
+                } else {
+                    Instruction::LoadRegisters(x)
+                };
+                self.emit(instruction, &at)?;
+            }
+            "sprite" => {
+                let x = self.expect_register(&at)?;
+                let y = self.expect_register(&at)?;
+                let height = self.next(&at)?;
+                let height = self.nibble(&height)?;
+                self.emit(Instruction::Draw(x, y, height), &at)?;
+            }
+            "jump" | "jump0" => {
+                let target = self.next(&at)?;
+                let address = self.address(&target, false)?;
+                let instruction = if token.text == "jump" {
+                    Instruction::Jump(address)
+                } else {
+                    Instruction::LongJump(address)
+                };
+                self.emit(instruction, &at)?;
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=", &at)?;
+                let x = self.expect_register(&at)?;
+                let instruction = match token.text.as_str() {
+                    "delay" => Instruction::SetTimer(x),
+                    "buzzer" => Instruction::SetSoundTimer(x),
+                    _ => Instruction::SetPitch(x),
+                };
+                self.emit(instruction, &at)?;
+            }
+            "i" => self.index_assignment(&at)?,
+            "loop" => self.blocks.push(Block::Loop {
+                start: self.here,
+                breaks: Vec::new(),
+            }),
+            "while" => {
+                let comparison = self.condition(&at)?;
+                self.emit_skip(comparison, true, &at)?;
+                let jump = self.emit(Instruction::Jump(0), &at)?;
+                match self
+                    .blocks
+                    .iter_mut()
+                    .rev()
+                    .find(|block| matches!(block, Block::Loop { .. }))
+                {
+                    Some(Block::Loop { breaks, .. }) => breaks.push(jump),
+                    _ => return error(&at, String::from("while outside of a loop")),
+                }
+            }
+            "again" => match self.blocks.pop() {
+                Some(Block::Loop { start, breaks }) => {
+                    self.emit(Instruction::Jump(start as Address), &at)?;
+                    for jump in breaks {
+                        self.patch_jump(jump, self.here);
+                    }
+                }
+                _ => return error(&at, String::from("again without loop")),
+            },
+            "if" => {
+                let comparison = self.condition(&at)?;
+                let keyword = self.next(&at)?;
+                match keyword.text.as_str() {
+                    "then" => self.emit_skip(comparison, false, &at)?,
+                    "begin" => {
+                        self.emit_skip(comparison, true, &at)?;
+                        let jump = self.emit(Instruction::Jump(0), &at)?;
+                        self.blocks.push(Block::If { jump, location: at });
+                    }
+                    _ => {
+                        return error(
+                            &keyword.location,
+                            format!("expected 'then' or 'begin', found '{}'", keyword.text),
+                        )
+                    }
+                }
+            }
+            "else" => match self.blocks.pop() {
+                Some(Block::If { jump, location }) => {
+                    let over = self.emit(Instruction::Jump(0), &at)?;
+                    self.patch_jump(jump, self.here);
+                    self.blocks.push(Block::Else {
+                        jump: over,
+                        location,
+                    });
+                }
+                _ => return error(&at, String::from("else without if ... begin")),
+            },
+            "end" => match self.blocks.pop() {
+                Some(Block::If { jump, .. }) | Some(Block::Else { jump, .. }) => {
+                    self.patch_jump(jump, self.here);
+                }
+                _ => return error(&at, String::from("end without if ... begin")),
+            },
+            _ => {
+                if self.macros.contains_key(&token.text) {
+                    return self.expand_macro(&token);
+                }
+                if self.number(&token).is_some() && !self.labels.contains_key(&token.text) {
+                    let byte = self.byte(&token)?;
+                    let address = self.here as Address;
+                    self.emit_byte(byte, &at)?;
+                    self.source_map.insert(address, at);
+                    return Ok(());
+                }
+                if token.text.starts_with(':') {
+                    return error(&at, format!("unknown directive '{}'", token.text));
+                }
+                let address = self.address(&token, false)?;
+                self.emit(Instruction::Call(address), &at)?;
+            }
+        }
+        return Ok(());
+    }
+
+    fn assignment(&mut self, x: RegisterNumber, at: &SourceLocation) -> Result<(), AssemblyError> {
+        let operator = self.next(at)?;
+        let instruction = match operator.text.as_str() {
+            ":=" => {
+                let source = self.next(at)?;
+                match source.text.as_str() {
+                    "key" => Instruction::WaitKey(x),
+                    "delay" => Instruction::GetTimer(x),
+                    "random" => {
+                        let mask = self.next(at)?;
+                        Instruction::Rand(x, self.byte(&mask)?)
+                    }
+                    _ => match self.register(&source) {
+                        Some(y) => Instruction::Set(x, y),
+                        None => Instruction::SetK(x, self.byte(&source)?),
+                    },
+                }
+            }
+            "+=" => match self.operand(at)? {
+                (Operand::Register(y), _) => Instruction::Add(x, y),
+                (Operand::Value(nn), _) => Instruction::AddK(x, nn),
+            },
+            "-=" => match self.operand(at)? {
+                (Operand::Register(y), _) => Instruction::Sub(x, y),
+                (Operand::Value(nn), _) => Instruction::AddK(x, nn.wrapping_neg()),
+            },
+            "=-" | "|=" | "&=" | "^=" | ">>=" | "<<=" => {
+                let y = self.expect_register(at)?;
+                match operator.text.as_str() {
+                    "=-" => Instruction::SubInv(x, y),
+                    "|=" => Instruction::Or(x, y),
+                    "&=" => Instruction::And(x, y),
+                    "^=" => Instruction::XOr(x, y),
+                    ">>=" => Instruction::ShiftRight(x, y),
+                    _ => Instruction::ShiftLeft(x, y),
+                }
+            }
+            _ => {
+                return error(
+                    &operator.location,
+                    format!("unknown operator '{}'", operator.text),
+                )
+            }
+        };
+        self.emit(instruction, at)?;
+        return Ok(());
+    }
+
+    fn index_assignment(&mut self, at: &SourceLocation) -> Result<(), AssemblyError> {
+        let operator = self.next(at)?;
+        match operator.text.as_str() {
+            "+=" => {
+                let x = self.expect_register(at)?;
+                self.emit(Instruction::AddToI(x), at)?;
+            }
+            ":=" => {
+                let source = self.next(at)?;
+                match source.text.as_str() {
+                    "hex" => {
+                        let x = self.expect_register(at)?;
+                        self.emit(Instruction::LoadHexGlyph(x), at)?;
+                    }
+                    "bighex" => {
+                        let x = self.expect_register(at)?;
+                        self.emit(Instruction::LoadBigHexGlyph(x), at)?;
+                    }
+                    "long" => {
+                        let target = self.next(at)?;
+                        let address = self.address(&target, true)?;
+                        self.emit(Instruction::LoadLongI, at)?;
+                        for byte in address.to_be_bytes() {
+                            self.emit_byte(byte, at)?;
+                        }
+                    }
+                    _ => {
+                        let address = self.address(&source, false)?;
+                        self.emit(Instruction::LoadI(address), at)?;
+                    }
+                }
+            }
+            _ => {
+                return error(
+                    &operator.location,
+                    format!("unknown operator '{}'", operator.text),
+                )
+            }
+        }
+        return Ok(());
+    }
+
+    /// Reads `vx op operand` and returns what to compare.
+    fn condition(
+        &mut self,
+        at: &SourceLocation,
+    ) -> Result<(RegisterNumber, Comparison, Option<Operand>), AssemblyError> {
+        let x = self.expect_register(at)?;
+        let operator = self.next(at)?;
+        let comparison = match operator.text.as_str() {
+            "==" => Comparison::Equal,
+            "!=" => Comparison::NotEqual,
+            "<" => Comparison::Less,
+            ">" => Comparison::Greater,
+            "<=" => Comparison::LessEqual,
+            ">=" => Comparison::GreaterEqual,
+            "key" => return Ok((x, Comparison::Key, None)),
+            "-key" => return Ok((x, Comparison::NotKey, None)),
+            _ => {
+                return error(
+                    &operator.location,
+                    format!("unknown comparison '{}'", operator.text),
+                )
+            }
+        };
+        let (operand, _) = self.operand(at)?;
+        return Ok((x, comparison, Some(operand)));
+    }
+
+    /// Emits instructions that skip the next one when the condition is
+    /// `when`. Ordering comparisons subtract into `vf` and test the borrow.
+    fn emit_skip(
+        &mut self,
+        (x, comparison, operand): (RegisterNumber, Comparison, Option<Operand>),
+        when: bool,
+        at: &SourceLocation,
+    ) -> Result<(), AssemblyError> {
+        let instruction = match (comparison, operand) {
+            (Comparison::Key, _) | (Comparison::NotKey, _) => {
+                if when == (comparison == Comparison::Key) {
+                    Instruction::SkipPressed(x)
+                } else {
+                    Instruction::SkipNotPressed(x)
+                }
+            }
+            (Comparison::Equal, Some(operand)) | (Comparison::NotEqual, Some(operand)) => {
+                let equal = when == (comparison == Comparison::Equal);
+                match (operand, equal) {
+                    (Operand::Register(y), true) => Instruction::SkipEqual(x, y),
+                    (Operand::Register(y), false) => Instruction::SkipNotEqual(x, y),
+                    (Operand::Value(nn), true) => Instruction::SkipEqualK(x, nn),
+                    (Operand::Value(nn), false) => Instruction::SkipNotEqualK(x, nn),
+                }
+            }
+            (_, Some(operand)) => {
+                let load = match operand {
+                    Operand::Register(y) => Instruction::Set(0xF, y),
+                    Operand::Value(nn) => Instruction::SetK(0xF, nn),
+                };
+                self.emit(load, at)?;
+                // vf =- vx leaves vf set when vx >= operand; vf -= vx when vx <= operand.
+                let subtract = match comparison {
+                    Comparison::Less | Comparison::GreaterEqual => Instruction::SubInv(0xF, x),
+                    _ => Instruction::Sub(0xF, x),
+                };
+                self.emit(subtract, at)?;
+                let true_when_set =
+                    matches!(comparison, Comparison::GreaterEqual | Comparison::LessEqual);
+                if when == true_when_set {
+                    Instruction::SkipNotEqualK(0xF, 0)
+                } else {
+                    Instruction::SkipEqualK(0xF, 0)
+                }
+            }
+            (_, None) => unreachable!("only key comparisons have no operand"),
+        };
+        self.emit(instruction, at)?;
+        return Ok(());
+    }
+
+    /// Reads a `{ ... }` block, keeping nested braces.
+    fn braced(&mut self, at: &SourceLocation) -> Result<Vec<Token>, AssemblyError> {
+        let open = self.expect("{", at)?;
+        let mut depth = 1;
+        let mut body = Vec::new();
+        loop {
+            let token = self.next(&open.location)?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(body);
+                    }
+                }
+                _ => {}
+            }
+            body.push(token);
+        }
+    }
+
+    fn define_macro(&mut self, at: &SourceLocation) -> Result<(), AssemblyError> {
+        let name = self.next(at)?;
+        let mut parameters = Vec::new();
+        while !self.peek_is("{") {
+            parameters.push(self.next(&name.location)?.text);
+        }
+        let body = self.braced(&name.location)?;
+        self.macros.insert(
+            name.text,
+            Macro {
+                parameters,
+                body,
+                calls: 0,
+            },
+        );
+        return Ok(());
+    }
+
+    fn expand_macro(&mut self, token: &Token) -> Result<(), AssemblyError> {
+        self.expansions += 1;
+        if self.expansions > MAX_MACRO_EXPANSIONS {
+            return error(&token.location, String::from("too many macro expansions"));
+        }
+        let (parameters, body, calls) = {
+            let definition = self.macros.get_mut(&token.text).expect("macro exists");
+            definition.calls += 1;
+            (
+                definition.parameters.clone(),
+                definition.body.clone(),
+                definition.calls - 1,
+            )
+        };
+        let mut arguments = HashMap::new();
+        for parameter in parameters {
+            let argument = self.next(&token.location)?;
+            arguments.insert(parameter, argument.text);
+        }
+        for mut body_token in body.into_iter().rev() {
+            if let Some(argument) = arguments.get(&body_token.text) {
+                body_token.text = argument.clone();
+            } else if body_token.text == "CALLS" {
+                body_token.text = calls.to_string();
+            }
+            self.tokens.push_front(body_token);
+        }
+        return Ok(());
+    }
+
+    fn calc(&mut self, at: &SourceLocation) -> Result<f64, AssemblyError> {
+        let tokens = self.braced(at)?;
+        let mut position = 0;
+        let value = self.expression(&tokens, &mut position, at)?;
+        if let Some(token) = tokens.get(position) {
+            return error(&token.location, format!("unexpected '{}'", token.text));
+        }
+        return Ok(value);
+    }
+
+    /// Octo evaluates binary operators right to left, without precedence.
+    fn expression(
+        &self,
+        tokens: &[Token],
+        position: &mut usize,
+        at: &SourceLocation,
+    ) -> Result<f64, AssemblyError> {
+        let left = self.term(tokens, position, at)?;
+        let operator = match tokens.get(*position) {
+            Some(token) if token.text != ")" => token.clone(),
+            _ => return Ok(left),
+        };
+        *position += 1;
+        let right = self.expression(tokens, position, at)?;
+        let (a, b) = (left as i64, right as i64);
+        return Ok(match operator.text.as_str() {
+            "+" => left + right,
+            "-" => left - right,
+            "*" => left * right,
+            "/" => left / right,
+            "%" => left % right,
+            "&" => (a & b) as f64,
+            "|" => (a | b) as f64,
+            "^" => (a ^ b) as f64,
+            "<<" => a.wrapping_shl(b as u32) as f64,
+            ">>" => a.wrapping_shr(b as u32) as f64,
+            "pow" => left.powf(right),
+            "min" => left.min(right),
+            "max" => left.max(right),
+            "<" => (left < right) as i64 as f64,
+            ">" => (left > right) as i64 as f64,
+            "<=" => (left <= right) as i64 as f64,
+            ">=" => (left >= right) as i64 as f64,
+            "==" => (left == right) as i64 as f64,
+            "!=" => (left != right) as i64 as f64,
+            _ => {
+                return error(
+                    &operator.location,
+                    format!("unknown operator '{}'", operator.text),
+                )
+            }
+        });
+    }
+
+    fn term(
+        &self,
+        tokens: &[Token],
+        position: &mut usize,
+        at: &SourceLocation,
+    ) -> Result<f64, AssemblyError> {
+        let token = match tokens.get(*position) {
+            Some(token) => token,
+            None => return error(at, String::from("expected a value")),
+        };
+        *position += 1;
+        let unary: Option<fn(f64) -> f64> = match token.text.as_str() {
+            "-" => Some(|value| -value),
+            "~" => Some(|value| !(value as i64) as f64),
+            "!" => Some(|value| (value == 0.0) as i64 as f64),
+            "sin" => Some(f64::sin),
+            "cos" => Some(f64::cos),
+            "tan" => Some(f64::tan),
+            "exp" => Some(f64::exp),
+            "log" => Some(f64::ln),
+            "abs" => Some(f64::abs),
+            "sqrt" => Some(f64::sqrt),
+            "sign" => Some(f64::signum),
+            "ceil" => Some(f64::ceil),
+            "floor" => Some(f64::floor),
+            _ => None,
+        };
+        if let Some(function) = unary {
+            return Ok(function(self.term(tokens, position, at)?));
+        }
+        match token.text.as_str() {
+            "(" => {
+                let value = self.expression(tokens, position, at)?;
+                match tokens.get(*position) {
+                    Some(close) if close.text == ")" => *position += 1,
+                    _ => return error(&token.location, String::from("unclosed '('")),
+                }
+                return Ok(value);
+            }
+            "@" => {
+                let address = self.term(tokens, position, at)? as usize;
+                let byte = address
+                    .checked_sub(PROGRAM_START)
+                    .and_then(|offset| self.rom.get(offset));
+                return Ok(byte.copied().unwrap_or(0) as f64);
+            }
+            "HERE" => return Ok(self.here as f64),
+            "PI" => return Ok(std::f64::consts::PI),
+            "E" => return Ok(std::f64::consts::E),
+            _ => {}
+        }
+        return match self.number(token) {
+            Some(value) => Ok(value),
+            None => error(&token.location, format!("unknown name '{}'", token.text)),
+        };
+    }
+}
+
+#[test]
+fn compiles_octo() {
+    let source = "
+        :alias x v3
+        :const speed 2
+        :calc offset { speed * 3 + 1 }
+        :macro twice op { op op }
+        : main
+            x := offset
+            loop
+                x -= speed
+                while x != 0
+                if x < 5 then x += 1
+            again
+            if v0 key begin twice clear else i := data end
+            draw
+            jump main
+        : draw
+            sprite x v0 1 ;
+        : data
+            0x3C 0b01000010
+    ";
+    let assembly = compile(source).unwrap();
+    assert_eq!(
+        assembly.rom,
+        vec![
+            0x63, 0x08, // x := offset, which is 2 * (3 + 1)
+            0x73, 0xFE, // x -= speed
+            0x43, 0x00, 0x12, 0x12, // while x != 0
+            0x6F, 0x05, 0x8F, 0x37, 0x4F, 0x00, 0x73, 0x01, // if x < 5 then x += 1
+            0x12, 0x02, // again
+            0xE0, 0x9E, 0x12, 0x1C, 0x00, 0xE0, 0x00, 0xE0, 0x12,
+            0x1E, // if ... begin ... else
+            0xA2, 0x26, // i := data
+            0x22, 0x22, // draw
+            0x12, 0x00, // jump main
+            0xD3, 0x01, 0x00, 0xEE, // : draw
+            0x3C, 0x42, // : data
+        ]
+    );
+    assert_eq!(assembly.source_map[&0x202].line, 9);
+
+    // The disassembler's Octo output compiles back to the same bytes.
+    let disassembly = crate::disassembler::Disassembly::new(&assembly.rom).unwrap();
+    assert_eq!(
+        compile(&disassembly.octo_source()).unwrap().rom,
+        assembly.rom
+    );
+
+    // Without main first, 0x200 jumps to it.
+    let assembly = compile("0xFF : main clear").unwrap();
+    assert_eq!(assembly.rom, vec![0x12, 0x03, 0xFF, 0x00, 0xE0]);
+
+    // Oversized shift counts wrap rather than overflow.
+    assert!(compile(":calc big { 1 << 70 } : main v0 := big").is_ok());
+
+    let error = compile(": main\n  v0 := 300").unwrap_err();
+    assert_eq!((error.location.line, error.location.column), (2, 9));
+}
+
+#[test]
+fn runs_comparisons() {
+    use crate::chip8::Chip8;
+    use crate::quirks::Quirks;
+
+    let expected = |operator: &str, a: u8, b: u8| match operator {
+        "==" => a == b,
+        "!=" => a != b,
+        "<" => a < b,
+        ">" => a > b,
+        "<=" => a <= b,
+        _ => a >= b,
+    };
+    for operator in ["==", "!=", "<", ">", "<=", ">="] {
+        for value in [3, 5, 7] {
+            // v0 tests against a constant with `then`, v1 against a register
+            // with `begin ... else ... end`.
+            let source = format!(
+                ": main
+                    v3 := {value} v4 := 5 v0 := 0
+                    if v3 {operator} 5 then v0 := 1
+                    if v3 {operator} v4 begin v1 := 1 else v1 := 2 end
+                    loop again"
+            );
+            let mut chip8 = Chip8::new(Quirks::SUPER_CHIP);
+            chip8.load_rom(compile(&source).unwrap().rom).unwrap();
+            while !chip8.is_jumping_to_self() {
+                chip8.execute_cycle().unwrap();
+            }
+            let registers = chip8.get_registers();
+            let holds = expected(operator, value, 5);
+            assert_eq!(registers[0], holds as u8, "{value} {operator} 5");
+            assert_eq!(registers[1], 2 - holds as u8, "{value} {operator} v4");
+        }
+    }
+}
+
+#[test]
+fn data_fits_in_memory() {
+    let assembly = compile(": main\n:org 0xFFFE\n:byte 1\n2").unwrap();
+    assert_eq!(assembly.rom.len(), 0x10000 - PROGRAM_START);
+    assert_eq!(assembly.source_map[&0xFFFF].line, 4);
+
+    for (source, line) in [
+        (": main\n:org 0xFFFF\n:byte 1\n:byte 2", 4),
+        (": main\n:org 0xFFFF\n1 2", 3),
+        (": main\n:org 0xFFFE\ni := long main", 3),
+    ] {
+        let error = compile(source).unwrap_err();
+        assert_eq!(error.message, "program does not fit in memory", "{source}");
+        assert_eq!(error.location.line, line, "{source}");
+    }
+}
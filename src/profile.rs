@@ -0,0 +1,387 @@
+//! Where the cycles go: execution counts per address and per instruction,
+//! and per subroutine through the call stack.
+//!
+//! A subroutine is named after its entry point the way the disassembler
+//! names it, `sub_206` for code called at 0x206, with everything outside
+//! any call counted under `main`. The profiler follows CALL and RET with a
+//! stack of its own, kept as deep as the machine's.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::chip8::{Address, Instruction, PROGRAM_START};
+use crate::disassembler::{mnemonic, Syntax};
+
+/// A subroutine the profiler is inside, and how it got there.
+#[derive(Debug, Clone)]
+struct Frame {
+    function: Address,
+    caller: Address,
+    call_site: Address,
+    /// The cycle count when the subroutine was entered.
+    entered: u64,
+    /// Cycles spent in the subroutine's own code so far.
+    own: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct CallCost {
+    calls: u64,
+    /// Cycles spent inside the callee, its own calls included.
+    cycles: u64,
+    /// Cycles spent in the callee's own code.
+    own: u64,
+}
+
+/// Collects the profile. Attach one with `Chip8::set_profiler`.
+#[derive(Debug, Clone, Default)]
+pub struct Profiler {
+    cycles: u64,
+    /// Cycles per function and address, so code shared by several
+    /// subroutines is charged to each separately.
+    costs: BTreeMap<(Address, Address), u64>,
+    instructions: BTreeMap<Address, Instruction>,
+    variants: BTreeMap<String, u64>,
+    /// Keyed by caller, call site and callee.
+    calls: BTreeMap<(Address, Address, Address), CallCost>,
+    /// Keyed by the chain of subroutines from `main` down to the callee, so
+    /// the call tree can charge a subroutine to each caller separately.
+    paths: BTreeMap<Vec<Address>, CallCost>,
+    frames: Vec<Frame>,
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        return Profiler::default();
+    }
+
+    /// Takes over the subroutines a machine is already inside, as call sites
+    /// and their targets, outermost first. `Chip8::set_profiler` does this,
+    /// so returning from them is followed like any other return.
+    pub fn enter_calls(&mut self, calls: &[(Address, Address)]) {
+        for (call_site, function) in calls {
+            self.frames.push(Frame {
+                function: *function,
+                caller: self.current_function(),
+                call_site: *call_site,
+                entered: self.cycles,
+                own: 0,
+            });
+        }
+    }
+
+    /// Counts one execution of `instruction` at `address`; `stack_depth` is
+    /// the machine's stack depth once it has run.
+    pub fn record(&mut self, address: Address, instruction: Instruction, stack_depth: usize) {
+        let function = self.current_function();
+        self.cycles += 1;
+        *self.costs.entry((function, address)).or_default() += 1;
+        self.instructions.insert(address, instruction);
+        *self.variants.entry(variant_name(&instruction)).or_default() += 1;
+        if let Some(frame) = self.frames.last_mut() {
+            frame.own += 1;
+        }
+
+        if let Instruction::Call(target) = instruction {
+            if stack_depth > self.frames.len() {
+                self.frames.push(Frame {
+                    function: target,
+                    caller: function,
+                    call_site: address,
+                    entered: self.cycles,
+                    own: 0,
+                });
+            }
+        }
+        while self.frames.len() > stack_depth {
+            let frame = self.frames.pop().expect("checked the stack is not empty");
+            self.charge_call(&frame, self.cycles);
+        }
+    }
+
+    pub fn get_cycles(&self) -> u64 {
+        return self.cycles;
+    }
+
+    /// How many times the instruction at `address` ran.
+    pub fn get_address_count(&self, address: Address) -> u64 {
+        return self
+            .costs
+            .iter()
+            .filter(|((_, at), _)| *at == address)
+            .map(|(_, count)| count)
+            .sum();
+    }
+
+    /// How many times instructions of a variant ran, by its name in
+    /// `Instruction`, such as `Draw`.
+    pub fn get_variant_count(&self, variant: &str) -> u64 {
+        return self.variants.get(variant).copied().unwrap_or(0);
+    }
+
+    /// Cycles spent in the subroutine entered at `function`, first in its
+    /// own code and then counting the subroutines it called.
+    pub fn get_function_cycles(&self, function: Address) -> (u64, u64) {
+        let own: u64 = self
+            .costs
+            .range((function, 0)..=(function, Address::MAX))
+            .map(|(_, count)| count)
+            .sum();
+        let called: u64 = self
+            .charged()
+            .calls
+            .iter()
+            .filter(|((caller, _, _), _)| *caller == function)
+            .map(|(_, cost)| cost.cycles)
+            .sum();
+        return (own, own + called);
+    }
+
+    /// A readable report: the `top` hottest addresses, every instruction
+    /// variant seen, and the call tree.
+    pub fn report(&self, top: usize) -> String {
+        let mut report = format!("{} cycles\n\nhottest addresses\n", self.cycles);
+        let _ = writeln!(
+            report,
+            "{:>12} {:>7}  address  {:<10} instruction",
+            "cycles", "%", "function"
+        );
+        // Each address's total over every function, and the function that
+        // spent the most there.
+        let mut totals: BTreeMap<Address, u64> = BTreeMap::new();
+        let mut owners: BTreeMap<Address, (u64, Address)> = BTreeMap::new();
+        for ((function, address), count) in &self.costs {
+            *totals.entry(*address).or_default() += count;
+            let owner = owners.entry(*address).or_insert((*count, *function));
+            if *count >= owner.0 {
+                *owner = (*count, *function);
+            }
+        }
+        let mut addresses: Vec<(Address, u64)> = totals.into_iter().collect();
+        addresses.sort_by(|left, right| right.1.cmp(&left.1).then(left.0.cmp(&right.0)));
+        for (address, count) in addresses.iter().take(top) {
+            let function = owners[address].1;
+            let _ = writeln!(
+                report,
+                "{count:>12} {:>7}  0x{address:04X}   {:<10} {}",
+                self.percent(*count),
+                function_name(function),
+                mnemonic(&self.instructions[address], Syntax::Classic)
+            );
+        }
+
+        let _ = writeln!(
+            report,
+            "\ninstructions\n{:>12} {:>7}  instruction",
+            "count", "%"
+        );
+        let mut variants: Vec<(&String, &u64)> = self.variants.iter().collect();
+        variants.sort_by(|left, right| right.1.cmp(left.1).then(left.0.cmp(right.0)));
+        for (variant, count) in variants {
+            let _ = writeln!(report, "{count:>12} {:>7}  {variant}", self.percent(*count));
+        }
+
+        let _ = writeln!(
+            report,
+            "\ncall tree{:>31} {:>12} {:>12} {:>7}",
+            "calls", "self", "inclusive", "%"
+        );
+        let (own, inclusive) = self.get_function_cycles(PROGRAM_START as Address);
+        let root = CallCost {
+            calls: 1,
+            cycles: inclusive,
+            own,
+        };
+        let paths = self.charged().paths;
+        self.report_path(&mut report, &paths, &[PROGRAM_START as Address], root);
+        return report;
+    }
+
+    fn report_path(
+        &self,
+        report: &mut String,
+        paths: &BTreeMap<Vec<Address>, CallCost>,
+        path: &[Address],
+        cost: CallCost,
+    ) {
+        let function = *path.last().expect("a path starts at main");
+        let name = format!("{}{}", "  ".repeat(path.len() - 1), function_name(function));
+        let _ = writeln!(
+            report,
+            "{name:<30} {:>9} {:>12} {:>12} {:>7}",
+            cost.calls,
+            cost.own,
+            cost.cycles,
+            self.percent(cost.cycles)
+        );
+        // The stack is only sixteen deep, so even recursion ends.
+        for (callee, cost) in paths {
+            if callee.len() == path.len() + 1 && callee.starts_with(path) {
+                self.report_path(report, paths, callee, *cost);
+            }
+        }
+    }
+
+    /// The profile in callgrind's format, for KCachegrind and friends.
+    /// `file` names the ROM in the output.
+    pub fn callgrind(&self, file: &str) -> String {
+        let mut output = String::from("# callgrind format\nversion: 1\n");
+        output.push_str("creator: chip-8-interpreter\npositions: instr\nevents: Cycles\n");
+        let _ = writeln!(output, "summary: {}\n\nfl={file}", self.cycles);
+
+        let calls = self.charged().calls;
+        let mut functions: Vec<Address> =
+            self.costs.keys().map(|(function, _)| *function).collect();
+        functions.dedup();
+        for function in functions {
+            let _ = writeln!(output, "\nfn={}", function_name(function));
+            for ((_, address), count) in self.costs.range((function, 0)..=(function, Address::MAX))
+            {
+                let _ = writeln!(output, "0x{address:X} {count}");
+            }
+            for ((_, call_site, callee), cost) in
+                calls.range((function, 0, 0)..=(function, Address::MAX, Address::MAX))
+            {
+                let _ = writeln!(
+                    output,
+                    "cfn={}\ncalls={} 0x{callee:X}\n0x{call_site:X} {}",
+                    function_name(*callee),
+                    cost.calls,
+                    cost.cycles
+                );
+            }
+        }
+        return output;
+    }
+
+    fn current_function(&self) -> Address {
+        return self
+            .frames
+            .last()
+            .map_or(PROGRAM_START as Address, |frame| frame.function);
+    }
+
+    /// The profile with the calls still running charged up to now.
+    fn charged(&self) -> Profiler {
+        let mut profile = self.clone();
+        while let Some(frame) = profile.frames.pop() {
+            profile.charge_call(&frame, self.cycles);
+        }
+        return profile;
+    }
+
+    /// Charges a call that has just left the frame stack.
+    fn charge_call(&mut self, frame: &Frame, now: u64) {
+        let mut path = vec![PROGRAM_START as Address];
+        path.extend(self.frames.iter().map(|frame| frame.function));
+        path.push(frame.function);
+        let edge = (frame.caller, frame.call_site, frame.function);
+        for cost in [
+            self.calls.entry(edge).or_default(),
+            self.paths.entry(path).or_default(),
+        ] {
+            cost.calls += 1;
+            cost.cycles += now - frame.entered;
+            cost.own += frame.own;
+        }
+    }
+
+    fn percent(&self, count: u64) -> String {
+        return format!("{:.1}%", count as f64 * 100.0 / self.cycles.max(1) as f64);
+    }
+}
+
+fn function_name(function: Address) -> String {
+    if function as usize == PROGRAM_START {
+        return String::from("main");
+    }
+    return format!("sub_{function:03x}");
+}
+
+/// `SetK` for `SetK(1, 2)`.
+fn variant_name(instruction: &Instruction) -> String {
+    let name = format!("{instruction:?}");
+    return match name.split_once('(') {
+        Some((variant, _)) => variant.to_string(),
+        None => name,
+    };
+}
+
+#[test]
+fn profiles_subroutines() {
+    use crate::chip8::Chip8;
+    use crate::quirks::Quirks;
+
+    // main: 2206 1200   sub_206: 7001 2210 7001 00EE   (padding)   sub_210: 00EE
+    let mut chip8 = Chip8::new(Quirks::SUPER_CHIP);
+    let mut rom = vec![0x22, 0x06, 0x12, 0x00, 0x00, 0x00, 0x70, 0x01, 0x22, 0x10];
+    rom.extend_from_slice(&[0x70, 0x01, 0x00, 0xEE, 0x00, 0x00, 0x00, 0xEE]);
+    chip8.load_rom(rom).unwrap();
+    chip8.set_profiler(Profiler::new());
+    // Two trips round main, then a third call still running at the end.
+    for _ in 0..16 {
+        chip8.execute_cycle().unwrap();
+    }
+    let profiler = chip8.take_profiler().unwrap();
+
+    assert_eq!(profiler.get_cycles(), 16);
+    assert_eq!(profiler.get_address_count(0x206), 3);
+    assert_eq!(profiler.get_variant_count("AddK"), 5);
+    assert_eq!(profiler.get_variant_count("Call"), 5);
+    assert_eq!(profiler.get_function_cycles(0x210), (2, 2));
+    assert_eq!(profiler.get_function_cycles(0x206), (9, 11));
+    assert_eq!(profiler.get_function_cycles(0x200), (5, 16));
+
+    let report = profiler.report(3);
+    assert!(report.contains("           3   18.8%  0x0206   sub_206    ADD V0, 0x01\n"));
+    assert!(report
+        .contains("  sub_206                              3            9           11   68.8%\n"));
+    assert!(report
+        .contains("    sub_210                            2            2            2   12.5%\n"));
+    let callgrind = profiler.callgrind("test.ch8");
+    assert!(callgrind.contains("fn=main\n0x200 3\n0x202 2\ncfn=sub_206\ncalls=3 0x206\n0x200 11\n"));
+}
+
+#[test]
+fn profiles_call_paths() {
+    use crate::chip8::Chip8;
+    use crate::quirks::Quirks;
+
+    // main: 2206 220A 1204   sub_206: 220E 00EE   sub_20a: 220E 00EE
+    // sub_20e: 2212 00EE   sub_212: 7001 00EE
+    let rom = vec![
+        0x22, 0x06, 0x22, 0x0A, 0x12, 0x04, 0x22, 0x0E, 0x00, 0xEE, 0x22, 0x0E, 0x00, 0xEE, 0x22,
+        0x12, 0x00, 0xEE, 0x70, 0x01, 0x00, 0xEE,
+    ];
+    let mut chip8 = Chip8::new(Quirks::SUPER_CHIP);
+    chip8.load_rom(rom.clone()).unwrap();
+    chip8.set_profiler(Profiler::new());
+    for _ in 0..15 {
+        chip8.execute_cycle().unwrap();
+    }
+    let report = chip8.take_profiler().unwrap().report(0);
+    // sub_20e's own cycles are split between its two callers.
+    assert!(report.ends_with(
+        "main                                   1            3           15  100.0%\n\
+         \x20 sub_206                              1            2            6   40.0%\n\
+         \x20   sub_20e                            1            2            4   26.7%\n\
+         \x20     sub_212                          1            2            2   13.3%\n\
+         \x20 sub_20a                              1            2            6   40.0%\n\
+         \x20   sub_20e                            1            2            4   26.7%\n\
+         \x20     sub_212                          1            2            2   13.3%\n"
+    ));
+
+    // Attached two calls deep, the profiler still sees them return.
+    let mut chip8 = Chip8::new(Quirks::SUPER_CHIP);
+    chip8.load_rom(rom).unwrap();
+    chip8.execute_cycle().unwrap();
+    chip8.execute_cycle().unwrap();
+    chip8.set_profiler(Profiler::new());
+    for _ in 0..6 {
+        chip8.execute_cycle().unwrap();
+    }
+    let profiler = chip8.take_profiler().unwrap();
+    assert_eq!(profiler.get_function_cycles(0x20E), (2, 4));
+    assert_eq!(profiler.get_function_cycles(0x206), (1, 5));
+    assert_eq!(profiler.get_function_cycles(0x200), (1, 6));
+}
@@ -0,0 +1,192 @@
+/// Behaviour switches for the opcodes that historical interpreters disagree on.
+///
+/// ROMs are usually written against one specific interpreter, so the right
+/// combination depends on the ROM rather than on the emulator. The presets
+/// below cover the common targets; individual fields can be overridden on top.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// 8XY6/8XYE shift VY and store the result in VX, instead of shifting VX in place.
+    pub shift_uses_vy: bool,
+    /// FX55/FX65 leave I pointing one past the last register stored or loaded.
+    pub load_store_increments_i: bool,
+    /// FX55/FX65 leave I pointing at the last register stored or loaded, one
+    /// short of the VIP, as CHIP-48 did. Ignored with `load_store_increments_i`.
+    pub load_store_increments_i_by_x: bool,
+    /// 8XY1/8XY2/8XY3 reset VF to 0.
+    pub vf_reset: bool,
+    /// BNNN is read as BXNN and jumps to NNN + VX instead of NNN + V0.
+    pub jump_with_vx: bool,
+    /// Sprites are clipped at the screen edges instead of wrapping around.
+    pub clip_sprites: bool,
+    /// DXYN waits for the next 60 Hz frame before drawing.
+    pub display_wait: bool,
+    /// 64 KiB of addressable memory as on XO-CHIP, instead of 4 KiB.
+    pub extended_memory: bool,
+    /// In lores, 00CN, 00DN, 00FB and 00FC move hires pixels as on SUPER-CHIP
+    /// 1.1, so half as far, instead of whole lores pixels as in Octo.
+    pub lores_scrolls_hires_pixels: bool,
+}
+
+impl Quirks {
+    /// The original RCA COSMAC VIP interpreter.
+    pub const COSMAC_VIP: Quirks = Quirks {
+        shift_uses_vy: true,
+        load_store_increments_i: true,
+        load_store_increments_i_by_x: false,
+        vf_reset: true,
+        jump_with_vx: false,
+        clip_sprites: true,
+        display_wait: true,
+        extended_memory: false,
+        lores_scrolls_hires_pixels: false,
+    };
+
+    /// CHIP-48 for the HP-48 calculators, which SUPER-CHIP grew out of. It
+    /// still moved I on FX55/FX65, but by X rather than X + 1.
+    pub const CHIP_48: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store_increments_i: false,
+        load_store_increments_i_by_x: true,
+        vf_reset: false,
+        jump_with_vx: true,
+        clip_sprites: true,
+        display_wait: false,
+        extended_memory: false,
+        lores_scrolls_hires_pixels: false,
+    };
+
+    /// SUPER-CHIP 1.1.
+    pub const SUPER_CHIP: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store_increments_i: false,
+        load_store_increments_i_by_x: false,
+        vf_reset: false,
+        jump_with_vx: true,
+        clip_sprites: true,
+        display_wait: false,
+        extended_memory: false,
+        lores_scrolls_hires_pixels: true,
+    };
+
+    /// XO-CHIP as implemented by Octo.
+    pub const XO_CHIP: Quirks = Quirks {
+        shift_uses_vy: true,
+        load_store_increments_i: true,
+        load_store_increments_i_by_x: false,
+        vf_reset: false,
+        jump_with_vx: false,
+        clip_sprites: false,
+        display_wait: false,
+        extended_memory: true,
+        lores_scrolls_hires_pixels: false,
+    };
+
+    pub const PRESETS: [(&'static str, Quirks); 4] = [
+        ("vip", Quirks::COSMAC_VIP),
+        ("chip48", Quirks::CHIP_48),
+        ("schip", Quirks::SUPER_CHIP),
+        ("xochip", Quirks::XO_CHIP),
+    ];
+
+    /// Packs the switches into bits, in the order they were added.
+    pub fn to_bits(&self) -> u16 {
+        let switches = [
+            self.shift_uses_vy,
+            self.load_store_increments_i,
+            self.vf_reset,
+            self.jump_with_vx,
+            self.clip_sprites,
+            self.display_wait,
+            self.extended_memory,
+            self.load_store_increments_i_by_x,
+            self.lores_scrolls_hires_pixels,
+        ];
+        return switches
+            .iter()
+            .enumerate()
+            .fold(0, |bits, (bit, on)| bits | (*on as u16) << bit);
+    }
+
+    /// The inverse of `to_bits`, or `None` if an unused bit is set or both
+    /// ways of moving I on FX55/FX65 are.
+    pub fn from_bits(bits: u16) -> Option<Quirks> {
+        let bit = |index: u8| return bits & (1 << index) != 0;
+        if bits >> 9 != 0 || (bit(1) && bit(7)) {
+            return None;
+        }
+        return Some(Quirks {
+            shift_uses_vy: bit(0),
+            load_store_increments_i: bit(1),
+            load_store_increments_i_by_x: bit(7),
+            vf_reset: bit(2),
+            jump_with_vx: bit(3),
+            clip_sprites: bit(4),
+            display_wait: bit(5),
+            extended_memory: bit(6),
+            lores_scrolls_hires_pixels: bit(8),
+        });
+    }
+
+    /// How far FX55/FX65 with register `x` move I.
+    pub fn load_store_increment(&self, x: u8) -> u16 {
+        if self.load_store_increments_i {
+            return x as u16 + 1;
+        } else if self.load_store_increments_i_by_x {
+            return x as u16;
+        }
+        return 0;
+    }
+
+    pub fn from_name(name: &str) -> Option<Quirks> {
+        return Quirks::PRESETS
+            .iter()
+            .find(|(preset, _)| preset.eq_ignore_ascii_case(name))
+            .map(|(_, quirks)| *quirks);
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Quirks {
+        return Quirks::COSMAC_VIP;
+    }
+}
+
+#[test]
+fn packs_and_names_presets() {
+    for (name, quirks) in Quirks::PRESETS {
+        assert_eq!(Quirks::from_bits(quirks.to_bits()), Some(quirks), "{name}");
+        assert_eq!(Quirks::from_name(&name.to_uppercase()), Some(quirks));
+    }
+    for bits in 0..=0x1FF {
+        if let Some(quirks) = Quirks::from_bits(bits) {
+            assert_eq!(quirks.to_bits(), bits);
+        }
+    }
+    assert_eq!(Quirks::from_bits(0x82), None);
+    assert_eq!(Quirks::from_bits(0x200), None);
+    assert_eq!(Quirks::from_name("chip-9"), None);
+    assert_eq!(Quirks::from_name(""), None);
+}
+
+#[test]
+fn chip_48_moves_i_by_x() {
+    use crate::chip8::Chip8;
+
+    // A300 F255 F265: I = 0x300, store V0-V2, load V0-V2
+    for (quirks, after_store, after_load) in [
+        (Quirks::COSMAC_VIP, 0x303, 0x306),
+        (Quirks::CHIP_48, 0x302, 0x304),
+        (Quirks::SUPER_CHIP, 0x300, 0x300),
+    ] {
+        let mut chip8 = Chip8::new(quirks);
+        chip8
+            .load_rom(vec![0xA3, 0x00, 0xF2, 0x55, 0xF2, 0x65])
+            .unwrap();
+        chip8.execute_cycle().unwrap();
+        chip8.execute_cycle().unwrap();
+        assert_eq!(chip8.get_index_register(), after_store);
+        chip8.execute_cycle().unwrap();
+        assert_eq!(chip8.get_index_register(), after_load);
+    }
+    assert_ne!(Quirks::CHIP_48, Quirks::SUPER_CHIP);
+}
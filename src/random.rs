@@ -0,0 +1,130 @@
+/// Where CXNN gets its random bytes.
+///
+/// The state is part of save states, so a generator must be able to describe
+/// where it is in its sequence with a single number and resume from it.
+pub trait RandomSource {
+    fn next_byte(&mut self) -> u8;
+    /// Names the generator in save states, so a state is only ever resumed
+    /// by the kind of generator that wrote it.
+    fn kind(&self) -> &'static str;
+    fn get_state(&self) -> u64;
+    fn set_state(&mut self, state: u64);
+}
+
+/// The default generator, SplitMix64. Two machines built with the same seed
+/// produce the same bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeededRandom {
+    state: u64,
+}
+
+impl SeededRandom {
+    pub const KIND: &'static str = "splitmix64";
+
+    pub fn new(seed: u64) -> SeededRandom {
+        return SeededRandom { state: seed };
+    }
+}
+
+impl RandomSource for SeededRandom {
+    fn next_byte(&mut self) -> u8 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        return ((z ^ (z >> 31)) >> 56) as u8;
+    }
+
+    fn kind(&self) -> &'static str {
+        return SeededRandom::KIND;
+    }
+
+    fn get_state(&self) -> u64 {
+        return self.state;
+    }
+
+    fn set_state(&mut self, state: u64) {
+        self.state = state;
+    }
+}
+
+/// Hands out a fixed list of bytes, starting over at the end, so tests can
+/// decide exactly what CXNN sees.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptedRandom {
+    values: Vec<u8>,
+    position: usize,
+}
+
+impl ScriptedRandom {
+    pub const KIND: &'static str = "scripted";
+
+    pub fn new(values: Vec<u8>) -> ScriptedRandom {
+        return ScriptedRandom {
+            values,
+            position: 0,
+        };
+    }
+}
+
+impl RandomSource for ScriptedRandom {
+    fn next_byte(&mut self) -> u8 {
+        if self.values.is_empty() {
+            return 0;
+        }
+        let value = self.values[self.position % self.values.len()];
+        self.position += 1;
+        return value;
+    }
+
+    fn kind(&self) -> &'static str {
+        return ScriptedRandom::KIND;
+    }
+
+    fn get_state(&self) -> u64 {
+        return self.position as u64;
+    }
+
+    fn set_state(&mut self, state: u64) {
+        self.position = state as usize;
+    }
+}
+
+#[test]
+fn splitmix64_sequence() {
+    // The top bytes of SplitMix64's first outputs from seed 0:
+    // 0xE220A8397B1DCDAF, 0x6E789E6AA1B965F4, 0x06C45D188009454F.
+    let mut random = SeededRandom::new(0);
+    let bytes: Vec<u8> = (0..3).map(|_| random.next_byte()).collect();
+    assert_eq!(bytes, [0xE2, 0x6E, 0x06]);
+
+    let mut other = SeededRandom::new(1);
+    assert_ne!(other.next_byte(), 0xE2);
+}
+
+#[test]
+fn scripted_values_repeat() {
+    let mut random = ScriptedRandom::new(vec![1, 2, 3]);
+    let bytes: Vec<u8> = (0..7).map(|_| random.next_byte()).collect();
+    assert_eq!(bytes, [1, 2, 3, 1, 2, 3, 1]);
+
+    let mut empty = ScriptedRandom::new(Vec::new());
+    assert_eq!(empty.next_byte(), 0);
+    assert_eq!(empty.next_byte(), 0);
+}
+
+#[test]
+fn resumes_from_saved_state() {
+    let sources: [Box<dyn RandomSource>; 2] = [
+        Box::new(SeededRandom::new(42)),
+        Box::new(ScriptedRandom::new(vec![5, 6, 7, 8])),
+    ];
+    for mut random in sources {
+        random.next_byte();
+        let state = random.get_state();
+        let expected: Vec<u8> = (0..6).map(|_| random.next_byte()).collect();
+        random.set_state(state);
+        let resumed: Vec<u8> = (0..6).map(|_| random.next_byte()).collect();
+        assert_eq!(resumed, expected, "{}", random.kind());
+    }
+}
@@ -0,0 +1,140 @@
+use std::collections::VecDeque;
+
+use crate::chip8::Chip8;
+use crate::error::StateError;
+use crate::state::{StateReader, StateWriter};
+
+/// Recent frames to step back through, a few bytes each.
+///
+/// Only the newest snapshot is kept whole. Each older frame is stored as the
+/// XOR of its snapshot with the next newer one, with zero runs packed away,
+/// so a frame where little changed costs almost nothing. Reaching a frame
+/// `n` back means undoing `n` deltas starting from the newest snapshot.
+#[derive(Debug, Clone)]
+pub struct RewindBuffer {
+    capacity: usize,
+    latest: Option<Vec<u8>>,
+    /// Oldest first; the last entry turns `latest` into the frame before it.
+    deltas: VecDeque<Vec<u8>>,
+}
+
+impl RewindBuffer {
+    /// A buffer that remembers at most `capacity` frames.
+    pub fn new(capacity: usize) -> RewindBuffer {
+        return RewindBuffer {
+            capacity: capacity.max(1),
+            latest: None,
+            deltas: VecDeque::new(),
+        };
+    }
+
+    /// Number of frames that can be restored, counting the newest.
+    pub fn len(&self) -> usize {
+        return match self.latest {
+            Some(_) => self.deltas.len() + 1,
+            None => 0,
+        };
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.latest.is_none();
+    }
+
+    /// Approximate memory used by the snapshots, in bytes.
+    pub fn size_in_bytes(&self) -> usize {
+        let latest = self.latest.as_ref().map_or(0, |latest| latest.len());
+        return latest + self.deltas.iter().map(|delta| delta.len()).sum::<usize>();
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+    }
+
+    /// Snapshots `chip8`, dropping the oldest frame once the buffer is full.
+    pub fn record(&mut self, chip8: &Chip8) {
+        let snapshot = chip8.write_state(StateWriter::unpacked());
+        if let Some(previous) = self.latest.take() {
+            self.deltas.push_back(delta(&snapshot, &previous));
+            if self.deltas.len() >= self.capacity {
+                self.deltas.pop_front();
+            }
+        }
+        self.latest = Some(snapshot);
+    }
+
+    /// Removes every frame newer than `frames` back and returns the snapshot
+    /// of that frame, which becomes the newest. Asking for more frames than
+    /// are buffered gives the oldest one.
+    pub(crate) fn rewind(&mut self, frames: usize) -> Result<Option<Vec<u8>>, StateError> {
+        let mut snapshot = match self.latest.take() {
+            Some(latest) => latest,
+            None => return Ok(None),
+        };
+        for _ in 0..frames {
+            match self.deltas.pop_back() {
+                Some(delta) => snapshot = apply(&snapshot, &delta)?,
+                None => break,
+            }
+        }
+        self.latest = Some(snapshot.clone());
+        return Ok(Some(snapshot));
+    }
+}
+
+/// Encodes the change from `from` to `to` as the length of `to` followed by
+/// the packed XOR of the two, padded with zeros to the longer length.
+fn delta(from: &[u8], to: &[u8]) -> Vec<u8> {
+    let length = from.len().max(to.len());
+    let xor: Vec<u8> = (0..length)
+        .map(|index| from.get(index).unwrap_or(&0) ^ to.get(index).unwrap_or(&0))
+        .collect();
+    let mut writer = StateWriter::new();
+    writer.varint(to.len());
+    writer.packed(&xor);
+    return writer.finish();
+}
+
+fn apply(from: &[u8], delta: &[u8]) -> Result<Vec<u8>, StateError> {
+    let mut reader = StateReader::new(delta);
+    let length = reader.varint()?;
+    let xor = reader.packed(usize::MAX)?;
+    let mut to: Vec<u8> = (0..xor.len())
+        .map(|index| from.get(index).unwrap_or(&0) ^ xor[index])
+        .collect();
+    to.truncate(length);
+    return Ok(to);
+}
+
+#[test]
+fn rewinds_frames() {
+    use crate::quirks::Quirks;
+
+    // 7001 D005 1200: count up in V0 and draw, forever
+    let mut chip8 = Chip8::new(Quirks::SUPER_CHIP);
+    chip8.set_instructions_per_frame(3);
+    chip8
+        .load_rom(vec![0x70, 0x01, 0xD0, 0x05, 0x12, 0x00])
+        .unwrap();
+    let mut buffer = RewindBuffer::new(4);
+    let mut states = Vec::new();
+    for _ in 0..6 {
+        chip8.run_frame().unwrap();
+        buffer.record(&chip8);
+        states.push(chip8.save_state());
+    }
+    assert_eq!(buffer.len(), 4);
+    // A frame's delta is far smaller than the 12 KiB snapshot.
+    assert!(buffer.deltas.iter().all(|delta| delta.len() < 64));
+
+    assert!(chip8.rewind(&mut buffer, 2).unwrap());
+    assert_eq!(chip8.save_state(), states[3]);
+    assert_eq!(buffer.len(), 2);
+    // A key held while rewinding stays held.
+    chip8.set_key(0x3, true);
+    assert!(chip8.rewind(&mut buffer, 10).unwrap());
+    assert!(chip8.get_keys()[0x3]);
+    chip8.set_key(0x3, false);
+    assert_eq!(chip8.save_state(), states[2]);
+    assert_eq!(buffer.len(), 1);
+}
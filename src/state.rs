@@ -0,0 +1,191 @@
+//! Helpers for the binary save state format.
+//!
+//! Multi-byte integers are little-endian. Large, mostly empty buffers such
+//! as memory and the framebuffer are stored as alternating runs of zeros and
+//! literal bytes, each run length a LEB128 varint, so a 64 KiB XO-CHIP
+//! memory with a small ROM costs a few kilobytes.
+
+use crate::error::StateError;
+
+pub(crate) const MAGIC: &[u8; 4] = b"C8ST";
+
+pub(crate) struct StateWriter {
+    bytes: Vec<u8>,
+    pack: bool,
+}
+
+impl StateWriter {
+    pub fn new() -> StateWriter {
+        return StateWriter {
+            bytes: Vec::new(),
+            pack: true,
+        };
+    }
+
+    /// A writer that stores buffers as a single literal run, so the layout
+    /// barely moves between states and two states can be diffed bytewise.
+    pub fn unpacked() -> StateWriter {
+        return StateWriter {
+            bytes: Vec::new(),
+            pack: false,
+        };
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        return self.bytes;
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    pub fn varint(&mut self, mut value: usize) {
+        loop {
+            let byte = (value & 0x7F) as u8;
+            value >>= 7;
+            if value == 0 {
+                self.u8(byte);
+                return;
+            }
+            self.u8(byte | 0x80);
+        }
+    }
+
+    /// Writes `bytes` with zero runs squeezed out.
+    pub fn packed(&mut self, bytes: &[u8]) {
+        self.varint(bytes.len());
+        if !self.pack {
+            if !bytes.is_empty() {
+                self.varint(0);
+                self.varint(bytes.len());
+                self.bytes(bytes);
+            }
+            return;
+        }
+        let mut position = 0;
+        while position < bytes.len() {
+            let zeros = bytes[position..]
+                .iter()
+                .take_while(|byte| **byte == 0)
+                .count();
+            position += zeros;
+            // A lone zero inside literal data is cheaper to keep than to split on.
+            let literals = bytes[position..]
+                .windows(2)
+                .position(|pair| pair == [0, 0])
+                .unwrap_or(bytes.len() - position);
+            self.varint(zeros);
+            self.varint(literals);
+            self.bytes(&bytes[position..position + literals]);
+            position += literals;
+        }
+    }
+}
+
+pub(crate) struct StateReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> StateReader<'a> {
+        return StateReader { bytes, position: 0 };
+    }
+
+    pub fn is_at_end(&self) -> bool {
+        return self.position == self.bytes.len();
+    }
+
+    pub fn bytes(&mut self, length: usize) -> Result<&'a [u8], StateError> {
+        let end = self
+            .position
+            .checked_add(length)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or(StateError::Truncated)?;
+        let bytes = &self.bytes[self.position..end];
+        self.position = end;
+        return Ok(bytes);
+    }
+
+    pub fn array<const N: usize>(&mut self) -> Result<[u8; N], StateError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.bytes(N)?);
+        return Ok(array);
+    }
+
+    pub fn u8(&mut self) -> Result<u8, StateError> {
+        return Ok(self.bytes(1)?[0]);
+    }
+
+    pub fn bool(&mut self) -> Result<bool, StateError> {
+        return match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Invalid("boolean")),
+        };
+    }
+
+    pub fn u16(&mut self) -> Result<u16, StateError> {
+        return Ok(u16::from_le_bytes(self.array()?));
+    }
+
+    pub fn u32(&mut self) -> Result<u32, StateError> {
+        return Ok(u32::from_le_bytes(self.array()?));
+    }
+
+    pub fn u64(&mut self) -> Result<u64, StateError> {
+        return Ok(u64::from_le_bytes(self.array()?));
+    }
+
+    pub fn varint(&mut self) -> Result<usize, StateError> {
+        let mut value = 0usize;
+        for shift in (0..usize::BITS).step_by(7) {
+            let byte = self.u8()?;
+            value |= ((byte & 0x7F) as usize) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        return Err(StateError::Invalid("length"));
+    }
+
+    /// Reads what `StateWriter::packed` wrote, which must be at most `limit` bytes.
+    pub fn packed(&mut self, limit: usize) -> Result<Vec<u8>, StateError> {
+        let length = self.varint()?;
+        if length > limit {
+            return Err(StateError::Invalid("buffer size"));
+        }
+        let mut bytes = Vec::with_capacity(length);
+        while bytes.len() < length {
+            let zeros = self.varint()?;
+            let literals = self.varint()?;
+            let run = zeros.saturating_add(literals);
+            if run == 0 || run > length - bytes.len() {
+                return Err(StateError::Invalid("run length"));
+            }
+            bytes.resize(bytes.len() + zeros, 0);
+            bytes.extend_from_slice(self.bytes(literals)?);
+        }
+        return Ok(bytes);
+    }
+}
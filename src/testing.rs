@@ -0,0 +1,211 @@
+//! Helpers for unit-testing a ROM's subroutines from Rust.
+//!
+//! Load the ROM, put the machine in the state a subroutine expects, run it
+//! with `Chip8::call` and check what it left behind:
+//!
+//! ```
+//! use chip_8_interpreter::{assert_memory, assert_registers, set_registers};
+//! use chip_8_interpreter::{Chip8, Quirks};
+//!
+//! // 0x200: store V0 and V1 at I, then add them into V0.
+//! let mut chip8 = Chip8::new(Quirks::SUPER_CHIP);
+//! chip8.load_rom(vec![0xF1, 0x55, 0x80, 0x14, 0x00, 0xEE]).unwrap();
+//! set_registers!(chip8, V0 = 0xF0, V1 = 0x20, I = 0x300);
+//! chip8.call(0x200, 100).unwrap();
+//! assert_registers!(chip8, V0 = 0x10, VF = 1, I = 0x300);
+//! assert_memory!(chip8, 0x300, [0xF0, 0x20]);
+//! ```
+//!
+//! Registers are named `V0` to `VF` and `I`, in either case.
+
+use crate::chip8::{Address, Chip8, RegisterNumber};
+
+/// Sets registers by name: `set_registers!(chip8, V0 = 1, VA = 2, I = 0x300)`.
+#[macro_export]
+macro_rules! set_registers {
+    ($chip8:expr, $($register:ident = $value:expr),+ $(,)?) => {
+        $(
+            $crate::testing::set_register(&mut $chip8, stringify!($register), $value as u16);
+        )+
+    };
+}
+
+/// Checks registers by name: `assert_registers!(chip8, V0 = 1, I = 0x300)`.
+#[macro_export]
+macro_rules! assert_registers {
+    ($chip8:expr, $($register:ident = $value:expr),+ $(,)?) => {
+        $(
+            assert_eq!(
+                $crate::testing::get_register(&$chip8, stringify!($register)),
+                $value as u16,
+                "register {}",
+                stringify!($register)
+            );
+        )+
+    };
+}
+
+/// Checks the bytes in memory from an address on:
+/// `assert_memory!(chip8, 0x300, [1, 2, 3])`.
+#[macro_export]
+macro_rules! assert_memory {
+    ($chip8:expr, $address:expr, [$($byte:expr),* $(,)?]) => {{
+        let expected: &[u8] = &[$($byte),*];
+        let address = $address as usize;
+        let memory = $chip8.get_memory();
+        assert!(
+            address.saturating_add(expected.len()) <= memory.len(),
+            "memory from {address:#05X}: {} bytes run past the end of {} bytes of memory",
+            expected.len(),
+            memory.len()
+        );
+        assert_eq!(
+            &memory[address..address + expected.len()],
+            expected,
+            "memory from {address:#05X}"
+        );
+    }};
+}
+
+/// Checks part of the screen, one string per row in `DisplayBuffer::to_text`
+/// characters, from the top left corner `x`, `y`:
+/// `assert_display!(chip8, 0, 0, ["#..#", ".##."])`.
+#[macro_export]
+macro_rules! assert_display {
+    ($chip8:expr, $x:expr, $y:expr, [$($row:expr),+ $(,)?]) => {{
+        let expected: Vec<&str> = vec![$($row),+];
+        assert_eq!(
+            $crate::testing::display_rows(&$chip8, $x, $y, expected[0].len(), expected.len()),
+            expected,
+            "display from ({}, {})",
+            $x,
+            $y
+        );
+    }};
+}
+
+/// Which V register a name like `V3` or `vf` means, or `None` for `I`.
+/// Panics on anything else, as a test with a typo should.
+fn parse_register(name: &str) -> Option<RegisterNumber> {
+    if name.eq_ignore_ascii_case("i") {
+        return None;
+    }
+    let number = name
+        .strip_prefix(['V', 'v'])
+        .filter(|digit| digit.len() == 1)
+        .and_then(|digit| RegisterNumber::from_str_radix(digit, 16).ok());
+    match number {
+        Some(number) => return Some(number),
+        None => panic!("'{name}' is not a register, expected V0-VF or I"),
+    }
+}
+
+/// Sets a register by name. Panics if a V register is given more than a byte.
+pub fn set_register(chip8: &mut Chip8, name: &str, value: u16) {
+    match parse_register(name) {
+        Some(register) => {
+            let value =
+                u8::try_from(value).unwrap_or_else(|_| panic!("{value:#X} does not fit in {name}"));
+            chip8.set_register(register, value);
+        }
+        None => chip8.set_index_register(value as Address),
+    }
+}
+
+pub fn get_register(chip8: &Chip8, name: &str) -> u16 {
+    return match parse_register(name) {
+        Some(register) => chip8.get_registers()[register as usize] as u16,
+        None => chip8.get_index_register(),
+    };
+}
+
+/// A `width` by `height` corner of the screen from `x`, `y`, as rows of
+/// `DisplayBuffer::to_text` characters.
+pub fn display_rows(chip8: &Chip8, x: usize, y: usize, width: usize, height: usize) -> Vec<String> {
+    let text = chip8.get_display_buffer().to_text();
+    return text
+        .lines()
+        .skip(y)
+        .take(height)
+        .map(|row| row.chars().skip(x).take(width).collect())
+        .collect();
+}
+
+#[test]
+fn calls_subroutines() {
+    use crate::assembler::assemble;
+    use crate::chip8::STACK_DEPTH;
+    use crate::error::Chip8Error;
+    use crate::quirks::Quirks;
+
+    let assembly = assemble(
+        "main:\n\
+         \tJP main\n\
+         draw:\n\
+         \tCALL glyph\n\
+         \tDRW V0, V1, 2\n\
+         \tRET\n\
+         glyph:\n\
+         \tLD I, sprite\n\
+         \tRET\n\
+         spin:\n\
+         \tJP spin\n\
+         quit:\n\
+         \tEXIT\n\
+         sprite:\n\
+         \tDB 0x90, 0x60\n",
+    )
+    .unwrap();
+    let mut chip8 = Chip8::new(Quirks::COSMAC_VIP);
+    chip8.load_rom(assembly.rom).unwrap();
+
+    set_registers!(chip8, V0 = 4, v1 = 2, VF = 0xFF);
+    assert_eq!(chip8.call(0x202, 100), Ok(5));
+    assert_eq!(chip8.get_program_counter(), 0x202);
+    assert!(chip8.get_stack().is_empty());
+    assert_registers!(chip8, V0 = 4, VF = 0, I = 0x210);
+    assert_memory!(chip8, 0x210, [0x90, 0x60]);
+    assert_display!(chip8, 3, 1, ["......", ".#..#.", "..##..", "......"]);
+
+    assert_eq!(
+        chip8.call(0x20C, 50),
+        Err(Chip8Error::CycleLimit {
+            program_counter: 0x20C,
+            limit: 50
+        })
+    );
+    assert_eq!(
+        chip8.call(0x20E, 50),
+        Err(Chip8Error::Exited {
+            program_counter: 0x20E
+        })
+    );
+    chip8.set_stack(&[0x200; STACK_DEPTH]);
+    assert!(matches!(
+        chip8.call(0x202, 50),
+        Err(Chip8Error::StackOverflow { .. })
+    ));
+}
+
+#[test]
+fn macros_are_expressions() {
+    use crate::quirks::Quirks;
+
+    let mut chip8 = Chip8::new(Quirks::SUPER_CHIP);
+    chip8.set_memory(0x300, &[1, 2]);
+    let check = |chip8: &Chip8| assert_memory!(chip8, 0x300, [1, 2]);
+    check(&chip8);
+    match chip8.get_display_buffer().is_hires() {
+        true => unreachable!(),
+        false => assert_display!(chip8, 0, 0, ["..", ".."]),
+    }
+}
+
+#[test]
+#[should_panic(expected = "memory from 0xFFF: 2 bytes run past the end of 4096 bytes of memory")]
+fn memory_past_the_end() {
+    use crate::quirks::Quirks;
+
+    let chip8 = Chip8::new(Quirks::SUPER_CHIP);
+    assert_memory!(chip8, 0xFFF, [0, 0]);
+}
@@ -0,0 +1,604 @@
+//! Instruction traces, one record per executed instruction, for comparing a
+//! run line by line against another emulator.
+//!
+//! Each record shows the machine as the instruction is about to execute,
+//! followed by the memory the instruction wrote. The text format lines up in
+//! columns:
+//!
+//! ```text
+//!      cycle   pc  opcode  instruction           V0 V1 V2 V3 V4 V5 V6 V7 V8 V9 VA VB VC VD VE VF     I SP DT ST  writes
+//!          0 0200  6A05    LD VA, 0x05           00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00  0000  0 00 00
+//!          1 0202  FA33    LD B, VA              00 00 00 00 00 00 00 00 00 00 05 00 00 00 00 00  0300  0 00 00  0300=00 0301=00 0302=05
+//! ```
+//!
+//! and the JSON-lines format has one object per line:
+//!
+//! ```text
+//! {"cycle":0,"pc":512,"opcode":27141,"instruction":"SetK(10, 5)","mnemonic":"LD VA, 0x05","v":[0,...],"i":0,"sp":0,"dt":0,"st":0,"writes":[]}
+//! ```
+//!
+//! `TraceRecord::parse` reads either format back, so traces from another
+//! emulator can be compared once they are written in one of them.
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::ops::RangeInclusive;
+
+use crate::chip8::{Address, Chip8, Instruction};
+use crate::debugger::parse_address;
+use crate::disassembler::{mnemonic, Syntax};
+
+/// The header line the text format starts each file with.
+pub const TEXT_HEADER: &str = "     cycle   pc  opcode  instruction           V0 V1 V2 V3 V4 V5 V6 V7 V8 V9 VA VB VC VD VE VF     I SP DT ST  writes";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    Text,
+    JsonLines,
+}
+
+impl TraceFormat {
+    pub fn from_name(name: &str) -> Option<TraceFormat> {
+        return match name.to_ascii_lowercase().as_str() {
+            "text" => Some(TraceFormat::Text),
+            "json" | "jsonl" => Some(TraceFormat::JsonLines),
+            _ => None,
+        };
+    }
+}
+
+/// The machine just before an instruction executes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceRecord {
+    /// Instructions executed before this one since the machine was created.
+    pub cycle: u64,
+    pub program_counter: Address,
+    pub opcode: u16,
+    pub instruction: Instruction,
+    pub registers: [u8; 16],
+    pub index_register: Address,
+    pub stack_pointer: usize,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    /// Addresses the instruction wrote and the values it left there.
+    pub memory_writes: Vec<(Address, u8)>,
+}
+
+impl TraceRecord {
+    pub fn to_text(&self) -> String {
+        let registers: Vec<String> = self
+            .registers
+            .iter()
+            .map(|value| format!("{value:02X}"))
+            .collect();
+        let mut line = format!(
+            "{:>10} {:04X}  {:04X}    {:<20}  {}  {:04X} {:>2} {:02X} {:02X}",
+            self.cycle,
+            self.program_counter,
+            self.opcode,
+            mnemonic(&self.instruction, Syntax::Classic),
+            registers.join(" "),
+            self.index_register,
+            self.stack_pointer,
+            self.delay_timer,
+            self.sound_timer,
+        );
+        if !self.memory_writes.is_empty() {
+            let writes: Vec<String> = self
+                .memory_writes
+                .iter()
+                .map(|(address, value)| format!("{address:04X}={value:02X}"))
+                .collect();
+            line.push_str("  ");
+            line.push_str(&writes.join(" "));
+        }
+        return line;
+    }
+
+    pub fn to_json(&self) -> String {
+        let registers: Vec<String> = self
+            .registers
+            .iter()
+            .map(|value| value.to_string())
+            .collect();
+        let writes: Vec<String> = self
+            .memory_writes
+            .iter()
+            .map(|(address, value)| format!("[{address},{value}]"))
+            .collect();
+        return format!(
+            "{{\"cycle\":{},\"pc\":{},\"opcode\":{},\"instruction\":\"{:?}\",\"mnemonic\":\"{}\",\"v\":[{}],\"i\":{},\"sp\":{},\"dt\":{},\"st\":{},\"writes\":[{}]}}",
+            self.cycle,
+            self.program_counter,
+            self.opcode,
+            self.instruction,
+            mnemonic(&self.instruction, Syntax::Classic),
+            registers.join(","),
+            self.index_register,
+            self.stack_pointer,
+            self.delay_timer,
+            self.sound_timer,
+            writes.join(","),
+        );
+    }
+
+    /// Reads a line written by `to_text` or `to_json`. The instruction is
+    /// decoded again from the opcode, so the mnemonic column is not needed.
+    pub fn parse(line: &str) -> Option<TraceRecord> {
+        let line = line.trim();
+        if line.starts_with('{') {
+            return TraceRecord::parse_json(line);
+        }
+        return TraceRecord::parse_text(line);
+    }
+
+    fn parse_text(line: &str) -> Option<TraceRecord> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let writes = fields
+            .iter()
+            .rev()
+            .take_while(|field| field.contains('='))
+            .count();
+        let (fields, writes) = fields.split_at(fields.len() - writes);
+        // cycle, pc and opcode, at least one word of mnemonic, then 20 columns
+        if fields.len() < 24 {
+            return None;
+        }
+        let hex = |field: &str| u16::from_str_radix(field, 16).ok();
+        let numbers = &fields[fields.len() - 20..];
+        let mut registers = [0; 16];
+        for (register, field) in registers.iter_mut().zip(numbers) {
+            *register = u8::from_str_radix(field, 16).ok()?;
+        }
+        let memory_writes = writes
+            .iter()
+            .map(|write| {
+                let (address, value) = write.split_once('=')?;
+                return Some((hex(address)?, u8::from_str_radix(value, 16).ok()?));
+            })
+            .collect::<Option<Vec<(Address, u8)>>>()?;
+        let opcode = hex(fields[2])?;
+        return Some(TraceRecord {
+            cycle: fields[0].parse().ok()?,
+            program_counter: hex(fields[1])?,
+            opcode,
+            instruction: Chip8::parse_instruction(opcode)?,
+            registers,
+            index_register: hex(numbers[16])?,
+            stack_pointer: numbers[17].parse().ok()?,
+            delay_timer: u8::from_str_radix(numbers[18], 16).ok()?,
+            sound_timer: u8::from_str_radix(numbers[19], 16).ok()?,
+            memory_writes,
+        });
+    }
+
+    fn parse_json(line: &str) -> Option<TraceRecord> {
+        let number = |key: &str| json_value(line, key)?.parse::<u64>().ok();
+        let numbers = |text: &str| -> Option<Vec<u64>> {
+            let text = text.strip_prefix('[')?.strip_suffix(']')?;
+            if text.trim().is_empty() {
+                return Some(Vec::new());
+            }
+            return text
+                .split(',')
+                .map(|number| number.trim().parse().ok())
+                .collect();
+        };
+
+        let registers = numbers(json_value(line, "v")?)?;
+        if registers.len() != 16 || registers.iter().any(|value| *value > 0xFF) {
+            return None;
+        }
+        let mut memory_writes = Vec::new();
+        let writes = json_value(line, "writes").unwrap_or("[]");
+        let writes = writes.strip_prefix('[')?.strip_suffix(']')?;
+        for write in writes
+            .split_inclusive(']')
+            .filter(|write| !write.trim().is_empty())
+        {
+            let write = numbers(write.trim_start_matches(',').trim())?;
+            match write.as_slice() {
+                [address, value] => memory_writes.push((
+                    Address::try_from(*address).ok()?,
+                    u8::try_from(*value).ok()?,
+                )),
+                _ => return None,
+            }
+        }
+        let opcode = u16::try_from(number("opcode")?).ok()?;
+        return Some(TraceRecord {
+            cycle: number("cycle")?,
+            program_counter: Address::try_from(number("pc")?).ok()?,
+            opcode,
+            instruction: Chip8::parse_instruction(opcode)?,
+            registers: std::array::from_fn(|index| registers[index] as u8),
+            index_register: Address::try_from(number("i")?).ok()?,
+            stack_pointer: number("sp")? as usize,
+            delay_timer: u8::try_from(number("dt")?).ok()?,
+            sound_timer: u8::try_from(number("st")?).ok()?,
+            memory_writes,
+        });
+    }
+}
+
+/// The raw text of a number or array value in one of our flat JSON objects.
+fn json_value<'a>(line: &'a str, key: &str) -> Option<&'a str> {
+    let start = line.find(&format!("\"{key}\":"))? + key.len() + 3;
+    let rest = &line[start..];
+    if rest.starts_with('[') {
+        let mut depth = 0;
+        for (index, character) in rest.char_indices() {
+            match character {
+                '[' => depth += 1,
+                ']' => depth -= 1,
+                _ => {}
+            }
+            if depth == 0 {
+                return Some(&rest[..=index]);
+            }
+        }
+        return None;
+    }
+    let end = rest.find([',', '}']).unwrap_or(rest.len());
+    return Some(rest[..end].trim());
+}
+
+/// Describes how two records of the same cycle differ, one entry per field,
+/// or nothing if they agree. The cycle count itself is not compared.
+pub fn differences(left: &TraceRecord, right: &TraceRecord) -> Vec<String> {
+    let mut differences = Vec::new();
+    if left.program_counter != right.program_counter {
+        differences.push(format!(
+            "PC {:04X} vs {:04X}",
+            left.program_counter, right.program_counter
+        ));
+    }
+    if left.opcode != right.opcode {
+        differences.push(format!(
+            "opcode {:04X} vs {:04X}",
+            left.opcode, right.opcode
+        ));
+    }
+    for (index, (left, right)) in left.registers.iter().zip(right.registers).enumerate() {
+        if *left != right {
+            differences.push(format!("V{index:X} {left:02X} vs {right:02X}"));
+        }
+    }
+    if left.index_register != right.index_register {
+        differences.push(format!(
+            "I {:04X} vs {:04X}",
+            left.index_register, right.index_register
+        ));
+    }
+    if left.stack_pointer != right.stack_pointer {
+        differences.push(format!(
+            "SP {} vs {}",
+            left.stack_pointer, right.stack_pointer
+        ));
+    }
+    if left.delay_timer != right.delay_timer {
+        differences.push(format!(
+            "DT {:02X} vs {:02X}",
+            left.delay_timer, right.delay_timer
+        ));
+    }
+    if left.sound_timer != right.sound_timer {
+        differences.push(format!(
+            "ST {:02X} vs {:02X}",
+            left.sound_timer, right.sound_timer
+        ));
+    }
+
+    let written = |record: &TraceRecord, address: Address| {
+        return match record
+            .memory_writes
+            .iter()
+            .rev()
+            .find(|(at, _)| *at == address)
+        {
+            Some((_, value)) => format!("{value:02X}"),
+            None => "unwritten".to_string(),
+        };
+    };
+    let mut addresses: Vec<Address> = left
+        .memory_writes
+        .iter()
+        .chain(&right.memory_writes)
+        .map(|(address, _)| *address)
+        .collect();
+    addresses.sort();
+    addresses.dedup();
+    for address in addresses {
+        let (left, right) = (written(left, address), written(right, address));
+        if left != right {
+            differences.push(format!("memory {address:04X} {left} vs {right}"));
+        }
+    }
+    return differences;
+}
+
+/// Reads a `start-end` range of hex addresses, both ends included.
+pub fn parse_address_range(text: &str) -> Option<RangeInclusive<Address>> {
+    let (start, end) = text.split_once('-')?;
+    let start = Address::try_from(parse_address(start)?).ok()?;
+    let end = Address::try_from(parse_address(end)?).ok()?;
+    return Some(start..=end);
+}
+
+enum TraceSink {
+    Buffer(Vec<u8>),
+    File {
+        path: String,
+        file: BufWriter<File>,
+        written: u64,
+    },
+}
+
+/// Writes trace records for the instructions it is shown, to memory or to a
+/// file. Attach one with `Chip8::set_tracer`.
+///
+/// With a maximum size set, a file that would grow past it is renamed to
+/// `<path>.1`, older ones moving up to `<path>.2` and so on, and a fresh file
+/// is started; only the newest `keep` old files are kept.
+pub struct Tracer {
+    format: TraceFormat,
+    ranges: Vec<RangeInclusive<Address>>,
+    max_size: Option<u64>,
+    keep: usize,
+    sink: TraceSink,
+    error: Option<io::Error>,
+}
+
+impl Tracer {
+    /// A tracer that keeps its output in memory, for tests.
+    pub fn to_buffer(format: TraceFormat) -> Tracer {
+        let mut tracer = Tracer::with_sink(format, TraceSink::Buffer(Vec::new()));
+        tracer.write_header();
+        return tracer;
+    }
+
+    pub fn to_file(path: &str, format: TraceFormat) -> io::Result<Tracer> {
+        let file = BufWriter::new(File::create(path)?);
+        let sink = TraceSink::File {
+            path: path.to_string(),
+            file,
+            written: 0,
+        };
+        let mut tracer = Tracer::with_sink(format, sink);
+        tracer.write_header();
+        return Ok(tracer);
+    }
+
+    fn with_sink(format: TraceFormat, sink: TraceSink) -> Tracer {
+        return Tracer {
+            format,
+            ranges: Vec::new(),
+            max_size: None,
+            keep: 1,
+            sink,
+            error: None,
+        };
+    }
+
+    /// Only traces instructions inside `range`. Several ranges can be added;
+    /// with none, everything is traced.
+    pub fn range(mut self, range: RangeInclusive<Address>) -> Tracer {
+        self.ranges.push(range);
+        return self;
+    }
+
+    /// Rotates the file once it reaches `max_size` bytes, keeping `keep` old ones.
+    pub fn max_size(mut self, max_size: u64, keep: usize) -> Tracer {
+        self.max_size = Some(max_size);
+        self.keep = keep;
+        return self;
+    }
+
+    /// Whether an instruction at `address` would be traced.
+    pub fn is_traced(&self, address: Address) -> bool {
+        return self.ranges.is_empty() || self.ranges.iter().any(|range| range.contains(&address));
+    }
+
+    /// The output so far of a tracer made with `to_buffer`.
+    pub fn get_buffer(&self) -> &[u8] {
+        return match &self.sink {
+            TraceSink::Buffer(buffer) => buffer,
+            TraceSink::File { .. } => &[],
+        };
+    }
+
+    /// Writes a record out, unless its address is filtered away. After a write
+    /// fails, the tracer stops and `finish` reports the error.
+    pub fn trace(&mut self, record: &TraceRecord) {
+        if self.error.is_some() || !self.is_traced(record.program_counter) {
+            return;
+        }
+        let line = match self.format {
+            TraceFormat::Text => record.to_text(),
+            TraceFormat::JsonLines => record.to_json(),
+        };
+        if let Err(error) = self.write_line(&line) {
+            self.error = Some(error);
+        }
+    }
+
+    /// Flushes the file and reports the first error the tracer ran into.
+    pub fn finish(mut self) -> io::Result<()> {
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+        if let TraceSink::File { file, .. } = &mut self.sink {
+            file.flush()?;
+        }
+        return Ok(());
+    }
+
+    fn write_header(&mut self) {
+        if self.format == TraceFormat::Text {
+            if let Err(error) = self.write_line(TEXT_HEADER) {
+                self.error = Some(error);
+            }
+        }
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let length = line.len() as u64 + 1;
+        match &mut self.sink {
+            TraceSink::Buffer(buffer) => {
+                buffer.extend_from_slice(line.as_bytes());
+                buffer.push(b'\n');
+            }
+            TraceSink::File {
+                path,
+                file,
+                written,
+            } => {
+                if let Some(max_size) = self.max_size {
+                    if *written > 0 && *written + length > max_size {
+                        file.flush()?;
+                        rotate(path, self.keep)?;
+                        *file = BufWriter::new(File::create(&*path)?);
+                        *written = 0;
+                        if self.format == TraceFormat::Text {
+                            writeln!(file, "{TEXT_HEADER}")?;
+                            *written += TEXT_HEADER.len() as u64 + 1;
+                        }
+                    }
+                }
+                writeln!(file, "{line}")?;
+                *written += length;
+            }
+        }
+        return Ok(());
+    }
+}
+
+/// Shifts `path.1` to `path.2` and so on, dropping the oldest, then moves
+/// `path` to `path.1`.
+fn rotate(path: &str, keep: usize) -> io::Result<()> {
+    if keep == 0 {
+        return fs::remove_file(path);
+    }
+    let _ = fs::remove_file(format!("{path}.{keep}"));
+    for index in (1..keep).rev() {
+        let from = format!("{path}.{index}");
+        if fs::metadata(&from).is_ok() {
+            fs::rename(&from, format!("{path}.{}", index + 1))?;
+        }
+    }
+    return fs::rename(path, format!("{path}.1"));
+}
+
+#[test]
+fn traces_instructions() {
+    use crate::chip8::Chip8;
+    use crate::quirks::Quirks;
+
+    // 6A05 2206 1202 7A01 00EE: set VA, then call a subroutine forever
+    let mut chip8 = Chip8::new(Quirks::SUPER_CHIP);
+    chip8
+        .load_rom(vec![
+            0x6A, 0x05, 0x22, 0x06, 0x12, 0x02, 0x7A, 0x01, 0x00, 0xEE,
+        ])
+        .unwrap();
+    chip8.set_tracer(Tracer::to_buffer(TraceFormat::Text).range(0x206..=0x209));
+    for _ in 0..5 {
+        chip8.execute_cycle().unwrap();
+    }
+    let text = String::from_utf8(chip8.take_tracer().unwrap().get_buffer().to_vec()).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0], TEXT_HEADER);
+    assert_eq!(
+        lines[1],
+        "         2 0206  7A01    ADD VA, 0x01          00 00 00 00 00 00 00 00 00 00 05 00 00 00 00 00  0000  1 00 00"
+    );
+    assert!(lines[2].starts_with("         3 0208  00EE    RET"));
+    assert_eq!(chip8.get_cycle_count(), 5);
+
+    chip8.set_tracer(Tracer::to_buffer(TraceFormat::JsonLines));
+    chip8.execute_cycle().unwrap();
+    let tracer = chip8.take_tracer().unwrap();
+    assert_eq!(
+        String::from_utf8(tracer.get_buffer().to_vec()).unwrap(),
+        "{\"cycle\":5,\"pc\":514,\"opcode\":8710,\"instruction\":\"Call(518)\",\"mnemonic\":\"CALL 0x206\",\"v\":[0,0,0,0,0,0,0,0,0,0,6,0,0,0,0,0],\"i\":0,\"sp\":0,\"dt\":0,\"st\":0,\"writes\":[]}\n"
+    );
+}
+
+#[test]
+fn rotates_trace_files() {
+    let path = std::env::temp_dir().join(format!("chip8-trace-{}.log", std::process::id()));
+    let path = path.to_str().unwrap();
+    let record = TraceRecord {
+        cycle: 0,
+        program_counter: 0x200,
+        opcode: 0x00E0,
+        instruction: Instruction::ClearDisplay,
+        registers: [0; 16],
+        index_register: 0,
+        stack_pointer: 0,
+        delay_timer: 0,
+        sound_timer: 0,
+        memory_writes: Vec::new(),
+    };
+    let line_length = record.to_json().len() as u64 + 1;
+    let mut tracer = Tracer::to_file(path, TraceFormat::JsonLines)
+        .unwrap()
+        .max_size(line_length * 2, 1);
+    for _ in 0..5 {
+        tracer.trace(&record);
+    }
+    tracer.finish().unwrap();
+    assert_eq!(fs::read_to_string(path).unwrap().lines().count(), 1);
+    assert_eq!(
+        fs::read_to_string(format!("{path}.1"))
+            .unwrap()
+            .lines()
+            .count(),
+        2
+    );
+    assert!(fs::metadata(format!("{path}.2")).is_err());
+    fs::remove_file(path).unwrap();
+    fs::remove_file(format!("{path}.1")).unwrap();
+}
+
+#[test]
+fn parses_and_compares_records() {
+    use crate::chip8::Chip8;
+    use crate::quirks::Quirks;
+
+    // 6AFF A300 FA33 6B01 8ABE 120A: store VA as BCD, then shift VB left into VA
+    let rom = vec![
+        0x6A, 0xFF, 0xA3, 0x00, 0xFA, 0x33, 0x6B, 0x01, 0x8A, 0xBE, 0x12, 0x0A,
+    ];
+    let run = |quirks: Quirks, format: TraceFormat| {
+        let mut chip8 = Chip8::new(quirks);
+        chip8.load_rom(rom.clone()).unwrap();
+        chip8.set_tracer(Tracer::to_buffer(format));
+        for _ in 0..6 {
+            chip8.execute_cycle().unwrap();
+        }
+        let tracer = chip8.take_tracer().unwrap();
+        return String::from_utf8(tracer.get_buffer().to_vec())
+            .unwrap()
+            .lines()
+            .filter_map(TraceRecord::parse)
+            .collect::<Vec<TraceRecord>>();
+    };
+
+    let text = run(Quirks::COSMAC_VIP, TraceFormat::Text);
+    assert_eq!(text, run(Quirks::COSMAC_VIP, TraceFormat::JsonLines));
+    assert_eq!(text.len(), 6);
+    assert_eq!(
+        text[2].memory_writes,
+        vec![(0x300, 2), (0x301, 5), (0x302, 5)]
+    );
+
+    // The shift quirk only shows once the shift has run.
+    let other = run(Quirks::SUPER_CHIP, TraceFormat::JsonLines);
+    assert!(differences(&text[4], &other[4]).is_empty());
+    assert_eq!(
+        differences(&text[5], &other[5]),
+        vec!["VA 02 vs FE", "VF 00 vs 01"]
+    );
+}
@@ -0,0 +1,92 @@
+#![allow(clippy::needless_return)]
+
+//! Runs the headless binary the way CI scripts do.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+// 6000 A000 D005 1206: draw the "0" glyph at the top left, then spin
+const ROM: [u8; 8] = [0x60, 0x00, 0xA0, 0x00, 0xD0, 0x05, 0x12, 0x06];
+
+/// A scratch directory for one test, holding the ROM as `test.ch8`.
+fn scratch(name: &str) -> PathBuf {
+    let directory = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let _ = fs::remove_dir_all(&directory);
+    fs::create_dir_all(&directory).unwrap();
+    fs::write(directory.join("test.ch8"), ROM).unwrap();
+    return directory;
+}
+
+fn headless(directory: &Path, args: &[&str]) -> Output {
+    return Command::new(env!("CARGO_BIN_EXE_chip8-headless"))
+        .current_dir(directory)
+        .args(args)
+        .output()
+        .unwrap();
+}
+
+#[test]
+fn golden_files() {
+    let directory = scratch("golden_files");
+    let output = headless(
+        &directory,
+        &["test.ch8", "--display", "screen.txt", "--registers", "-"],
+    );
+    assert_eq!(output.status.code(), Some(0));
+    let registers = String::from_utf8(output.stdout).unwrap();
+    assert!(registers.contains("\"pc\":518"), "{registers}");
+    let screen = fs::read_to_string(directory.join("screen.txt")).unwrap();
+    assert_eq!(screen.lines().count(), 32);
+    assert!(screen.starts_with(&format!("####{}\n#..#", ".".repeat(60))));
+
+    let output = headless(&directory, &["test.ch8", "--expect-display", "screen.txt"]);
+    assert_eq!(output.status.code(), Some(0));
+
+    fs::write(directory.join("wrong.txt"), screen.replacen('#', ".", 1)).unwrap();
+    let output = headless(&directory, &["test.ch8", "--expect-display", "wrong.txt"]);
+    assert_eq!(output.status.code(), Some(2));
+    let errors = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        errors.starts_with("display does not match wrong.txt"),
+        "{errors}"
+    );
+}
+
+#[test]
+fn png_display() {
+    let directory = scratch("png_display");
+    let output = headless(&directory, &["test.ch8", "--display", "screen.png"]);
+    assert_eq!(output.status.code(), Some(0));
+    let png = fs::read(directory.join("screen.png")).unwrap();
+    assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+
+    let output = headless(&directory, &["test.ch8", "--expect-display", "screen.png"]);
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn argument_errors() {
+    let directory = scratch("argument_errors");
+    for (args, message) in [
+        (vec![], "rom path not provided"),
+        (
+            vec!["test.ch8", "--quirks", "bogus"],
+            "unknown quirk preset 'bogus'",
+        ),
+        (
+            vec!["test.ch8", "--frames", "lots"],
+            "invalid value 'lots' for --frames",
+        ),
+        (vec!["missing.ch8"], "missing.ch8"),
+        (
+            vec!["test.ch8", "--expect-display", "missing.txt"],
+            "error reading missing.txt",
+        ),
+    ] {
+        let output = headless(&directory, &args);
+        assert_eq!(output.status.code(), Some(1), "{args:?}");
+        let errors = String::from_utf8(output.stderr).unwrap();
+        assert!(errors.contains(message), "{args:?}: {errors}");
+    }
+}